@@ -58,6 +58,9 @@ typedef struct _EekGtkKeyboardPrivate
     GdkEventSequence *sequence; // unowned reference
     LfbEvent *event;
 
+    guint long_press_source; // 0 when no press is being held
+    guint32 press_time;
+
     gulong kb_signal;
 } EekGtkKeyboardPrivate;
 
@@ -151,6 +154,28 @@ on_event_triggered (LfbEvent      *event,
     }
 }
 
+static void cancel_long_press(EekGtkKeyboardPrivate *priv)
+{
+    if (priv->long_press_source != 0) {
+        g_source_remove(priv->long_press_source);
+        priv->long_press_source = 0;
+    }
+}
+
+static gboolean on_long_press(gpointer user_data)
+{
+    EekGtkKeyboard *self = EEK_GTK_KEYBOARD(user_data);
+    EekGtkKeyboardPrivate *priv = eek_gtk_keyboard_get_instance_private (self);
+    priv->long_press_source = 0;
+    if (priv->keyboard) {
+        // There's no event here, so the time is counted from the press
+        guint32 time = priv->press_time
+            + squeek_layout_get_long_press_ms(priv->keyboard->layout);
+        squeek_layout_hold(priv->keyboard->layout, priv->submission, time);
+    }
+    return G_SOURCE_REMOVE;
+}
+
 static void depress(EekGtkKeyboard *self,
                     gdouble x, gdouble y, guint32 time)
 {
@@ -161,6 +186,12 @@ static void depress(EekGtkKeyboard *self,
     squeek_layout_depress(priv->keyboard->layout,
                           priv->submission,
                           x, y, priv->render_geometry.widget_to_layout, time, self);
+
+    cancel_long_press(priv);
+    priv->press_time = time;
+    priv->long_press_source = g_timeout_add(
+        squeek_layout_get_long_press_ms(priv->keyboard->layout),
+        on_long_press, self);
 }
 
 static void drag(EekGtkKeyboard *self,
@@ -179,6 +210,7 @@ static void drag(EekGtkKeyboard *self,
 static void release(EekGtkKeyboard *self, guint32 time)
 {
     EekGtkKeyboardPrivate *priv = eek_gtk_keyboard_get_instance_private (self);
+    cancel_long_press(priv);
     if (!priv->keyboard) {
         return;
     }
@@ -307,6 +339,8 @@ eek_gtk_keyboard_dispose (GObject *object)
         priv->kb_signal = 0;
     }
 
+    cancel_long_press(priv);
+
     if (priv->renderer) {
         eek_renderer_free(priv->renderer);
         priv->renderer = NULL;
@@ -126,7 +126,8 @@ eek_get_style_context_for_button (EekRenderer *self,
                                   const char *name,
                                   const char *outline_name,
                                   const char *locked_class,
-                                  uint64_t     pressed)
+                                  uint64_t     pressed,
+                                  uint64_t     disabled)
 {
     GtkStyleContext *ctx = self->button_context;
     /* Set the name of the button on the widget path, using the name obtained
@@ -138,9 +139,10 @@ eek_get_style_context_for_button (EekRenderer *self,
     /* Update the style context with the updated widget path. */
     gtk_style_context_set_path (ctx, path);
     /* Set the state to take into account whether the button is active
-       (pressed) or normal. */
+       (pressed) or normal, and whether it's disabled. */
     gtk_style_context_set_state(ctx,
-        pressed ? GTK_STATE_FLAG_ACTIVE : GTK_STATE_FLAG_NORMAL);
+        (pressed ? GTK_STATE_FLAG_ACTIVE : GTK_STATE_FLAG_NORMAL)
+        | (disabled ? GTK_STATE_FLAG_INSENSITIVE : 0));
     if (locked_class) {
         gtk_style_context_add_class(ctx, locked_class);
     }
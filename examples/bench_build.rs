@@ -0,0 +1,11 @@
+/*! Measures building a large generated layout */
+
+extern crate rs;
+
+use rs::tests::time_build;
+
+fn main() -> () {
+    // Many instances of few buttons, like in generated layouts
+    let elapsed = time_build(5000, 200);
+    println!("Built 5000 buttons with 200 names in {:?}", elapsed);
+}
@@ -1,11 +1,19 @@
 /*! The symbol object, defining actions that the key can do when activated */
 
+use crate::util::json_string;
 use std::ffi::CString;
 
 /// Name of the keysym
 #[derive(Debug, Clone, PartialEq)]
 pub struct KeySym(pub String);
 
+impl KeySym {
+    /// Dead keys type nothing, but change the next key instead
+    pub fn is_dead(&self) -> bool {
+        self.0.starts_with("dead_")
+    }
+}
+
 /// Use to switch views
 type View = String;
 
@@ -20,11 +28,48 @@ pub enum Modifier {
     Mod4,
 }
 
+/// Editing operations reached through the usual keyboard shortcuts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClipboardOp {
+    Cut,
+    Copy,
+    Paste,
+    SelectAll,
+}
+
+impl ClipboardOp {
+    /// The key which is pressed together with Control
+    pub fn keysym(&self) -> KeySym {
+        KeySym(match self {
+            ClipboardOp::Cut => "x",
+            ClipboardOp::Copy => "c",
+            ClipboardOp::Paste => "v",
+            ClipboardOp::SelectAll => "a",
+        }.into())
+    }
+}
+
+/// Where the preferences are shown
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PrefsTarget {
+    /// Squeekboard's own layout chooser
+    Chooser,
+    /// The keyboard panel of the system settings
+    Settings,
+}
+
 /// Action to perform on the keypress and, in reverse, on keyrelease
 #[derive(Debug, Clone, PartialEq)]
 pub enum Action {
     /// Switch to this view
     SetView(View),
+    /// Switch back to the view shown before the current one
+    PreviousView,
+    /// Switch to the first view, or to the second one when already there
+    ToggleView {
+        first: View,
+        second: View,
+    },
     /// Switch to a view and latch
     LockView {
         lock: View,
@@ -35,6 +80,9 @@ pub enum Action {
         latches: bool,
         /// Should take on *locked* appearance whenever latch comes back to those views.
         looks_locked_from: Vec<View>,
+        /// When latched, only a quick second press locks.
+        /// A slow one unlocks instead.
+        double_tap_lock: bool,
     },
     /// Hold this modifier for as long as the button is pressed
     ApplyModifier(Modifier),
@@ -48,19 +96,35 @@ pub enum Action {
     },
     /// Erase a position behind the cursor
     Erase,
-    ShowPreferences,
+    /// Discard input state waiting for the next key,
+    /// like locked modifiers or a dead key
+    ClearPending,
+    /// Break the line in text taking many lines.
+    /// Elsewhere, press Return, which e.g. sends a chat message.
+    Enter,
+    Clipboard(ClipboardOp),
+    /// Turn a layout flag on or off
+    ToggleFlag(String),
+    ShowPreferences(PrefsTarget),
+    /// Call a D-Bus method on the session bus, without arguments
+    DBus {
+        service: String,
+        path: String,
+        interface: String,
+        method: String,
+    },
 }
 
 impl Action {
     pub fn is_locked(&self, view_name: &str) -> bool {
         match self {
-            Action::LockView { lock, unlock: _, latches: _, looks_locked_from: _ } => lock == view_name,
+            Action::LockView { lock, unlock: _, latches: _, looks_locked_from: _, double_tap_lock: _ } => lock == view_name,
             _ => false,
         }
     }
     pub fn has_locked_appearance_from(&self, locked_view_name: &str) -> bool {
         match self {
-            Action::LockView { lock: _, unlock: _, latches: _, looks_locked_from } => {
+            Action::LockView { lock: _, unlock: _, latches: _, looks_locked_from, double_tap_lock: _ } => {
                 looks_locked_from.iter()
                     .find(|view| locked_view_name == view.as_str())
                     .is_some()
@@ -71,8 +135,108 @@ impl Action {
     pub fn is_active(&self, view_name: &str) -> bool {
         match self {
             Action::SetView(view) => view == view_name,
-            Action::LockView { lock, unlock: _, latches: _, looks_locked_from: _ } => lock == view_name,
+            Action::LockView { lock, unlock: _, latches: _, looks_locked_from: _, double_tap_lock: _ } => lock == view_name,
             _ => false,
         }
     }
+    /// Describes the action as a JSON object,
+    /// with the kind of action in "kind", and the details next to it
+    pub fn to_json(&self) -> String {
+        let strings = |items: &[String]| format!(
+            "[{}]",
+            items.iter()
+                .map(|item| json_string(item))
+                .collect::<Vec<_>>()
+                .join(","),
+        );
+        match self {
+            Action::SetView(view) => format!(
+                r#"{{"kind":"set_view","view":{}}}"#,
+                json_string(view),
+            ),
+            Action::PreviousView => r#"{"kind":"back"}"#.into(),
+            Action::ToggleView { first, second } => format!(
+                r#"{{"kind":"toggle_view","first":{},"second":{}}}"#,
+                json_string(first),
+                json_string(second),
+            ),
+            Action::LockView {
+                lock, unlock, latches, looks_locked_from, double_tap_lock,
+            } => format!(
+                concat!(
+                    r#"{{"kind":"lock_view","lock":{},"unlock":{},"#,
+                    r#""latches":{},"looks_locked_from":{},"#,
+                    r#""double_tap_lock":{}}}"#,
+                ),
+                json_string(lock),
+                json_string(unlock),
+                latches,
+                strings(looks_locked_from),
+                double_tap_lock,
+            ),
+            Action::ApplyModifier(modifier) => format!(
+                r#"{{"kind":"modifier","modifier":{}}}"#,
+                json_string(&format!("{:?}", modifier)),
+            ),
+            Action::Submit { text, keys } => format!(
+                r#"{{"kind":"submit","text":{},"keys":{}}}"#,
+                match text {
+                    Some(text) => json_string(&text.to_string_lossy()),
+                    None => "null".into(),
+                },
+                strings(
+                    &keys.iter().map(|key| key.0.clone()).collect::<Vec<_>>()
+                ),
+            ),
+            Action::Erase => r#"{"kind":"erase"}"#.into(),
+            Action::ClearPending => r#"{"kind":"clear"}"#.into(),
+            Action::Enter => r#"{"kind":"enter"}"#.into(),
+            Action::Clipboard(op) => format!(
+                r#"{{"kind":"clipboard","op":{}}}"#,
+                json_string(match op {
+                    ClipboardOp::Cut => "cut",
+                    ClipboardOp::Copy => "copy",
+                    ClipboardOp::Paste => "paste",
+                    ClipboardOp::SelectAll => "select_all",
+                }),
+            ),
+            Action::ToggleFlag(flag) => format!(
+                r#"{{"kind":"toggle","flag":{}}}"#,
+                json_string(flag),
+            ),
+            Action::ShowPreferences(target) => format!(
+                r#"{{"kind":"show_preferences","target":{}}}"#,
+                json_string(match target {
+                    PrefsTarget::Chooser => "chooser",
+                    PrefsTarget::Settings => "settings",
+                }),
+            ),
+            Action::DBus { service, path, interface, method } => format!(
+                concat!(
+                    r#"{{"kind":"dbus","service":{},"path":{},"#,
+                    r#""interface":{},"method":{}}}"#,
+                ),
+                json_string(service),
+                json_string(path),
+                json_string(interface),
+                json_string(method),
+            ),
+        }
+    }
+
+    /// Returns what `Enter` submits in a text field
+    /// taking one line or many. Other actions stay the same.
+    pub fn resolve_enter(self, multiline: bool) -> Action {
+        match (self, multiline) {
+            (Action::Enter, true) => Action::Submit {
+                text: Some(CString::new("\n").unwrap()),
+                keys: vec![KeySym("Return".into())],
+            },
+            (Action::Enter, false) => Action::Submit {
+                text: None,
+                keys: vec![KeySym("Return".into())],
+            },
+            (other, _) => other,
+        }
+    }
 }
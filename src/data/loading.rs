@@ -6,7 +6,11 @@
 
 use std::env;
 use std::fmt;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::sync::Mutex;
+use std::thread;
+use std::time::SystemTime;
 
 use super::{ Error, LoadError };
 use super::parsing;
@@ -14,15 +18,30 @@ use super::parsing;
 use crate::layout;
 use crate::layout::ArrangementKind;
 use crate::logging;
+use crate::resources;
+use crate::logging::Warn;
 use crate::xdg;
 use crate::imservice::ContentPurpose;
 
 
 const FALLBACK_LAYOUT_NAME: &str = "us";
 
+/// Layouts sharing a script, in the order of preference.
+/// A missing layout is better replaced by another one from its family
+/// than by the Latin fallback.
+const SCRIPT_FAMILIES: &[&[&str]] = &[
+    // Cyrillic
+    &["ru", "ua", "bg", "by", "kz", "mk", "rs"],
+    // Arabic
+    &["ara", "ir"],
+];
+
+/// Added to layout names when looking for the wide arrangement
+const WIDE_SUFFIX: &str = "_wide";
+
 
 #[derive(Debug, Clone, PartialEq)]
-enum DataSource {
+pub(crate) enum DataSource {
     File(PathBuf),
     Resource(String),
 }
@@ -51,7 +70,7 @@ fn _get_arrangement_names(name: &str, arrangement: ArrangementKind)
 {
     let name_with_arrangement = match arrangement {    
         ArrangementKind::Base => name.into(),
-        ArrangementKind::Wide => format!("{}_wide", name),
+        ArrangementKind::Wide => format!("{}{}", name, WIDE_SUFFIX),
     };
     
     let mut ret = Vec::new();
@@ -62,12 +81,95 @@ fn _get_arrangement_names(name: &str, arrangement: ArrangementKind)
     ret
 }
 
+/// Names ending like an arrangement get mixed up with
+/// the arrangements of other layouts, e.g. "foo_wide" is
+/// also the wide version of "foo".
+fn describe_suffix_conflict(name: &str) -> Option<String> {
+    name.strip_suffix(WIDE_SUFFIX)
+        .filter(|base| !base.is_empty())
+        .map(|base| format!(
+            "Layout name {} ends with {}, which is reserved for arrangements. It will be taken as the wide version of {}",
+            name, WIDE_SUFFIX, base,
+        ))
+}
+
+/// Splits a BCP-47-like tag, e.g. "pt-BR" or "pt_BR",
+/// into the language and the region.
+fn split_region(name: &str) -> Option<(&str, &str)> {
+    let idx = name.find(['-', '_'])?;
+    let (language, region) = (&name[..idx], &name[idx + 1..]);
+    let is_region = match region.len() {
+        2 => region.chars().all(|c| c.is_ascii_alphabetic()),
+        3 => region.chars().all(|c| c.is_ascii_digit()),
+        _ => false,
+    };
+    if language.is_empty() || !is_region {
+        None
+    } else {
+        Some((language, region))
+    }
+}
+
+/// Returns the other layouts written in the same script as `name`,
+/// ignoring any region in it.
+/// A variant after `+` may use another script, like "by+latin",
+/// so then there are none.
+fn get_script_family(name: &str) -> Vec<&'static str> {
+    if name.contains('+') {
+        return Vec::new();
+    }
+    let language = split_region(name)
+        .map(|(language, _region)| language)
+        .unwrap_or(name);
+    SCRIPT_FAMILIES.iter()
+        .find(|family| family.contains(&language))
+        .map(|family| {
+            family.iter()
+                .copied()
+                .filter(|member| *member != language)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
 /// Returns names accounting for any `+` in the `name`,
+/// and for a region like in "pt-BR",
+/// then other layouts with the same script,
 /// including the fallback to the default layout.
 fn get_preferred_names(name: &str, kind: ArrangementKind)
     -> Vec<(ArrangementKind, String)>
 {
+    let mut ret = get_own_names(name, kind);
+    for member in get_script_family(name) {
+        ret.extend(_get_arrangement_names(member, kind));
+    }
+    let fallback_names = _get_arrangement_names(FALLBACK_LAYOUT_NAME, kind);
+    ret.extend(fallback_names.into_iter());
+    ret
+}
+
+/// Like `get_preferred_names`, but without the default layout.
+fn get_own_names(name: &str, kind: ArrangementKind)
+    -> Vec<(ArrangementKind, String)>
+{
+    if let Some(message) = describe_suffix_conflict(name) {
+        log_print!(logging::Level::Warning, "{}", message);
+    }
+
+    // Regional layouts are stored as "pt_BR".
+    let (name, language) = match split_region(name) {
+        Some((language, region)) => (
+            format!("{}_{}", language, region.to_ascii_uppercase()),
+            Some(language),
+        ),
+        None => (name.to_string(), None),
+    };
+    let name = name.as_str();
+
     let mut ret = _get_arrangement_names(name, kind);
+    if let Some(language) = language {
+        ret.extend(_get_arrangement_names(language, kind));
+    }
     
     let base_name_preferences = {
         let mut parts = name.splitn(2, '+');
@@ -89,8 +191,6 @@ fn get_preferred_names(name: &str, kind: ArrangementKind)
     };
     
     ret.extend(base_name_preferences.into_iter());
-    let fallback_names = _get_arrangement_names(FALLBACK_LAYOUT_NAME, kind);
-    ret.extend(fallback_names.into_iter());
     ret
 }
 
@@ -156,21 +256,22 @@ fn to_layout_paths(
 
 type LayoutSource = (ArrangementKind, DataSource);
 
+/// Each directory in `filesystem_paths` is tried in order
+/// before the builtin layouts.
 fn to_layout_sources(
     layout_paths: impl Iterator<Item=(ArrangementKind, LayoutPath)>,
-    filesystem_path: Option<PathBuf>,
+    filesystem_paths: Vec<PathBuf>,
 ) -> impl Iterator<Item=LayoutSource> {
     layout_paths.flat_map(move |(arrangement, layout_path)| {
-        let mut sources = Vec::new();
-        if let Some(path) = &filesystem_path {
-            sources.push((
+        let mut sources: Vec<_> = filesystem_paths.iter()
+            .map(|path| (
                 arrangement,
                 DataSource::File(
                     path.join(&layout_path)
                         .with_extension("yaml")
                 )
-            ));
-        };
+            ))
+            .collect();
         sources.push((arrangement, DataSource::Resource(layout_path.clone())));
         sources.into_iter()
     })
@@ -184,72 +285,320 @@ fn iter_layout_sources(
     arrangement: ArrangementKind,
     purpose: ContentPurpose,
     ui_overlay: Option<&str>,
-    layout_storage: Option<PathBuf>,
+    layout_storage: Vec<PathBuf>,
 ) -> impl Iterator<Item=LayoutSource> {
     let names = get_preferred_names(name, arrangement);
     let paths = to_layout_paths(names, purpose, ui_overlay);
     to_layout_sources(paths, layout_storage)
 }
 
-fn load_layout_data(source: DataSource)
-    -> Result<crate::layout::LayoutParseData, LoadError>
-{
-    let handler = logging::Print {};
+/// Layouts installed for all users of the system
+const SYSTEM_LAYOUT_STORAGE: &str = "/usr/share/squeekboard/keyboards";
+
+/// Returns the directories with layout files, the most preferred first:
+/// the user's own layouts, then the system ones.
+/// SQUEEKBOARD_KEYBOARDSDIR replaces them with its own list,
+/// separated by colons.
+fn get_layout_storage() -> Vec<PathBuf> {
+    match env::var_os("SQUEEKBOARD_KEYBOARDSDIR") {
+        Some(paths) => env::split_paths(&paths)
+            .filter(|path| !path.as_os_str().is_empty())
+            .collect(),
+        None => xdg::data_path("squeekboard/keyboards").into_iter()
+            .chain(Some(PathBuf::from(SYSTEM_LAYOUT_STORAGE)))
+            .collect(),
+    }
+}
+
+/// Returns the names of builtin layouts which shouldn't be used,
+/// taken from SQUEEKBOARD_DISABLED_LAYOUTS, separated by commas.
+fn get_disabled_layouts() -> Vec<String> {
+    env::var("SQUEEKBOARD_DISABLED_LAYOUTS")
+        .map(|names| {
+            names.split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+impl DataSource {
+    /// Checks if the source is a builtin layout named in `disabled`.
+    /// The name covers the layout for every purpose and arrangement,
+    /// e.g. "de" covers "terminal/de_wide".
+    /// The last resort layout is never disabled.
+    fn is_disabled(&self, disabled: &[String]) -> bool {
+        match self {
+            DataSource::File(_) => false,
+            DataSource::Resource(path) => {
+                let name = path.rsplit('/').next().unwrap_or(path);
+                let name = name.strip_suffix(WIDE_SUFFIX).unwrap_or(name);
+                name != FALLBACK_LAYOUT_NAME
+                    && disabled.iter().any(|d| d == name)
+            },
+        }
+    }
+
+    /// Doesn't check if the contents are valid.
+    fn is_present(&self) -> bool {
+        match self {
+            DataSource::File(path) => path.is_file(),
+            DataSource::Resource(name) => resources::get_keyboard(name).is_some(),
+        }
+    }
+}
+
+/// Checks if the layout, or one it falls back to,
+/// can be found without resorting to the default layout.
+/// The layout is not parsed, so it may still fail to load.
+pub(crate) fn layout_exists(name: &str, kind: ArrangementKind) -> bool {
+    layout_exists_in(name, kind, get_layout_storage(), &get_disabled_layouts())
+}
+
+fn layout_exists_in(
+    name: &str,
+    kind: ArrangementKind,
+    layout_storage: Vec<PathBuf>,
+    disabled: &[String],
+) -> bool {
+    let names = get_own_names(name, kind);
+    let paths = to_layout_paths(names, ContentPurpose::Normal, None);
+    to_layout_sources(paths, layout_storage)
+        .filter(|(_kind, source)| !source.is_disabled(disabled))
+        .any(|(_kind, source)| source.is_present())
+}
+
+/// Buttons calling D-Bus methods are opt-in,
+/// because any layout file could otherwise call any method.
+fn dbus_actions_enabled() -> bool {
+    env::var_os("SQUEEKBOARD_DBUS_ACTIONS").is_some()
+}
+
+fn load_layout_data(
+    source: DataSource,
+    kind: ArrangementKind,
+    base_keymap: Option<&str>,
+    dbus_actions: bool,
+) -> Result<crate::layout::LayoutParseData, LoadError> {
+    let mut handler = logging::Print {};
     match source {
         DataSource::File(path) => {
-            parsing::Layout::from_file(path.clone())
+            let layout = match env::var_os("SQUEEKBOARD_LENIENT_PARSE") {
+                Some(_) => parsing::Layout::from_file_lenient(
+                    path.clone(),
+                    &mut handler,
+                ),
+                None => parsing::Layout::from_file(path.clone()),
+            };
+            layout
                 .map_err(LoadError::BadData)
                 .and_then(|layout|
-                    layout.build(handler).0.map_err(LoadError::BadKeyMap)
+                    layout.build_with_keymap_fallback(
+                        kind,
+                        base_keymap,
+                        dbus_actions,
+                        handler,
+                    ).0
+                        .map_err(LoadError::BadKeyMap)
                 )
         },
         DataSource::Resource(name) => {
             parsing::Layout::from_resource(&name)
                 .and_then(|layout|
-                    layout.build(handler).0.map_err(LoadError::BadKeyMap)
+                    layout.build_with_keymap_fallback(
+                        kind,
+                        base_keymap,
+                        dbus_actions,
+                        handler,
+                    ).0
+                        .map_err(LoadError::BadKeyMap)
                 )
         },
     }
 }
 
-fn load_layout_data_with_fallback(
+/// The state of a layout file at the time it was read
+#[derive(Clone, Debug, PartialEq)]
+struct FileStamp {
+    path: PathBuf,
+    modified: SystemTime,
+}
+
+impl FileStamp {
+    fn read(path: &Path) -> Option<FileStamp> {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .map(|modified| FileStamp { path: path.into(), modified })
+    }
+
+    /// True if the file was modified or removed since.
+    fn is_outdated(&self) -> bool {
+        FileStamp::read(&self.path).as_ref() != Some(self)
+    }
+}
+
+/// The arguments of `load_layout`: name, kind, purpose and overlay
+type LoadRequest = (String, ArrangementKind, ContentPurpose, Option<String>);
+
+/// The files which layouts were last loaded from, by what was asked for.
+/// Layouts loaded from resources have no entry.
+static SOURCE_FILES: Mutex<Vec<(LoadRequest, FileStamp)>>
+    = Mutex::new(Vec::new());
+
+fn record_source_file(request: LoadRequest, stamp: Option<FileStamp>) {
+    let mut files = SOURCE_FILES.lock().unwrap();
+    files.retain(|(loaded, _stamp)| loaded != &request);
+    if let Some(stamp) = stamp {
+        files.push((request, stamp));
+    }
+}
+
+/// Checks if the file the layout was last loaded from changed since,
+/// so that it's worth loading again.
+/// Takes the same arguments as `load_layout`.
+/// Layouts from resources never change.
+pub(crate) fn layout_file_changed(
     name: &str,
     kind: ArrangementKind,
     purpose: ContentPurpose,
     overlay: Option<&str>,
-) -> (ArrangementKind, layout::LayoutParseData) {
+) -> bool {
+    let files = SOURCE_FILES.lock().unwrap();
+    let stamp = files.iter()
+        .find(|((loaded_name, loaded_kind, loaded_purpose, loaded_overlay), _)| {
+            loaded_name == name
+                && *loaded_kind == kind
+                && *loaded_purpose == purpose
+                && loaded_overlay.as_deref() == overlay
+        });
+    match stamp {
+        Some((_request, stamp)) => stamp.is_outdated(),
+        None => false,
+    }
+}
 
-    // Build the path to the right keyboard layout subdirectory
-    let path = env::var_os("SQUEEKBOARD_KEYBOARDSDIR")
-        .map(PathBuf::from)
-        .or_else(|| xdg::data_path("squeekboard/keyboards"));
+/// Returns how important the failure is, and what to report.
+/// A missing file is normal, but one which can't be read
+/// means the permissions need fixing.
+fn describe_load_failure(e: &LoadError, source: &DataSource)
+    -> (logging::Level, String)
+{
+    match (e, source) {
+        (
+            LoadError::BadData(Error::Missing(e)),
+            DataSource::File(file)
+        ) => (
+            logging::Level::Debug,
+            format!("Tried file {:?}, but it's missing: {}", file, e),
+        ),
+        (
+            LoadError::BadData(Error::PermissionDenied(e)),
+            DataSource::File(file)
+        ) => (
+            logging::Level::Error,
+            format!(
+                "Can't read file {:?}, check its permissions: {}, skipping",
+                file, e,
+            ),
+        ),
+        (e, source) => (
+            logging::Level::Warning,
+            format!("Failed to load layout from {}: {}, skipping", source, e),
+        ),
+    }
+}
+
+/// Sources which failed to load, with the reasons, in the order tried
+pub(crate) type LoadAttempts = Vec<(DataSource, LoadError)>;
 
-    for (kind, source) in iter_layout_sources(&name, kind, purpose, overlay, path) {
-        let layout = load_layout_data(source.clone());
+/// Loads the first source which works,
+/// or returns all the failed attempts if none does.
+fn load_first_source(
+    sources: impl Iterator<Item=LayoutSource>,
+    base_keymap: Option<&str>,
+    dbus_actions: bool,
+) -> Result<
+    (ArrangementKind, layout::LayoutParseData, Option<FileStamp>),
+    LoadAttempts,
+> {
+    let mut attempts = Vec::new();
+    for (kind, source) in sources {
+        // Taken before reading, so that changes made meanwhile are not missed.
+        let stamp = match &source {
+            DataSource::File(path) => FileStamp::read(path),
+            DataSource::Resource(_) => None,
+        };
+        let layout = load_layout_data(
+            source.clone(),
+            kind,
+            base_keymap,
+            dbus_actions,
+        );
         match layout {
-            Err(e) => match (e, source) {
-                (
-                    LoadError::BadData(Error::Missing(e)),
-                    DataSource::File(file)
-                ) => log_print!(
-                    logging::Level::Debug,
-                    "Tried file {:?}, but it's missing: {}",
-                    file, e
-                ),
-                (e, source) => log_print!(
-                    logging::Level::Warning,
-                    "Failed to load layout from {}: {}, skipping",
-                    source, e
-                ),
+            Err(e) => {
+                let (level, message) = describe_load_failure(&e, &source);
+                logging::print(level, &message);
+                attempts.push((source, e));
             },
             Ok(layout) => {
                 log_print!(logging::Level::Info, "Loaded layout {}", source);
-                return (kind, layout);
+                return Ok((kind, layout, stamp));
             }
         }
     }
+    Err(attempts)
+}
+
+/// Like `load_layout_data_with_fallback`,
+/// but reports every attempt instead of panicking when nothing loads.
+/// The file of the loaded layout is remembered for `layout_file_changed`.
+pub(crate) fn try_load_layout_data(
+    name: &str,
+    kind: ArrangementKind,
+    purpose: ContentPurpose,
+    overlay: Option<&str>,
+) -> Result<(ArrangementKind, layout::LayoutParseData), LoadAttempts> {
+
+    let path = get_layout_storage();
+    let disabled = get_disabled_layouts();
+
+    // Keys to keep in the generated keymap, for compositors which need them
+    let base_keymap = env::var_os("SQUEEKBOARD_BASE_KEYMAP")
+        .and_then(|path| {
+            fs::read_to_string(&path)
+                .or_print(
+                    logging::Problem::Warning,
+                    &format!("Can't read base keymap {:?}", path),
+                )
+        });
+
+    let sources = iter_layout_sources(&name, kind, purpose, overlay, path)
+        .filter(|(_kind, source)| !source.is_disabled(&disabled));
+    let (found_kind, data, stamp) = load_first_source(
+        sources,
+        base_keymap.as_deref(),
+        dbus_actions_enabled(),
+    )?;
+    record_source_file(
+        (name.into(), kind, purpose, overlay.map(String::from)),
+        stamp,
+    );
+    Ok((found_kind, data))
+}
 
-    panic!("No useful layout found!");
+fn load_layout_data_with_fallback(
+    name: &str,
+    kind: ArrangementKind,
+    purpose: ContentPurpose,
+    overlay: Option<&str>,
+) -> (ArrangementKind, layout::LayoutParseData) {
+    match try_load_layout_data(name, kind, purpose, overlay) {
+        Ok(loaded) => loaded,
+        Err(_attempts) => panic!("No useful layout found!"),
+    }
 }
 
 pub fn load_layout(
@@ -264,24 +613,324 @@ pub fn load_layout(
     layout::Layout::new(layout, found_kind, variant)
 }
 
+/// A built layout which is not in use yet.
+/// Unlike `layout::Layout`, it can be sent between threads.
+pub struct LoadedLayout {
+    pub kind: ArrangementKind,
+    pub purpose: ContentPurpose,
+    pub data: layout::LayoutParseData,
+}
+
+impl LoadedLayout {
+    pub fn into_layout(self) -> layout::Layout {
+        layout::Layout::new(self.data, self.kind, self.purpose)
+    }
+}
+
+/// Like `load_layout`, but reads files and generates keymaps
+/// on a new thread, so that the calling thread is not blocked.
+///
+/// `on_loaded` is called on that new thread.
+/// To get the layout into the UI, it should only send the result,
+/// e.g. through a `glib::Sender`,
+/// and the receiving end on the main thread calls `into_layout`.
+/// Layouts keep state which the UI touches without locking,
+/// so a `Layout` must not be created on the loading thread.
+///
+/// If no layout can be loaded at all, the thread panics
+/// and `on_loaded` is never called.
+pub fn load_layout_in_thread<F>(
+    name: String,
+    kind: ArrangementKind,
+    variant: ContentPurpose,
+    overlay: Option<String>,
+    on_loaded: F,
+) -> thread::JoinHandle<()>
+    where F: FnOnce(LoadedLayout) + Send + 'static
+{
+    thread::spawn(move || {
+        let (found_kind, data) = load_layout_data_with_fallback(
+            &name,
+            kind,
+            variant,
+            overlay.as_deref(),
+        );
+        on_loaded(LoadedLayout {
+            kind: found_kind,
+            purpose: variant,
+            data,
+        })
+    })
+}
+
+mod c {
+    use super::*;
+
+    use crate::util;
+    use std::os::raw::c_char;
+    use std::os::unix::io::RawFd;
+    use std::ptr;
+
+    /// Returns 1 if the layout exists, 0 otherwise
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_exists(name: *const c_char, kind: u32) -> u32 {
+        let name = util::c::as_str(&name)
+            .unwrap_or(None)
+            .unwrap_or("");
+        let kind = match kind {
+            1 => ArrangementKind::Wide,
+            _ => ArrangementKind::Base,
+        };
+        layout_exists(name, kind) as u32
+    }
+
+    /// Loads the layout from a file descriptor, which stays open.
+    /// Returns NULL if the layout can't be loaded.
+    /// The layout must be freed with squeek_layout_free.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_load_layout_from_fd(
+        fd: RawFd,
+        kind: u32,
+        purpose: u32,
+    ) -> *mut layout::Layout {
+        let kind = match kind {
+            1 => ArrangementKind::Wide,
+            _ => ArrangementKind::Base,
+        };
+        let purpose = ContentPurpose::try_from(purpose)
+            .unwrap_or(ContentPurpose::Normal);
+        let data = unsafe { parsing::Layout::from_fd(fd) }
+            .map_err(LoadError::BadData)
+            .and_then(|layout| {
+                layout.build_with_keymap_fallback(
+                    kind,
+                    None,
+                    dbus_actions_enabled(),
+                    logging::Print {},
+                ).0
+                    .map_err(LoadError::BadKeyMap)
+            });
+        match data {
+            Ok(data) => Box::into_raw(Box::new(
+                layout::Layout::new(data, kind, purpose)
+            )),
+            Err(e) => {
+                log_print!(
+                    logging::Level::Warning,
+                    "Failed to load layout from file descriptor {}: {}",
+                    fd, e,
+                );
+                ptr::null_mut()
+            },
+        }
+    }
+
+    /// Returns 1 if the file of the layout last loaded
+    /// with the same arguments changed since, 0 otherwise
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_file_changed(
+        name: *const c_char,
+        kind: u32,
+        purpose: u32,
+        overlay: *const c_char,
+    ) -> u32 {
+        let name = util::c::as_str(&name)
+            .unwrap_or(None)
+            .unwrap_or("");
+        let overlay = util::c::as_str(&overlay)
+            .unwrap_or(None);
+        let kind = match kind {
+            1 => ArrangementKind::Wide,
+            _ => ArrangementKind::Base,
+        };
+        let purpose = ContentPurpose::try_from(purpose)
+            .unwrap_or(ContentPurpose::Normal);
+        layout_file_changed(name, kind, purpose, overlay) as u32
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     use crate::logging::ProblemPanic;
+    use std::io;
+    use std::process;
+
+    /// A path in the temporary directory, unique to the test,
+    /// and to the process, so that separate runs don't collide
+    fn make_temp_path(test_name: &str) -> PathBuf {
+        env::temp_dir().join(
+            format!("squeekboard-{}-{}", test_name, process::id())
+        )
+    }
 
     #[test]
     fn parsing_fallback() {
         assert!(parsing::Layout::from_resource(FALLBACK_LAYOUT_NAME)
-            .map(|layout| layout.build(ProblemPanic).0.unwrap())
+            .map(|layout| layout.build(ArrangementKind::Base, ProblemPanic).0.unwrap())
             .is_ok()
         );
     }
     
+    #[test]
+    fn test_permission_denied() {
+        let source = DataSource::File("keyboards/us.yaml".into());
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        let (level, _message) = describe_load_failure(
+            &LoadError::BadData(denied.into()),
+            &source,
+        );
+        assert!(matches!(level, logging::Level::Error));
+
+        let missing = io::Error::from(io::ErrorKind::NotFound);
+        let (level, _message) = describe_load_failure(
+            &LoadError::BadData(missing.into()),
+            &source,
+        );
+        assert!(matches!(level, logging::Level::Debug));
+
+        // The layout is there, so a missing keymap file is a mistake
+        let missing = io::Error::from(io::ErrorKind::NotFound);
+        let (level, _message) = describe_load_failure(
+            &LoadError::BadData(
+                Error::KeymapFile("keyboards/us.xkb".into(), missing),
+            ),
+            &source,
+        );
+        assert!(matches!(level, logging::Level::Warning));
+    }
+
+    #[test]
+    fn test_load_attempts() {
+        let sources = vec![
+            (
+                ArrangementKind::Wide,
+                DataSource::File("/nonexistent/nonexistent_wide.yaml".into()),
+            ),
+            (
+                ArrangementKind::Base,
+                DataSource::Resource("nonexistent".into()),
+            ),
+        ];
+        let attempts = match load_first_source(sources.into_iter(), None, false) {
+            Ok(_) => panic!("Nonexistent layout loaded"),
+            Err(attempts) => attempts,
+        };
+        assert_eq!(attempts.len(), 2);
+        assert_eq!(
+            attempts[0].0,
+            DataSource::File("/nonexistent/nonexistent_wide.yaml".into()),
+        );
+        assert!(matches!(
+            attempts[0].1,
+            LoadError::BadData(Error::Missing(_)),
+        ));
+        assert_eq!(attempts[1].0, DataSource::Resource("nonexistent".into()));
+        assert!(matches!(attempts[1].1, LoadError::MissingResource));
+    }
+
+    #[test]
+    fn test_suffix_conflict() {
+        assert_eq!(
+            describe_suffix_conflict("foo_wide").as_deref(),
+            Some("Layout name foo_wide ends with _wide, which is reserved for arrangements. It will be taken as the wide version of foo"),
+        );
+        assert_eq!(describe_suffix_conflict("foo"), None);
+        assert_eq!(describe_suffix_conflict("pt_BR"), None);
+    }
+
+    #[test]
+    fn test_layout_exists() {
+        assert!(layout_exists_in("us", ArrangementKind::Wide, Vec::new(), &[]));
+        assert!(!layout_exists_in("nonexistent", ArrangementKind::Base, Vec::new(), &[]));
+    }
+
+    #[test]
+    fn test_disabled_layouts() {
+        let disabled = ["nb".to_string(), "us".to_string()];
+        assert!(layout_exists_in("nb", ArrangementKind::Base, Vec::new(), &[]));
+        assert!(!layout_exists_in("nb", ArrangementKind::Base, Vec::new(), &disabled));
+        // Nothing would be left to fall back to
+        assert!(layout_exists_in("us", ArrangementKind::Base, Vec::new(), &disabled));
+
+        let sources = iter_layout_sources(
+            "nb",
+            ArrangementKind::Wide,
+            ContentPurpose::Terminal,
+            None,
+            vec![".".into()],
+        ).filter(|(_kind, source)| !source.is_disabled(&disabled));
+        assert_eq!(
+            sources.map(|(_kind, source)| source).collect::<Vec<_>>(),
+            vec![
+                DataSource::File("./terminal/nb_wide.yaml".into()),
+                DataSource::File("./terminal/nb.yaml".into()),
+                DataSource::File("./terminal/us_wide.yaml".into()),
+                DataSource::Resource("terminal/us_wide".into()),
+                DataSource::File("./terminal/us.yaml".into()),
+                DataSource::Resource("terminal/us".into()),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_load_in_thread() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        load_layout_in_thread(
+            "us".into(),
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+            None,
+            move |loaded| sender.send(loaded).unwrap(),
+        ).join().unwrap();
+        let layout = receiver.recv().unwrap().into_layout();
+        assert_eq!(layout.shape.purpose, ContentPurpose::Normal);
+    }
+
+    #[test]
+    fn test_file_stamp_outdated() {
+        let path = make_temp_path("test_file_stamp_outdated");
+        fs::write(&path, "").unwrap();
+        let stamp = FileStamp::read(&path).unwrap();
+        assert!(!stamp.is_outdated());
+
+        let older = FileStamp {
+            modified: SystemTime::UNIX_EPOCH,
+            ..stamp.clone()
+        };
+        assert!(older.is_outdated());
+
+        let name = "test_file_stamp_outdated";
+        let request = |stamp| record_source_file(
+            (name.into(), ArrangementKind::Base, ContentPurpose::Normal, None),
+            stamp,
+        );
+        let changed = || layout_file_changed(
+            name,
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+            None,
+        );
+        request(Some(stamp.clone()));
+        assert!(!changed());
+
+        fs::remove_file(&path).unwrap();
+        assert!(stamp.is_outdated());
+        assert!(changed());
+
+        // Loaded again from a resource
+        request(None);
+        assert!(!changed());
+    }
+
     /// First fallback should be to builtin, not to FALLBACK_LAYOUT_NAME
     #[test]
     fn test_fallback_basic_builtin() {
-        let sources = iter_layout_sources("nb", ArrangementKind::Base, ContentPurpose::Normal, None, None);
+        let sources = iter_layout_sources("nb", ArrangementKind::Base, ContentPurpose::Normal, None, Vec::new());
         
         assert_eq!(
             sources.collect::<Vec<_>>(),
@@ -298,7 +947,7 @@ mod tests {
     /// Prefer loading from file system before builtin.
     #[test]
     fn test_preferences_order_path() {
-        let sources = iter_layout_sources("nb", ArrangementKind::Base, ContentPurpose::Normal, None, Some(".".into()));
+        let sources = iter_layout_sources("nb", ArrangementKind::Base, ContentPurpose::Normal, None, vec![".".into()]);
         
         assert_eq!(
             sources.collect::<Vec<_>>(),
@@ -317,10 +966,77 @@ mod tests {
         );
     }
 
+    /// The user's layout wins over the system one with the same name.
+    #[test]
+    fn test_preferences_order_storages() {
+        let root = make_temp_path("test_preferences_order_storages");
+        let user = root.join("user");
+        let system = root.join("system");
+        for dir in [&user, &system] {
+            fs::create_dir_all(dir).unwrap();
+            fs::write(dir.join("nb.yaml"), "").unwrap();
+        }
+
+        let found = iter_layout_sources(
+            "nb",
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+            None,
+            vec![user.clone(), system.clone()],
+        ).find(|(_kind, source)| source.is_present());
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(
+            found,
+            Some((ArrangementKind::Base, DataSource::File(user.join("nb.yaml")))),
+        );
+    }
+
+    /// A layout in a non-Latin script first falls back to its relatives.
+    #[test]
+    fn test_preferences_order_script() {
+        let sources = iter_layout_sources("by", ArrangementKind::Base, ContentPurpose::Normal, None, Vec::new());
+        let sources: Vec<_> = sources.collect();
+
+        let position = |name: &str| sources.iter()
+            .position(|(_kind, source)| {
+                source == &DataSource::Resource(name.into())
+            })
+            .unwrap();
+        assert_eq!(position("by"), 0);
+        assert!(position("ru") < position(FALLBACK_LAYOUT_NAME));
+        assert!(position("ua") < position(FALLBACK_LAYOUT_NAME));
+        assert_eq!(
+            sources.last(),
+            Some(&(
+                ArrangementKind::Base,
+                DataSource::Resource(FALLBACK_LAYOUT_NAME.into()),
+            )),
+        );
+    }
+
+    /// A variant may be in another script, so it skips the family.
+    #[test]
+    fn test_preferences_order_script_variant() {
+        let sources = iter_layout_sources("by+latin", ArrangementKind::Base, ContentPurpose::Normal, None, Vec::new());
+
+        assert_eq!(
+            sources.collect::<Vec<_>>(),
+            vec!(
+                (ArrangementKind::Base, DataSource::Resource("by+latin".into())),
+                (ArrangementKind::Base, DataSource::Resource("by".into())),
+                (
+                    ArrangementKind::Base,
+                    DataSource::Resource(FALLBACK_LAYOUT_NAME.into())
+                ),
+            )
+        );
+    }
+
     /// If layout contains a "+", it should reach for what's in front of it too.
     #[test]
     fn test_preferences_order_base() {
-        let sources = iter_layout_sources("nb+aliens", ArrangementKind::Base, ContentPurpose::Normal, None, None);
+        let sources = iter_layout_sources("nb+aliens", ArrangementKind::Base, ContentPurpose::Normal, None, Vec::new());
 
         assert_eq!(
             sources.collect::<Vec<_>>(),
@@ -335,9 +1051,47 @@ mod tests {
         );
     }
 
+    /// A regional layout falls back to the language, then to the default.
+    #[test]
+    fn test_preferences_order_region() {
+        let sources = iter_layout_sources("pt-BR", ArrangementKind::Wide, ContentPurpose::Normal, None, Vec::new());
+
+        assert_eq!(
+            sources.collect::<Vec<_>>(),
+            vec!(
+                (ArrangementKind::Wide, DataSource::Resource("pt_BR_wide".into())),
+                (ArrangementKind::Base, DataSource::Resource("pt_BR".into())),
+                (ArrangementKind::Wide, DataSource::Resource("pt_wide".into())),
+                (ArrangementKind::Base, DataSource::Resource("pt".into())),
+                (
+                    ArrangementKind::Wide,
+                    DataSource::Resource("us_wide".into())
+                ),
+                (
+                    ArrangementKind::Base,
+                    DataSource::Resource("us".into())
+                ),
+            )
+        );
+
+        let sources = iter_layout_sources("pt_br", ArrangementKind::Base, ContentPurpose::Normal, None, Vec::new());
+
+        assert_eq!(
+            sources.collect::<Vec<_>>(),
+            vec!(
+                (ArrangementKind::Base, DataSource::Resource("pt_BR".into())),
+                (ArrangementKind::Base, DataSource::Resource("pt".into())),
+                (
+                    ArrangementKind::Base,
+                    DataSource::Resource(FALLBACK_LAYOUT_NAME.into())
+                ),
+            )
+        );
+    }
+
     #[test]
     fn test_preferences_order_arrangement() {
-        let sources = iter_layout_sources("nb", ArrangementKind::Wide, ContentPurpose::Normal, None, None);
+        let sources = iter_layout_sources("nb", ArrangementKind::Wide, ContentPurpose::Normal, None, Vec::new());
 
         assert_eq!(
             sources.collect::<Vec<_>>(),
@@ -358,7 +1112,7 @@ mod tests {
 
     #[test]
     fn test_preferences_order_overlay() {
-        let sources = iter_layout_sources("nb", ArrangementKind::Base, ContentPurpose::Normal, Some("terminal"), None);
+        let sources = iter_layout_sources("nb", ArrangementKind::Base, ContentPurpose::Normal, Some("terminal"), Vec::new());
 
         assert_eq!(
             sources.collect::<Vec<_>>(),
@@ -374,7 +1128,7 @@ mod tests {
 
     #[test]
     fn test_preferences_order_hint() {
-        let sources = iter_layout_sources("nb", ArrangementKind::Base, ContentPurpose::Terminal, None, None);
+        let sources = iter_layout_sources("nb", ArrangementKind::Base, ContentPurpose::Terminal, None, Vec::new());
 
         assert_eq!(
             sources.collect::<Vec<_>>(),
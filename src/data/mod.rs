@@ -6,9 +6,11 @@
 
 pub mod loading;
 pub mod parsing;
+mod shortcodes;
 
 use std::io;
 use std::fmt;
+use std::path::PathBuf;
 
 use crate::keyboard::FormattingError;
 
@@ -21,6 +23,11 @@ pub enum Error {
     /// It's distinct from Io in order to make it matchable
     /// without calling io::Error::kind()
     Missing(io::Error),
+    /// The file is there, but can't be read.
+    /// Unlike a missing file, it's a mistake which the user should fix.
+    PermissionDenied(io::Error),
+    /// The layout is there, but its `keymap_file` can't be read
+    KeymapFile(PathBuf, io::Error),
 }
 
 impl fmt::Display for Error {
@@ -29,6 +36,12 @@ impl fmt::Display for Error {
             Error::Yaml(e) => write!(f, "YAML: {}", e),
             Error::Io(e) => write!(f, "IO: {}", e),
             Error::Missing(e) => write!(f, "Missing: {}", e),
+            Error::PermissionDenied(e) => write!(f, "Not allowed: {}", e),
+            Error::KeymapFile(path, e) => write!(
+                f,
+                "Keymap file {:?}: {}",
+                path, e,
+            ),
         }
     }
 }
@@ -38,6 +51,7 @@ impl From<io::Error> for Error {
         let kind = e.kind();
         match kind {
             io::ErrorKind::NotFound => Error::Missing(e),
+            io::ErrorKind::PermissionDenied => Error::PermissionDenied(e),
             _ => Error::Io(e),
         }
     }
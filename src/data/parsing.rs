@@ -4,19 +4,28 @@
 
 /*! Parsing of the data files containing layouts */
 
-use std::collections::{ HashMap, HashSet };
+use std::collections::{ BTreeMap, HashMap, HashSet };
 use std::ffi::CString;
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::hash::{ Hash, Hasher };
+use std::io::Read;
+use std::mem;
+use std::os::unix::io::{ FromRawFd, RawFd };
+use std::path::{ Path, PathBuf };
 use std::vec::Vec;
 
 use xkbcommon::xkb;
 
-use super::{ Error, LoadError };
+use super::{ shortcodes, Error, LoadError };
 
 use crate::action;
 use crate::keyboard::{
-    Key, generate_keymaps, generate_keycodes, KeyCode, FormattingError
+    Key, free_keycodes, generate_empty_keymap, generate_keymaps,
+    generate_keycodes, KeyCode,
+    find_keys_by_name,
+    ShiftLevels,
+    KeymapNames, FormattingError, read_keymap_keys,
 };
 use crate::layout;
 use crate::logging;
@@ -24,66 +33,290 @@ use crate::resources;
 
 // traits, derives
 use serde::Deserialize;
-use std::io::BufReader;
+use serde::de::{ self, DeserializeOwned };
 use std::iter::FromIterator;
-use crate::logging::Warn;
 
 // TODO: find a nice way to make sure non-positive sizes don't break layouts
 
-/// The root element describing an entire keyboard
-#[derive(Debug, Deserialize, PartialEq)]
+/// The root element describing an entire keyboard.
+/// Can also be assembled in memory, e.g. in tools and tests,
+/// and then built the same as when loaded from a file.
+#[derive(Debug, Default, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct Layout {
     #[serde(default)]
-    margins: Margins,
-    views: HashMap<String, Vec<ButtonIds>>,
+    pub(crate) margins: Margins,
+    /// Rows may also be written as lists of names,
+    /// which are stored joined with spaces.
+    #[serde(deserialize_with = "deserialize_views")]
+    pub(crate) views: HashMap<String, Vec<ButtonIds>>,
+    /// The view shown first, e.g. digits on a PIN pad.
+    /// Defaults to "base".
+    pub(crate) default_view: Option<String>,
+    /// The order of views for listing them to the user.
+    /// Views not listed come after, sorted by name.
+    pub(crate) view_order: Option<Vec<String>>,
     #[serde(default)] 
-    buttons: HashMap<String, ButtonMeta>,
-    outlines: HashMap<String, Outline>
+    pub(crate) buttons: HashMap<String, ButtonMeta>,
+    #[serde(default)]
+    pub(crate) outlines: HashMap<String, Outline>,
+    /// Used for buttons without an outline
+    /// when `outlines` has no "default" entry.
+    pub(crate) default_outline: Option<Outline>,
+    /// Start text with capital letters. Defaults to false.
+    pub(crate) autocapitalize: Option<bool>,
+    /// How long a button must be held for a long press.
+    /// Must be between 100 and 5000.
+    pub(crate) long_press_ms: Option<u32>,
+    /// What buttons with invalid keysyms emit instead.
+    /// Defaults to nothing.
+    pub(crate) invalid_keysym: Option<KeysymFallback>,
+    /// What to do when there are no outlines at all.
+    /// Defaults to using a standard outline.
+    pub(crate) missing_outlines: Option<MissingOutlines>,
+    /// Send characters like "." as keysyms with names, like "period",
+    /// instead of ones named after the code point, like "U002E".
+    /// Defaults to false.
+    pub(crate) named_keysyms: Option<bool>,
+    /// The size of one cell, for outlines measured in grid cells
+    pub(crate) grid_unit: Option<f64>,
+    /// Let presses between the view and the edge of the panel
+    /// reach the buttons on the left, right and bottom.
+    /// Defaults to false.
+    pub(crate) extend_edge_hits: Option<bool>,
+    /// Stretch the buttons of every row to share the width of the view
+    /// evenly, e.g. for keypads. Defaults to false.
+    pub(crate) fill_rows: Option<bool>,
+    /// Gaps between rows and between buttons. Defaults to none.
+    pub(crate) spacing: Option<Spacing>,
+    /// The order of buttons in rows. Defaults to left to right.
+    pub(crate) direction: Option<Direction>,
+    /// A handwritten XKB keymap to use instead of a generated one,
+    /// relative to the layout file.
+    /// Buttons can choose its keys with `key`.
+    pub(crate) keymap_file: Option<String>,
+    /// The contents of `keymap_file`, read by `from_file`
+    #[serde(skip)]
+    pub(crate) keymap: Option<String>,
+    /// Buttons in other views preview the label of the button
+    /// in the same row and column of this view, e.g. "upper".
+    pub(crate) shift_view: Option<String>,
+    /// Replaces `direction` for the named views,
+    /// e.g. to keep digits left to right in an Arabic layout.
+    #[serde(default)]
+    pub(crate) view_directions: HashMap<String, Direction>,
+    /// Views which stay latched when their buttons are used,
+    /// e.g. emoji. Views not listed are not modal.
+    #[serde(default)]
+    pub(crate) modal_views: HashMap<String, bool>,
+    /// XKB names used in the generated keymaps,
+    /// for compositors which expect particular ones.
+    /// Empty or unusable names are replaced by the usual ones.
+    pub(crate) xkb_rules: Option<String>,
+    pub(crate) xkb_model: Option<String>,
+    pub(crate) xkb_layout: Option<String>,
+    // Information for people choosing a layout.
+    // Kept in the built layout, but doesn't change it.
+    /// Human-readable name of the layout
+    pub(crate) name_display: Option<String>,
+    pub(crate) author: Option<String>,
+    pub(crate) license: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize, PartialEq, Default)]
 #[serde(deny_unknown_fields)]
-struct Margins {
-    top: f64,
-    bottom: f64,
-    side: f64,
+pub(crate) struct Margins {
+    pub(crate) top: f64,
+    pub(crate) bottom: f64,
+    pub(crate) side: f64,
+}
+
+// Floats are hashed by their bits, so equal values hash the same,
+// apart from 0.0 and -0.0.
+
+impl Hash for Margins {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.top.to_bits().hash(state);
+        self.bottom.to_bits().hash(state);
+        self.side.to_bits().hash(state);
+    }
 }
 
 /// Buttons are embedded in a single string
-type ButtonIds = String;
+pub(crate) type ButtonIds = String;
+
+/// A row as written in the file
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RowForm {
+    /// Names separated by spaces
+    Text(ButtonIds),
+    /// One entry per button, e.g. for grids
+    Names(Vec<String>),
+}
+
+fn deserialize_views<'de, D: de::Deserializer<'de>>(deserializer: D)
+    -> Result<HashMap<String, Vec<ButtonIds>>, D::Error>
+{
+    let views: HashMap<String, Vec<RowForm>>
+        = Deserialize::deserialize(deserializer)?;
+    views.into_iter()
+        .map(|(name, rows)| {
+            let rows = rows.into_iter()
+                .map(|row| match row {
+                    RowForm::Text(text) => Ok(text),
+                    RowForm::Names(names) => {
+                        let bad_name = names.iter().find(|name| {
+                            name.is_empty()
+                                || name.contains(|c: char| c.is_ascii_whitespace())
+                        });
+                        match bad_name {
+                            Some(bad) => Err(de::Error::custom(format!(
+                                "Button name {:?} in view {} is empty or contains spaces",
+                                bad, name,
+                            ))),
+                            None => Ok(names.join(" ")),
+                        }
+                    },
+                })
+                .collect::<Result<_, _>>()?;
+            Ok((name, rows))
+        })
+        .collect()
+}
+
+/// Actions are single-entry maps, or bare names when they have no options.
+/// "show_prefs" takes optional ones, so its bare name is turned into a map.
+fn deserialize_action<'de, D: de::Deserializer<'de>>(deserializer: D)
+    -> Result<Option<Action>, D::Error>
+{
+    let value: Option<serde_yaml::Value>
+        = Deserialize::deserialize(deserializer)?;
+    let value = match value {
+        None => return Ok(None),
+        Some(serde_yaml::Value::String(name)) if name == "show_prefs" => {
+            let mut mapping = serde_yaml::Mapping::new();
+            mapping.insert(name.into(), serde_yaml::Mapping::new().into());
+            serde_yaml::Value::Mapping(mapping)
+        },
+        Some(value) => value,
+    };
+    serde_yaml::with::singleton_map::deserialize(value)
+        .map(Some)
+        .map_err(de::Error::custom)
+}
 
 /// All info about a single button
 /// Buttons can have multiple instances though.
-#[derive(Debug, Default, Deserialize, PartialEq)]
+#[derive(Debug, Default, Deserialize, PartialEq, Hash)]
 #[serde(deny_unknown_fields)]
-struct ButtonMeta {
+pub(crate) struct ButtonMeta {
     // TODO: structure (action, keysym, text, modifier) as an enum
     // to detect conflicts and missing values at compile time
     /// Special action to perform on activation.
     /// Conflicts with keysym, text, modifier.
-#[serde(with = "serde_yaml::with::singleton_map", default)]
-    action: Option<Action>,
+#[serde(deserialize_with = "deserialize_action", default)]
+    pub(crate) action: Option<Action>,
     /// The name of the XKB keysym to emit on activation.
     /// Conflicts with action, text, modifier.
-    keysym: Option<String>,
+    pub(crate) keysym: Option<String>,
     /// The text to submit on activation. Will be derived from ID if not present
     /// Conflicts with action, keysym, modifier.
-    text: Option<String>,
+    pub(crate) text: Option<String>,
     /// The modifier to apply while the key is locked
     /// Conflicts with action, keysym, text
-    modifier: Option<Modifier>,
+    pub(crate) modifier: Option<Modifier>,
     /// If not present, will be derived from text or the button ID
-    label: Option<String>,
+    pub(crate) label: Option<String>,
+    /// Label used instead of `label` in wide arrangements,
+    /// which have more room.
+    pub(crate) label_wide: Option<String>,
     /// Conflicts with label
-    icon: Option<String>,
+    pub(crate) icon: Option<String>,
+    /// Description for screen readers, e.g. "Delete" for an icon.
+    /// If not present, will be derived from the label, text or ID.
+    pub(crate) a11y_label: Option<String>,
     /// The name of the outline. If not present, will be "default"
-    outline: Option<String>,
+    pub(crate) outline: Option<String>,
+    /// Submit and show the uppercase form of the text,
+    /// whether it's derived from the ID or explicit.
+    /// Characters without an uppercase form stay unchanged.
+    pub(crate) uppercase: Option<bool>,
+    /// Time before a held button starts repeating.
+    /// If not present, the global setting applies.
+    pub(crate) repeat_delay_ms: Option<u32>,
+    /// Time between repetitions of a held button. Must be positive.
+    /// If not present, the global setting applies.
+    pub(crate) repeat_interval_ms: Option<u32>,
+    /// Presses coming sooner than this after the last one are ignored,
+    /// for touchscreens which register a single tap twice.
+    /// If not present, every press counts.
+    pub(crate) debounce_ms: Option<u32>,
+    /// Don't make sounds or vibrate when pressed. Defaults to false.
+    pub(crate) silent: Option<bool>,
+    /// Submit only into an empty text field, e.g. "https://" in URLs.
+    /// Defaults to false.
+    pub(crate) only_in_empty_field: Option<bool>,
+    /// Stay in the current view after submitting,
+    /// even if it was only latched. Defaults to false.
+    pub(crate) keep_view: Option<bool>,
+    /// Keysym on the second level of the key, selected with Shift.
+    /// Only for buttons submitting a single keysym.
+    pub(crate) shift_keysym: Option<String>,
+    /// Lets the input method handle composition for this button,
+    /// e.g. for CJK input. Defaults to false.
+    pub(crate) ime_aware: Option<bool>,
+    /// Starts out ignoring presses and drawn dimmed,
+    /// until enabled at runtime, e.g. paste with an empty clipboard.
+    /// Defaults to false.
+    pub(crate) disabled: Option<bool>,
+    /// Submitted instead of the usual text when the button is held
+    pub(crate) long_press_text: Option<String>,
+    /// Like `long_press_text`, but a keysym
+    pub(crate) long_press_keysym: Option<String>,
+    /// The key to press, by its name in the layout's `keymap_file`,
+    /// e.g. "<AE01>"
+    pub(crate) key: Option<String>,
+    /// Submits something else while a layout flag is on
+    pub(crate) alternate: Option<Alternate>,
+    /// Name of a group of buttons drawn on a shared background
+    pub(crate) cluster: Option<String>,
+    /// Special action to perform as soon as the button is pressed.
+    /// Together with on_release, conflicts with action, keysym, text, modifier.
+    #[serde(deserialize_with = "deserialize_action", default)]
+    pub(crate) on_press: Option<Action>,
+    /// Special action to perform when the button is released
+    #[serde(deserialize_with = "deserialize_action", default)]
+    pub(crate) on_release: Option<Action>,
+    /// Data for extensions, e.g. a macro ID. Not used by Squeekboard.
+    pub(crate) extra: Option<BTreeMap<String, String>>,
+}
+
+/// A submission replacing the usual one while a flag is on.
+/// Like in a button, the text is derived from the ID
+/// if neither keysym nor text is present.
+#[derive(Debug, Clone, Deserialize, PartialEq, Hash)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Alternate {
+    /// The name of the flag, as used by the "toggle" action
+    pub(crate) when: String,
+    /// Conflicts with text
+    pub(crate) keysym: Option<String>,
+    /// Conflicts with keysym
+    pub(crate) text: Option<String>,
+}
+
+/// A view together with the label of the button leading there
+#[derive(Debug, Deserialize, PartialEq, Clone, Hash)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ViewChoice {
+    pub(crate) view: String,
+    pub(crate) label: String,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[derive(Debug, Deserialize, PartialEq, Clone, Hash)]
 #[serde(deny_unknown_fields)]
-enum Action {
+pub(crate) enum Action {
     #[serde(rename="locking")]
     Locking {
         lock_view: String,
@@ -91,19 +324,60 @@ enum Action {
         pops: Option<bool>,
         #[serde(default)]
         looks_locked_from: Vec<String>,
+        /// Lock only when pressed twice in quick succession.
+        /// Defaults to false.
+        double_tap_lock: Option<bool>,
     },
     #[serde(rename="set_view")]
     SetView(String),
+    /// Switches to the first view, or from there to the second one.
+    /// The label shows which one is next.
+    #[serde(rename="toggle_view")]
+    ToggleView { first: ViewChoice, second: ViewChoice },
+    /// Return to the previous view
+    #[serde(rename="back")]
+    Back,
+    /// The target is "chooser" or "settings",
+    /// defaulting to "chooser"
     #[serde(rename="show_prefs")]
-    ShowPrefs,
+    ShowPrefs { target: Option<String> },
     /// Remove last character
     #[serde(rename="erase")]
     Erase,
+    /// Drop pending state, like locked modifiers or a dead key
+    #[serde(rename="clear")]
+    Clear,
+    /// Break the line in text fields taking many lines,
+    /// press Return in others
+    #[serde(rename="enter")]
+    Enter,
+    /// Call a D-Bus method without arguments.
+    /// Ignored unless enabled with SQUEEKBOARD_DBUS_ACTIONS.
+    #[serde(rename="dbus")]
+    DBus {
+        service: String,
+        path: String,
+        /// Interface name and method name, e.g. "org.example.Help.Open"
+        method: String,
+    },
+    /// Emit a dead keysym, which combines with the next key,
+    /// e.g. "dead_acute". Composing is left to the receiving side,
+    /// so the next key is sent as a key even if it has text.
+    #[serde(rename="dead_key")]
+    DeadKey { keysym: String },
+    /// Turns the named flag on or off,
+    /// switching between the submissions of buttons with an alternate.
+    #[serde(rename="toggle")]
+    Toggle(String),
+    /// One of "cut", "copy", "paste", "select_all".
+    /// Checked when the layout is built.
+    #[serde(rename="clipboard")]
+    Clipboard(String),
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Hash)]
 #[serde(deny_unknown_fields)]
-enum Modifier {
+pub(crate) enum Modifier {
     Control,
     Shift,
     Lock,
@@ -115,13 +389,632 @@ enum Modifier {
     Mod5,
 }
 
+/// Which way the buttons of a row go
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Hash)]
+#[serde(deny_unknown_fields)]
+pub(crate) enum Direction {
+    #[serde(rename="ltr")]
+    LeftToRight,
+    /// The first button of each row is on the right
+    #[serde(rename="rtl")]
+    RightToLeft,
+}
+
+/// Replacement for keysyms which don't exist
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Default, Hash)]
+#[serde(deny_unknown_fields)]
+pub(crate) enum KeysymFallback {
+    /// The button does nothing
+    #[default]
+    #[serde(rename="nothing")]
+    Nothing,
+    #[serde(rename="space")]
+    Space,
+    /// U+FFFD REPLACEMENT CHARACTER, to make the mistake visible
+    #[serde(rename="replacement")]
+    Replacement,
+}
+
+/// Handling of layouts without any outlines
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Default, Hash)]
+#[serde(deny_unknown_fields)]
+pub(crate) enum MissingOutlines {
+    /// Every button gets the outline of a letter key, with one warning
+    #[default]
+    #[serde(rename="default")]
+    Default,
+    /// The layout fails to build
+    #[serde(rename="error")]
+    Error,
+}
+
+/// Shorter would catch taps, longer would feel stuck
+const LONG_PRESS_RANGE_MS: std::ops::RangeInclusive<u32> = 100..=5000;
+
+/// The shape of a letter key in the bundled layouts
+const STANDARD_OUTLINE: (f64, f64) = (35.33, 52.0);
+
+/// A number, a percentage of the size of the view, like "10%",
+/// or a number of grid cells, like `{ cells: 2 }`
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub(crate) enum Length {
+    Absolute(f64),
+    /// Not validated until the layout is built.
+    Relative(String),
+    /// Multiplied by the layout's `grid_unit`
+    Cells { cells: u32 },
+}
+
+impl Hash for Length {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match self {
+            Length::Absolute(length) => length.to_bits().hash(state),
+            Length::Relative(text) => text.hash(state),
+            Length::Cells { cells } => cells.hash(state),
+        }
+    }
+}
+
+impl Length {
+    fn parse_percentage(text: &str) -> Option<f64> {
+        text.trim()
+            .strip_suffix('%')
+            .and_then(|number| number.trim_end().parse().ok())
+    }
+
+    /// Replaces grid cells with the length they stand for.
+    fn apply_grid(&self, grid_unit: f64) -> Length {
+        match self {
+            Length::Cells { cells } => Length::Absolute(*cells as f64 * grid_unit),
+            other => other.clone(),
+        }
+    }
+
+    /// Without the size of the view, relative lengths count as 0.
+    /// Grid cells count as 1 unless `apply_grid` was used before.
+    fn absolute_part(&self) -> f64 {
+        match self {
+            Length::Absolute(length) => *length,
+            Length::Relative(_) => 0.0,
+            Length::Cells { cells } => *cells as f64,
+        }
+    }
+
+    /// The part given as a percentage of the view, as a fraction of it.
+    /// Malformed percentages count as 0.
+    fn relative_part(&self) -> f64 {
+        match self {
+            Length::Relative(text) => Length::parse_percentage(text)
+                .map(|percent| percent / 100.0)
+                .unwrap_or(0.0),
+            Length::Absolute(_) | Length::Cells { .. } => 0.0,
+        }
+    }
+
+    /// Malformed percentages become 1.
+    fn resolve(&self, whole: f64) -> f64 {
+        match self {
+            Length::Absolute(_) | Length::Cells { .. } => self.absolute_part(),
+            Length::Relative(text) => Length::parse_percentage(text)
+                .map(|percent| whole * percent / 100.0)
+                .unwrap_or(1.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Outline {
+    pub(crate) width: Length,
+    pub(crate) height: Length,
+    /// Shifts the drawn button away from its place in the row.
+    /// Negative values are allowed, e.g. to reach into the margins.
+    #[serde(default)]
+    pub(crate) x: f64,
+    #[serde(default)]
+    pub(crate) y: f64,
+    /// Replaces some of the above in the wide arrangement
+    pub(crate) wide: Option<OutlineOverride>,
+}
+
+/// Each value present replaces the one in the outline
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct OutlineOverride {
+    pub(crate) width: Option<Length>,
+    pub(crate) height: Option<Length>,
+    pub(crate) x: Option<f64>,
+    pub(crate) y: Option<f64>,
+}
+
+impl Hash for Outline {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.width.hash(state);
+        self.height.hash(state);
+        self.x.to_bits().hash(state);
+        self.y.to_bits().hash(state);
+        self.wide.hash(state);
+    }
+}
+
+impl Hash for OutlineOverride {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.width.hash(state);
+        self.height.hash(state);
+        self.x.map(f64::to_bits).hash(state);
+        self.y.map(f64::to_bits).hash(state);
+    }
+}
+
+impl Outline {
+    /// Returns the outline used in the given arrangement
+    fn for_arrangement(&self, kind: layout::ArrangementKind) -> Outline {
+        match (kind, &self.wide) {
+            (layout::ArrangementKind::Wide, Some(wide)) => Outline {
+                width: wide.width.clone().unwrap_or(self.width.clone()),
+                height: wide.height.clone().unwrap_or(self.height.clone()),
+                x: wide.x.unwrap_or(self.x),
+                y: wide.y.unwrap_or(self.y),
+                wide: None,
+            },
+            _ => self.clone(),
+        }
+    }
+}
+
+/// Gaps in the same units as outlines
+#[derive(Debug, Clone, Deserialize, PartialEq, Default)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Spacing {
+    /// Between rows
+    #[serde(default)]
+    pub(crate) row: f64,
+    /// Between buttons in a row
+    #[serde(default)]
+    pub(crate) button: f64,
+    /// Replaces some of the above in the wide arrangement
+    pub(crate) wide: Option<SpacingOverride>,
+}
+
+/// Each value present replaces the one in the spacing
 #[derive(Debug, Clone, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
-struct Outline {
-    width: f64,
-    height: f64,
+pub(crate) struct SpacingOverride {
+    pub(crate) row: Option<f64>,
+    pub(crate) button: Option<f64>,
+}
+
+impl Hash for Spacing {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.row.to_bits().hash(state);
+        self.button.to_bits().hash(state);
+        self.wide.hash(state);
+    }
+}
+
+impl Hash for SpacingOverride {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.row.map(f64::to_bits).hash(state);
+        self.button.map(f64::to_bits).hash(state);
+    }
+}
+
+impl Spacing {
+    /// Returns the spacing used in the given arrangement
+    fn for_arrangement(&self, kind: layout::ArrangementKind) -> layout::Spacing {
+        match (kind, &self.wide) {
+            (layout::ArrangementKind::Wide, Some(wide)) => layout::Spacing {
+                row: wide.row.unwrap_or(self.row),
+                button: wide.button.unwrap_or(self.button),
+            },
+            _ => layout::Spacing { row: self.row, button: self.button },
+        }
+    }
+}
+
+/// Problems with the layout, found while building it
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayoutWarning {
+    /// More than one of action, keysym, text, modifier
+    ConflictingFields { button: String },
+    /// Like `ConflictingFields`, but with an action, which is kept
+    IgnoredFields { button: String, fields: Vec<&'static str> },
+    MissingView { button: String, view: String },
+    DBusWithoutInterface { button: String, method: String },
+    DBusDisabled { button: String },
+    InvalidKeysym { name: String },
+    /// The text to submit can't be used
+    InvalidText { text: String, error: String },
+    /// The text to show can't be used
+    InvalidLabel { text: String, error: String },
+    /// The modifier by its name in the layout
+    UnsupportedModifier { modifier: String },
+    EmptyField { button: String, field: &'static str },
+    MissingOutline { button: String, outline: String },
+    NoDefaultOutline,
+    /// Nothing to show, so the label was made from an id like "Shift_L"
+    LabelFromId { button: String, label: String },
+    /// Reported once instead of `NoDefaultOutline` for every button
+    NoOutlines,
+    NonPositiveRepeatInterval { button: String },
+    UnknownClipboardOp { button: String, op: String },
+    /// A valid keysym, but not one of the dead_* family
+    NotDeadKey { button: String, keysym: String },
+    /// Probably a sentence put in by mistake
+    LongLabel { button: String },
+    /// Text in the form of a shortcode, but not a known one
+    UnknownShortcode { button: String, text: String },
+    MissingDefaultView { view: String },
+    UnknownPrefsTarget { button: String, target: String },
+    /// Probably the lock and unlock views swapped
+    LockingIntoDefault { button: String },
+    /// Rows are split on whitespace, so the button can't be placed
+    ButtonNameWhitespace { button: String },
+    /// Drawn buttons stick out of the margins
+    ViewOverflow { view: String, overflow: f64 },
+    /// Offsets moved the button entirely out of its view
+    ButtonOutsideView { button: String, view: String },
+    /// Often intentional, e.g. for multi-character buttons
+    SharedKeysym { keysym: String, first: String, second: String },
+    /// The buttons keep working without keycodes
+    KeymapFailed { error: String },
+    /// The size is used as 1
+    NotPercentage { outline: String, size: String },
+    BaseKeymapInvalid,
+    /// The generated keys would need the keycodes of all base keys
+    BaseKeymapFull,
+    /// One side in grid cells and the other in plain numbers
+    MixedGridCells { outline: String },
+    /// Grid cells are used as 1
+    MissingGridUnit { outline: String },
+    /// Not usable as an XKB name
+    UnusableKeymapName { field: &'static str, value: String },
+    /// Only in lenient parsing, the field is removed
+    UnknownField { field: String, place: String },
+    MissingDirectionView { view: String },
+    MissingOrderedView { view: String },
+    UnknownShiftKeysym { button: String, keysym: String },
+    /// The shifted keysym needs a key of the button's own
+    UnusableShiftKeysym { button: String },
+    MissingShiftView { view: String },
+    /// Separate names may be wanted for styling
+    IdenticalOutlines { first: String, second: String },
+    /// The default duration is used
+    LongPressOutOfRange { ms: u32 },
+    MissingModalView { view: String },
+    /// Reachable from the default view, but without a way back
+    DeadEndView { view: String, default_view: String },
+    /// Only buttons with a key can be used
+    KeymapFileInvalid,
+    /// The keymap file is used as it is, so the keysym can't be added
+    KeysymNotInKeymapFile { keysym: String },
+    KeyNotInKeymapFile { button: String, key: String },
+    KeyWithoutKeymapFile { button: String },
+}
+
+impl LayoutWarning {
+    pub fn level(&self) -> logging::Level {
+        match self {
+            LayoutWarning::UnsupportedModifier { .. } => logging::Level::Bug,
+            // Readable enough, only a hint
+            LayoutWarning::LabelFromId { .. } => logging::Level::Info,
+            // Only useful when looking for mistakes
+            LayoutWarning::SharedKeysym { .. } => logging::Level::Debug,
+            LayoutWarning::KeymapFailed { .. } => logging::Level::Error,
+            LayoutWarning::IdenticalOutlines { .. } => logging::Level::Debug,
+            _ => logging::Level::Warning,
+        }
+    }
+}
+
+impl fmt::Display for LayoutWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::LayoutWarning::*;
+        match self {
+            ConflictingFields { button } => write!(
+                f,
+                "Button {} has more than one of (action, keysym, text, modifier)",
+                button,
+            ),
+            IgnoredFields { button, fields } => write!(
+                f,
+                "Button {} has an action, ignoring {}",
+                button, fields.join(", "),
+            ),
+            MissingView { button, view } => write!(
+                f,
+                "Button {} switches to missing view {}",
+                button, view,
+            ),
+            DBusWithoutInterface { button, method } => write!(
+                f,
+                "Button {} calls D-Bus method {} without an interface, ignoring",
+                button, method,
+            ),
+            DBusDisabled { button } => write!(
+                f,
+                "Button {} calls D-Bus, but SQUEEKBOARD_DBUS_ACTIONS is not set, ignoring",
+                button,
+            ),
+            InvalidKeysym { name } => write!(f, "Keysym name invalid: {}", name),
+            InvalidText { text, error } => write!(
+                f,
+                "Text {} contains problems: {}",
+                text, error,
+            ),
+            InvalidLabel { text, error } => write!(
+                f,
+                "Text {} is invalid: {}",
+                text, error,
+            ),
+            UnsupportedModifier { modifier } => write!(
+                f,
+                "Modifier {} unsupported",
+                modifier,
+            ),
+            EmptyField { button, field } => write!(
+                f,
+                "Button {} has an empty {}, ignoring",
+                button, field,
+            ),
+            MissingOutline { button, outline } => write!(
+                f,
+                "Outline named {} does not exist! Using default for button {}",
+                outline, button,
+            ),
+            MissingDefaultView { view } => write!(
+                f,
+                "Default view {} does not exist, using base",
+                view,
+            ),
+            NoOutlines => write!(
+                f,
+                "Layout defines no outlines, using {}x{} for all buttons",
+                STANDARD_OUTLINE.0, STANDARD_OUTLINE.1,
+            ),
+            LabelFromId { button, label } => write!(
+                f,
+                "Button {} has no label, showing {}. Consider adding a label",
+                button, label,
+            ),
+            NoDefaultOutline => write!(
+                f,
+                "No default outline defined! Using 1x1!",
+            ),
+            NonPositiveRepeatInterval { button } => write!(
+                f,
+                "Repeat interval of button {} must be positive, ignoring",
+                button,
+            ),
+            UnknownClipboardOp { button, op } => write!(
+                f,
+                "Button {} uses unknown clipboard operation {}, ignoring",
+                button, op,
+            ),
+            NotDeadKey { button, keysym } => write!(
+                f,
+                "Button {} uses {} as a dead key, but it isn't one, ignoring",
+                button, keysym,
+            ),
+            LongLabel { button } => write!(
+                f,
+                "Label of button {} is longer than {} characters, truncating",
+                button, MAX_LABEL_CHARS,
+            ),
+            UnknownShortcode { button, text } => write!(
+                f,
+                "Button {} has the text {}, which is not a known shortcode",
+                button, text,
+            ),
+            LockingIntoDefault { button } => write!(
+                f,
+                "Button {} locks into the default view, are lock_view and unlock_view swapped?",
+                button,
+            ),
+            UnknownPrefsTarget { button, target } => write!(
+                f,
+                "Button {} shows preferences in unknown target {}, using the chooser",
+                button, target,
+            ),
+            ButtonNameWhitespace { button } => write!(
+                f,
+                "Button {:?} contains whitespace and can't be used in views",
+                button,
+            ),
+            ViewOverflow { view, overflow } => write!(
+                f,
+                "View {} reaches {} past the edge of the panel",
+                view, overflow,
+            ),
+            ButtonOutsideView { button, view } => write!(
+                f,
+                "Button {} is entirely outside of view {}",
+                button, view,
+            ),
+            SharedKeysym { keysym, first, second } => write!(
+                f,
+                "Keysym {} is emitted by buttons {} and {}, which submit different text",
+                keysym, first, second,
+            ),
+            KeymapFailed { error } => write!(
+                f,
+                "Failed to generate keymap, buttons will not send keys: {}",
+                error,
+            ),
+            NotPercentage { outline, size } => write!(
+                f,
+                "Outline {} has a size {:?} which is not a percentage, using 1",
+                outline, size,
+            ),
+            BaseKeymapInvalid => write!(
+                f,
+                "Base keymap can't be compiled, ignoring",
+            ),
+            BaseKeymapFull => write!(
+                f,
+                "Base keymap leaves no free keycodes, ignoring",
+            ),
+            MixedGridCells { outline } => write!(
+                f,
+                "Outline {} mixes grid cells with plain numbers",
+                outline,
+            ),
+            MissingGridUnit { outline } => write!(
+                f,
+                "Outline {} uses grid cells, but there's no grid_unit, using 1",
+                outline,
+            ),
+            UnusableKeymapName { field, value } => write!(
+                f,
+                "Layout has an unusable {} {:?}, ignoring",
+                field, value,
+            ),
+            UnknownField { field, place } => write!(
+                f,
+                "Unknown field {} in {}, ignoring",
+                field, place,
+            ),
+            MissingDirectionView { view } => write!(
+                f,
+                "Direction given for missing view {}, ignoring",
+                view,
+            ),
+            MissingOrderedView { view } => write!(
+                f,
+                "View order lists missing view {}, ignoring",
+                view,
+            ),
+            UnknownShiftKeysym { button, keysym } => write!(
+                f,
+                "Button {} has an unknown shift_keysym {}, ignoring",
+                button, keysym,
+            ),
+            UnusableShiftKeysym { button } => write!(
+                f,
+                "Button {} doesn't submit a single keysym, ignoring its shift_keysym",
+                button,
+            ),
+            MissingShiftView { view } => write!(
+                f,
+                "Shift view {} is missing, ignoring",
+                view,
+            ),
+            IdenticalOutlines { first, second } => write!(
+                f,
+                "Outlines {} and {} are identical, consider using only {}",
+                first, second, first,
+            ),
+            LongPressOutOfRange { ms } => write!(
+                f,
+                "long_press_ms {} is outside of {}..={}, using {}",
+                ms,
+                LONG_PRESS_RANGE_MS.start(),
+                LONG_PRESS_RANGE_MS.end(),
+                layout::DEFAULT_LONG_PRESS_MS,
+            ),
+            MissingModalView { view } => write!(
+                f,
+                "Modal view {} is missing, ignoring",
+                view,
+            ),
+            DeadEndView { view, default_view } => write!(
+                f,
+                "View {} has no buttons leading back to {}",
+                view, default_view,
+            ),
+            KeymapFileInvalid => write!(
+                f,
+                "Keymap file can't be compiled, ignoring",
+            ),
+            KeysymNotInKeymapFile { keysym } => write!(
+                f,
+                "Keysym {} is not in the keymap file, it will not be sent",
+                keysym,
+            ),
+            KeyNotInKeymapFile { button, key } => write!(
+                f,
+                "Button {} uses key {}, which is not in the keymap file",
+                button, key,
+            ),
+            KeyWithoutKeymapFile { button } => write!(
+                f,
+                "Button {} has a key, but there is no usable keymap file, ignoring",
+                button,
+            ),
+        }
+    }
+}
+
+/// Stages of building a layout, in the order they happen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BuildPhase {
+    Parsing,
+    GeneratingKeymap,
+    BuildingViews,
+}
+
+impl fmt::Display for BuildPhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            BuildPhase::Parsing => "parsing",
+            BuildPhase::GeneratingKeymap => "generating keymap",
+            BuildPhase::BuildingViews => "building views",
+        })
+    }
+}
+
+/// Receives problems with buttons in a form which tools can inspect.
+/// By default, they are passed on as text.
+pub trait WarningHandler: logging::Handler {
+    fn handle_warning(&mut self, warning: LayoutWarning) {
+        self.handle(warning.level(), &warning.to_string())
+    }
+
+    /// Called when `build` enters a phase.
+    /// `fraction` is a rough estimate of the work done before it,
+    /// between 0 and 1. Ignored by default.
+    fn report_progress(&mut self, _phase: BuildPhase, _fraction: f64) {}
+}
+
+impl WarningHandler for logging::Print {}
+impl WarningHandler for logging::ProblemPanic {}
+
+/// Why `Layout::build_strict` rejected a layout
+#[derive(Debug)]
+pub enum StrictBuildError {
+    Keymap(FormattingError),
+    /// Every problem found, in the order they were reported
+    Warnings(Vec<String>),
+}
+
+/// Drops all problems, for when only the result counts
+struct IgnoreProblems;
+
+impl logging::Handler for IgnoreProblems {
+    fn handle(&mut self, _level: logging::Level, _message: &str) {}
+}
+
+impl WarningHandler for IgnoreProblems {}
+
+/// Keeps all problems for inspection
+struct CollectProblems(Vec<String>);
+
+impl logging::Handler for CollectProblems {
+    fn handle(&mut self, level: logging::Level, message: &str) {
+        use crate::logging::Level::*;
+        match level {
+            Panic | Bug | Error | Warning | Surprise => {
+                self.0.push(message.into())
+            },
+            _ => {},
+        }
+    }
 }
 
+impl WarningHandler for CollectProblems {}
+
 pub fn add_offsets<'a, I: 'a, T, F: 'a>(iterator: I, get_size: F)
     -> impl Iterator<Item=(f64, T)> + 'a
     where I: Iterator<Item=T>,
@@ -136,127 +1029,1178 @@ pub fn add_offsets<'a, I: 'a, T, F: 'a>(iterator: I, get_size: F)
     })
 }
 
+/// Some editors start UTF-8 files with a byte order mark,
+/// which is not part of the YAML
+fn strip_bom(data: &str) -> &str {
+    data.strip_prefix('\u{feff}').unwrap_or(data)
+}
+
+/// Makes keymaps out of the keysyms, the shift levels,
+/// the keys to keep from the base keymap, and the XKB names.
+/// Swapped out in tests.
+type GenerateKeymaps = fn(
+    HashMap<String, KeyCode>,
+    &ShiftLevels,
+    &[(u32, String)],
+    &KeymapNames,
+) -> Result<Vec<String>, FormattingError>;
+
 impl Layout {
     pub fn from_resource(name: &str) -> Result<Layout, LoadError> {
         let data = resources::get_keyboard(name)
                     .ok_or(LoadError::MissingResource)?;
-        serde_yaml::from_str(data)
-                    .map_err(LoadError::BadResource)
+        let layout: Layout = serde_yaml::from_str(strip_bom(data))
+                    .map_err(LoadError::BadResource)?;
+        layout.warn_keymap_file_unread();
+        Ok(layout)
     }
 
     pub fn from_file(path: PathBuf) -> Result<Layout, Error> {
-        let infile = BufReader::new(
-            fs::OpenOptions::new()
-                .read(true)
-                .open(&path)?
-        );
-        serde_yaml::from_reader(infile).map_err(Error::Yaml)
+        let data = fs::read_to_string(&path)?;
+        let mut layout: Layout = serde_yaml::from_str(strip_bom(&data))
+            .map_err(Error::Yaml)?;
+        layout.read_keymap_file(&path)?;
+        Ok(layout)
     }
 
-    pub fn build<H: logging::Handler>(self, mut warning_handler: H)
-        -> (Result<crate::layout::LayoutParseData, FormattingError>, H)
-    {
+    /// The fields describing the layout for people choosing one.
+    /// Text which can't be passed to C is left out.
+    pub fn get_metadata(&self) -> layout::Metadata {
+        let to_c = |text: &Option<String>| {
+            text.as_ref().and_then(|text| CString::new(text.as_str()).ok())
+        };
+        layout::Metadata {
+            name_display: to_c(&self.name_display),
+            author: to_c(&self.author),
+            license: to_c(&self.license),
+        }
+    }
+
+    /// Reads `keymap_file`, found next to the layout at `path`
+    fn read_keymap_file(&mut self, path: &Path) -> Result<(), Error> {
+        if let Some(keymap_file) = &self.keymap_file {
+            let keymap_path = match path.parent() {
+                Some(dir) => dir.join(keymap_file),
+                None => PathBuf::from(keymap_file),
+            };
+            let keymap = fs::read_to_string(&keymap_path)
+                .map_err(|e| Error::KeymapFile(keymap_path, e))?;
+            self.keymap = Some(keymap);
+        }
+        Ok(())
+    }
+
+    /// Like `from_file`, for descriptors handed over e.g. by portals.
+    /// The descriptor stays open.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be open, and not be closed by anyone else during the call.
+    pub unsafe fn from_fd(fd: RawFd) -> Result<Layout, Error> {
+        // Only borrowed, so it must not get closed on drop.
+        let mut file = mem::ManuallyDrop::new(fs::File::from_raw_fd(fd));
+        let mut data = String::new();
+        file.read_to_string(&mut data)?;
+        let layout: Layout = serde_yaml::from_str(strip_bom(&data))
+            .map_err(Error::Yaml)?;
+        layout.warn_keymap_file_unread();
+        Ok(layout)
+    }
+
+    /// Without a path, there's no directory to find `keymap_file` in
+    fn warn_keymap_file_unread(&self) {
+        if let Some(keymap_file) = &self.keymap_file {
+            log_print!(
+                logging::Level::Warning,
+                "Keymap file {} is only read next to layout files, ignoring",
+                keymap_file,
+            );
+        }
+    }
+
+    /// Returns a hash of the contents, which doesn't depend on formatting,
+    /// comments, or the order of entries in dictionaries.
+    /// It stays the same across runs and machines,
+    /// so it can be stored, e.g. as a cache key.
+    pub fn content_hash(&self) -> u64 {
+        fn hash_sorted<V: Hash, H: Hasher>(
+            map: &HashMap<String, V>,
+            state: &mut H,
+        ) {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by_key(|(name, _)| *name);
+            entries.hash(state);
+        }
+
+        let mut state = StableHasher::new();
+        let Layout {
+            margins, views, default_view, view_order, buttons, outlines, default_outline,
+            autocapitalize, long_press_ms, invalid_keysym, missing_outlines,
+            named_keysyms,
+            grid_unit, extend_edge_hits,
+            fill_rows, spacing, direction, keymap_file, keymap,
+            shift_view, view_directions, modal_views,
+            xkb_rules, xkb_model, xkb_layout,
+            name_display, author, license,
+        } = self;
+        margins.hash(&mut state);
+        hash_sorted(views, &mut state);
+        default_view.hash(&mut state);
+        view_order.hash(&mut state);
+        hash_sorted(buttons, &mut state);
+        hash_sorted(outlines, &mut state);
+        default_outline.hash(&mut state);
+        autocapitalize.hash(&mut state);
+        long_press_ms.hash(&mut state);
+        invalid_keysym.hash(&mut state);
+        missing_outlines.hash(&mut state);
+        named_keysyms.hash(&mut state);
+        grid_unit.map(f64::to_bits).hash(&mut state);
+        extend_edge_hits.hash(&mut state);
+        fill_rows.hash(&mut state);
+        spacing.hash(&mut state);
+        direction.hash(&mut state);
+        keymap_file.hash(&mut state);
+        keymap.hash(&mut state);
+        shift_view.hash(&mut state);
+        hash_sorted(view_directions, &mut state);
+        hash_sorted(modal_views, &mut state);
+        (xkb_rules, xkb_model, xkb_layout).hash(&mut state);
+        (name_display, author, license).hash(&mut state);
+        state.finish()
+    }
+
+    /// Like `from_file`, but fields which this version doesn't know
+    /// are reported and skipped, instead of failing.
+    /// Lets layouts written for newer versions load.
+    pub fn from_file_lenient<H: WarningHandler>(
+        path: PathBuf,
+        warning_handler: &mut H,
+    ) -> Result<Layout, Error> {
+        let data = fs::read_to_string(&path)?;
+        let mut value: serde_yaml::Value = serde_yaml::from_str(strip_bom(&data))
+            .map_err(Error::Yaml)?;
+        strip_unknown_fields(&mut value, warning_handler);
+        let mut layout: Layout = serde_yaml::from_value(value)
+            .map_err(Error::Yaml)?;
+        layout.read_keymap_file(&path)?;
+        Ok(layout)
+    }
+
+    /// Returns names of buttons placed in views
+    /// which are neither described in `buttons`,
+    /// nor are a single printable character.
+    /// Those would submit their own name, which is probably a typo.
+    pub fn missing_buttons(&self) -> Vec<String> {
         let button_names = self.views.values()
             .flat_map(|rows| {
                 rows.iter()
                     .flat_map(|row| row.split_ascii_whitespace())
             });
-        
         let button_names: HashSet<&str>
             = HashSet::from_iter(button_names);
 
-        let button_actions: Vec<(&str, crate::action::Action)>
-            = button_names.iter().map(|name| {(
-                *name,
-                create_action(
-                    &self.buttons,
-                    name,
-                    self.views.keys().collect(),
-                    &mut warning_handler,
-                )
-            )}).collect();
+        fn is_single_printable(name: &str) -> bool {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => !c.is_control(),
+                _ => false,
+            }
+        }
 
-        let symbolmap: HashMap<String, KeyCode> = generate_keycodes(
-            extract_symbol_names(&button_actions)
-        );
-
-        let button_states = HashMap::<String, Key>::from_iter(
-            button_actions.into_iter().map(|(name, action)| {
-                let keycodes = match &action {
-                    crate::action::Action::Submit { text: _, keys } => {
-                        keys.iter().map(|named_keysym| {
-                            symbolmap.get(named_keysym.0.as_str())
-                                .expect(
-                                    format!(
-                                        "keysym {} in key {} missing from symbol map",
-                                        named_keysym.0,
-                                        name
-                                    ).as_str()
-                                )
-                                .clone()
-                        }).collect()
-                    },
-                    action::Action::Erase => vec![
-                        symbolmap.get("BackSpace")
-                            .expect(&format!("BackSpace missing from symbol map"))
-                            .clone(),
-                    ],
-                    _ => Vec::new(),
-                };
-                (
-                    name.into(),
-                    Key {
-                        keycodes,
-                        action,
-                    }
-                )
+        let mut missing: Vec<String> = button_names.into_iter()
+            .filter(|name| !self.buttons.contains_key(*name))
+            .filter(|name| !is_single_printable(name))
+            .map(String::from)
+            .collect();
+        missing.sort();
+        missing
+    }
+
+    fn action_options(&self, dbus_enabled: bool) -> ActionOptions<'_> {
+        let default_view = match &self.default_view {
+            Some(view) if self.views.contains_key(view) => view.as_str(),
+            // Reported when building
+            _ => "base",
+        };
+        ActionOptions {
+            view_names: self.views.keys().collect(),
+            default_view,
+            dbus_enabled,
+            invalid_keysym: self.invalid_keysym.unwrap_or_default(),
+            named_keysyms: self.named_keysyms.unwrap_or(false),
+        }
+    }
+
+    /// Finds the characters typed by buttons in the views
+    /// whose keysyms are named after the code point, like "U20AC",
+    /// because there's no keysym named after the character.
+    /// Sorted, without repetitions.
+    pub fn codepoint_fallback_chars(&self) -> Vec<char> {
+        let button_names: HashSet<&str> = self.views.values()
+            .flat_map(|rows| {
+                rows.iter()
+                    .flat_map(|row| row.split_ascii_whitespace())
             })
-        );
+            .collect();
+        let options = self.action_options(false);
+        let mut chars: Vec<char> = button_names.into_iter()
+            .map(|name| create_action(
+                &self.buttons,
+                name,
+                &options,
+                // Reported when building anyway
+                &mut IgnoreProblems,
+            ))
+            .filter_map(|action| match action {
+                action::Action::Submit { text: Some(text), .. } => Some(text),
+                _ => None,
+            })
+            .flat_map(|text| {
+                text.to_string_lossy().chars().collect::<Vec<_>>()
+            })
+            .filter(|c| {
+                char_keysym_name(*c, options.named_keysyms)
+                    == codepoint_keysym_name(*c)
+            })
+            .collect();
+        chars.sort();
+        chars.dedup();
+        chars
+    }
 
-        let keymaps = match generate_keymaps(symbolmap) {
-            Err(e) => { return (Err(e), warning_handler) },
-            Ok(v) => v,
+    /// Returns the bounds of every outline, sorted by name.
+    /// Sizes relative to the view count as 0 in the bounds,
+    /// and are listed separately.
+    /// The root-level `default_outline` is listed as "default"
+    /// unless `outlines` already has that entry.
+    pub fn outlines_summary(&self) -> Vec<layout::OutlineSummary> {
+        let grid_unit = self.grid_unit.unwrap_or(1.0);
+        let default = match self.outlines.contains_key("default") {
+            true => None,
+            false => self.default_outline.as_ref()
+                .map(|outline| ("default", outline)),
         };
+        let mut summary: Vec<layout::OutlineSummary>
+            = self.outlines.iter()
+            .map(|(name, outline)| (name.as_str(), outline))
+            .chain(default)
+            // Names come from YAML strings, which may contain a nul.
+            .filter_map(|(name, outline)| Some(layout::OutlineSummary {
+                name: CString::new(name).ok()?,
+                bounds: layout::c::Bounds {
+                    x: outline.x,
+                    y: outline.y,
+                    width: outline.width.apply_grid(grid_unit).absolute_part(),
+                    height: outline.height.apply_grid(grid_unit)
+                        .absolute_part(),
+                },
+                relative: layout::Size {
+                    width: outline.width.relative_part(),
+                    height: outline.height.relative_part(),
+                },
+            }))
+            .collect();
+        summary.sort_by(|a, b| a.name.cmp(&b.name));
+        summary
+    }
 
-        let button_states_cache = button_states;
+    /// The arrangement kind selects between the regular and the wide labels.
+    pub fn build<H: WarningHandler>(
+        self,
+        kind: layout::ArrangementKind,
+        warning_handler: H,
+    ) -> (Result<crate::layout::LayoutParseData, FormattingError>, H)
+    {
+        self.build_with(
+            kind,
+            generate_keymaps,
+            false,
+            None,
+            false,
+            warning_handler,
+        )
+    }
 
-        let views: Vec<_> = self.views.iter()
-            .map(|(name, view)| {
-                let rows = view.iter().map(|row| {
-                    let buttons = row.split_ascii_whitespace()
-                        .map(|name| {
-                            create_button(
-                                &self.buttons,
-                                &self.outlines,
-                                name,
-                                button_states_cache.get(name.into())
-                                    .expect("Button state not created")
-                                    .clone(),
-                                &mut warning_handler,
-                            )
-                        });
-                    layout::Row::new(
-                        add_offsets(
-                            buttons,
-                            |button| button.size.width,
-                        ).collect()
-                    )
-                });
-                let rows = add_offsets(rows, |row| row.get_size().height)
-                    .collect();
-                (
-                    name.clone(),
-                    layout::View::new(rows)
+    /// Like `build`, but any problem makes the layout fail,
+    /// e.g. for checking layouts before they are shipped.
+    pub fn build_strict(self, kind: layout::ArrangementKind)
+        -> Result<crate::layout::LayoutParseData, StrictBuildError>
+    {
+        let (layout, problems) = self.build(kind, CollectProblems(Vec::new()));
+        match (layout, problems.0) {
+            (Err(e), _) => Err(StrictBuildError::Keymap(e)),
+            (Ok(layout), problems) => match problems.is_empty() {
+                true => Ok(layout),
+                false => Err(StrictBuildError::Warnings(problems)),
+            },
+        }
+    }
+
+    /// Finds all buttons naming outlines which don't exist,
+    /// without building the layout.
+    /// Returns the same messages as `build` would report, sorted by button.
+    pub fn check_outline_references(&self) -> Vec<String> {
+        let mut missing: Vec<(&String, &String)> = self.buttons.iter()
+            .filter_map(|(name, meta)| {
+                let outline = meta.outline.as_ref()?;
+                match self.outlines.contains_key(outline) {
+                    true => None,
+                    false => Some((name, outline)),
+                }
+            })
+            .collect();
+        missing.sort();
+        missing.into_iter()
+            .map(|(button, outline)| LayoutWarning::MissingOutline {
+                button: button.clone(),
+                outline: outline.clone(),
+            }.to_string())
+            .collect()
+    }
+
+    /// Finds keysyms named in buttons
+    /// which the xkbcommon library on this system doesn't know.
+    /// Those depend on the library version, so a layout checked elsewhere
+    /// may still be missing keys on a particular device.
+    /// Returns one message per keysym, sorted by button.
+    pub fn validate_keysyms_against_runtime(&self) -> Vec<String> {
+        let mut unknown: Vec<(&String, &String)> = self.buttons.iter()
+            .flat_map(|(name, meta)| {
+                let dead_keys = [&meta.action, &meta.on_press, &meta.on_release]
+                    .into_iter()
+                    .filter_map(|action| match action {
+                        Some(Action::DeadKey { keysym }) => Some(keysym),
+                        _ => None,
+                    });
+                let keysyms = [
+                    &meta.keysym,
+                    &meta.shift_keysym,
+                    &meta.long_press_keysym,
+                ];
+                keysyms.into_iter().flatten()
+                    .chain(meta.alternate.iter().flat_map(|a| a.keysym.iter()))
+                    .chain(dead_keys)
+                    .map(move |keysym| (name, keysym))
+            })
+            .filter(|(_name, keysym)| {
+                xkb::keysym_from_name(keysym, xkb::KEYSYM_NO_FLAGS)
+                    == xkb::KEY_NoSymbol
+            })
+            .collect();
+        unknown.sort();
+        unknown.into_iter()
+            .map(|(button, keysym)| format!(
+                "Button {} uses keysym {}, unknown to this system",
+                button, keysym,
+            ))
+            .collect()
+    }
+
+    /// Renames a view, together with every action switching to it,
+    /// for editing layouts in tools.
+    /// Nothing changes if the view is missing,
+    /// or if another view already has the new name.
+    pub fn rename_view(&mut self, from: &str, to: &str) -> Result<(), String> {
+        if self.views.contains_key(to) {
+            return Err(format!("View {} already exists", to));
+        }
+        let rows = self.views.remove(from)
+            .ok_or_else(|| format!("No view named {}", from))?;
+        self.views.insert(to.into(), rows);
+
+        let rename = |name: &mut String| if name == from {
+            *name = to.into();
+        };
+        self.default_view.iter_mut().for_each(rename);
+        self.view_order.iter_mut().flatten().for_each(rename);
+        if let Some(modal) = self.modal_views.remove(from) {
+            self.modal_views.insert(to.into(), modal);
+        }
+        self.shift_view.iter_mut().for_each(rename);
+        if let Some(direction) = self.view_directions.remove(from) {
+            self.view_directions.insert(to.into(), direction);
+        }
+        let actions = self.buttons.values_mut()
+            .flat_map(|meta| {
+                meta.action.iter_mut()
+                    .chain(meta.on_press.iter_mut())
+                    .chain(meta.on_release.iter_mut())
+            });
+        for action in actions {
+            match action {
+                Action::SetView(view) => rename(view),
+                Action::ToggleView { first, second } => {
+                    rename(&mut first.view);
+                    rename(&mut second.view);
+                },
+                Action::Locking {
+                    lock_view, unlock_view, looks_locked_from, ..
+                } => {
+                    rename(lock_view);
+                    rename(unlock_view);
+                    looks_locked_from.iter_mut().for_each(rename);
+                },
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `build`, but meant for layouts which will be shown.
+    ///
+    /// When the keymap can't be generated,
+    /// reports the problem and uses a keymap without any keys.
+    /// Switching views and submitting text through the input method
+    /// keep working then.
+    ///
+    /// The keys of `base_keymap` are kept in the generated keymaps,
+    /// and the layout's own keys get different keycodes.
+    ///
+    /// Buttons calling D-Bus methods only work with `dbus_actions` set.
+    pub fn build_with_keymap_fallback<H: WarningHandler>(
+        self,
+        kind: layout::ArrangementKind,
+        base_keymap: Option<&str>,
+        dbus_actions: bool,
+        warning_handler: H,
+    ) -> (Result<crate::layout::LayoutParseData, FormattingError>, H)
+    {
+        self.build_with(
+            kind,
+            generate_keymaps,
+            true,
+            base_keymap,
+            dbus_actions,
+            warning_handler,
+        )
+    }
+
+    fn build_with<H: WarningHandler>(
+        mut self,
+        kind: layout::ArrangementKind,
+        generate_keymaps: GenerateKeymaps,
+        keymap_fallback: bool,
+        base_keymap: Option<&str>,
+        dbus_actions: bool,
+        mut warning_handler: H,
+    ) -> (Result<crate::layout::LayoutParseData, FormattingError>, H)
+    {
+        warning_handler.report_progress(BuildPhase::Parsing, 0.0);
+        let metadata = self.get_metadata();
+        let outline_summary = self.outlines_summary();
+
+        // Filled rows make their own default outline later.
+        if self.outlines.is_empty() && self.default_outline.is_none()
+            && !self.fill_rows.unwrap_or(false)
+        {
+            match self.missing_outlines.unwrap_or_default() {
+                MissingOutlines::Error => {
+                    return (
+                        Err(FormattingError::NoOutlines),
+                        warning_handler,
+                    );
+                },
+                MissingOutlines::Default => {
+                    warning_handler.handle_warning(LayoutWarning::NoOutlines);
+                    self.default_outline = Some(Outline {
+                        width: Length::Absolute(STANDARD_OUTLINE.0),
+                        height: Length::Absolute(STANDARD_OUTLINE.1),
+                        x: 0.0,
+                        y: 0.0,
+                        wide: None,
+                    });
+                },
+            }
+        }
+
+        for (first, second) in find_identical_outlines(&self.outlines) {
+            warning_handler.handle_warning(LayoutWarning::IdenticalOutlines {
+                first: first.into(),
+                second: second.into(),
+            });
+        }
+
+        // From now on, outlines are those of the arrangement,
+        // and grid cells are plain numbers.
+        let grid_unit = self.grid_unit.unwrap_or(1.0);
+        let outlines = self.outlines.iter_mut()
+            .map(|(name, outline)| (name.as_str(), outline))
+            .chain(
+                self.default_outline.iter_mut()
+                    .map(|o| ("default_outline", o))
+            );
+        for (name, outline) in outlines {
+            // The other arrangement isn't needed any more.
+            *outline = outline.for_arrangement(kind);
+            match (&outline.width, &outline.height) {
+                (Length::Cells { .. }, Length::Absolute(_))
+                | (Length::Absolute(_), Length::Cells { .. }) => {
+                    warning_handler.handle_warning(
+                        LayoutWarning::MixedGridCells { outline: name.into() },
+                    );
+                },
+                _ => {},
+            };
+            let uses_cells = [&outline.width, &outline.height].iter()
+                .any(|length| matches!(length, Length::Cells { .. }));
+            if uses_cells && self.grid_unit.is_none() {
+                warning_handler.handle_warning(
+                    LayoutWarning::MissingGridUnit { outline: name.into() },
+                );
+            }
+            outline.width = outline.width.apply_grid(grid_unit);
+            outline.height = outline.height.apply_grid(grid_unit);
+        }
+
+        let outlines = self.outlines.iter()
+            .map(|(name, outline)| (name.as_str(), outline))
+            .chain(self.default_outline.iter().map(|o| ("default_outline", o)));
+        for (name, outline) in outlines {
+            for length in [&outline.width, &outline.height] {
+                if let Length::Relative(text) = length {
+                    if Length::parse_percentage(text).is_none() {
+                        warning_handler.handle_warning(
+                            LayoutWarning::NotPercentage {
+                                outline: name.into(),
+                                size: text.clone(),
+                            },
+                        );
+                    }
+                }
+            }
+        }
+
+        // Rows are split on whitespace,
+        // so such buttons can never be placed in a view.
+        for name in self.buttons.keys() {
+            if name.contains(|c: char| c.is_ascii_whitespace()) {
+                warning_handler.handle_warning(
+                    LayoutWarning::ButtonNameWhitespace { button: name.clone() },
+                );
+            }
+        }
+
+        let button_names = self.views.values()
+            .flat_map(|rows| {
+                rows.iter()
+                    .flat_map(|row| row.split_ascii_whitespace())
+            });
+        
+        let button_names: HashSet<&str>
+            = HashSet::from_iter(button_names);
+
+        let options = self.action_options(dbus_actions);
+
+        let button_actions: Vec<(&str, crate::action::Action)>
+            = button_names.iter().map(|name| {(
+                *name,
+                create_action(
+                    &self.buttons,
+                    name,
+                    &options,
+                    &mut warning_handler,
                 )
+            )}).collect();
+
+        // Checked the same as the usual submissions.
+        let mut alternate_flags: HashMap<&str, String> = HashMap::new();
+        let alternate_actions: Vec<(&str, crate::action::Action)>
+            = button_names.iter().filter_map(|name| {
+                let button_meta = self.buttons.get(*name)?;
+                let alternate = button_meta.alternate.as_ref()?;
+                alternate_flags.insert(*name, alternate.when.clone());
+                let alternate_meta = ButtonMeta {
+                    keysym: alternate.keysym.clone(),
+                    text: alternate.text.clone(),
+                    uppercase: button_meta.uppercase,
+                    ..ButtonMeta::default()
+                };
+                Some((
+                    *name,
+                    create_action_from_meta(
+                        &alternate_meta,
+                        name,
+                        &options,
+                        &mut warning_handler,
+                    ),
+                ))
             }).collect();
 
-        // Center views on the same point.
-        let views = {
-            let total_size = layout::View::calculate_super_size(
-                views.iter().map(|(_name, view)| view).collect()
-            );
+        // Checked the same as the usual submissions too.
+        let long_press_actions: Vec<(&str, crate::action::Action)>
+            = button_names.iter().filter_map(|name| {
+                let button_meta = self.buttons.get(*name)?;
+                if button_meta.long_press_text.is_none()
+                    && button_meta.long_press_keysym.is_none()
+                {
+                    return None;
+                }
+                let long_press_meta = ButtonMeta {
+                    keysym: button_meta.long_press_keysym.clone(),
+                    text: button_meta.long_press_text.clone(),
+                    uppercase: button_meta.uppercase,
+                    ..ButtonMeta::default()
+                };
+                Some((
+                    *name,
+                    create_action_from_meta(
+                        &long_press_meta,
+                        name,
+                        &options,
+                        &mut warning_handler,
+                    ),
+                ))
+            }).collect();
+
+        // Only special actions, so the same rules apply as to "action".
+        let mut press_actions: Vec<(&str, crate::action::Action)> = Vec::new();
+        let mut release_actions: Vec<(&str, crate::action::Action)>
+            = Vec::new();
+        for name in &button_names {
+            let button_meta = match self.buttons.get(*name) {
+                Some(meta) => meta,
+                None => continue,
+            };
+            let edges = [
+                (&button_meta.on_press, &mut press_actions),
+                (&button_meta.on_release, &mut release_actions),
+            ];
+            for (action, actions) in edges {
+                if let Some(action) = action {
+                    let edge_meta = ButtonMeta {
+                        action: Some(action.clone()),
+                        ..ButtonMeta::default()
+                    };
+                    actions.push((
+                        *name,
+                        create_action_from_meta(
+                            &edge_meta,
+                            name,
+                            &options,
+                            &mut warning_handler,
+                        ),
+                    ));
+                }
+            }
+        }
+
+        for (keysym, first, second) in find_shared_keysyms(&button_actions) {
+            warning_handler.handle_warning(LayoutWarning::SharedKeysym {
+                keysym: keysym.into(),
+                first: first.into(),
+                second: second.into(),
+            });
+        }
+
+        warning_handler.report_progress(BuildPhase::GeneratingKeymap, 0.4);
+
+        let base_keys = match base_keymap.map(read_keymap_keys) {
+            Some(None) => {
+                warning_handler.handle_warning(
+                    LayoutWarning::BaseKeymapInvalid,
+                );
+                Vec::new()
+            },
+            Some(Some(keys)) => keys,
+            None => Vec::new(),
+        };
+        let reserved: Vec<u32> = base_keys.iter()
+            .map(|(code, _name)| *code)
+            .collect();
+        let (base_keys, reserved) = match free_keycodes(&reserved).is_empty() {
+            true => {
+                warning_handler.handle_warning(LayoutWarning::BaseKeymapFull);
+                (Vec::new(), Vec::new())
+            },
+            false => (base_keys, reserved),
+        };
+
+        // A handwritten keymap is used as it is,
+        // so buttons can only send the keysyms which it has.
+        let own_keys = match self.keymap.as_deref() {
+            Some(keymap) => match read_keymap_keys(keymap) {
+                Some(keys) => Some((keymap, keys)),
+                None => {
+                    warning_handler.handle_warning(
+                        LayoutWarning::KeymapFileInvalid,
+                    );
+                    None
+                },
+            },
+            None => None,
+        };
+        let own_keymap = own_keys.is_some();
+
+        let symbolmap: HashMap<String, KeyCode> = match &own_keys {
+            // Reversed, so that the lowest keycode of a keysym wins.
+            Some((_keymap, keys)) => keys.iter().rev()
+                .map(|(code, name)| (
+                    name.clone(),
+                    KeyCode { code: *code, keymap_idx: 0 },
+                ))
+                .collect(),
+            None => generate_keycodes(
+                extract_symbol_names(&button_actions)
+                    .chain(extract_symbol_names(&alternate_actions))
+                    .chain(extract_symbol_names(&long_press_actions))
+                    .chain(extract_symbol_names(&press_actions))
+                    .chain(extract_symbol_names(&release_actions)),
+                &reserved,
+            ),
+        };
+
+        if own_keymap {
+            // Buttons with a key don't need their keysyms in the keymap.
+            let keyless: Vec<_> = button_actions.iter()
+                .filter(|(name, _action)| {
+                    self.buttons.get(*name)
+                        .and_then(|meta| meta.key.as_ref())
+                        .is_none()
+                })
+                .cloned()
+                .collect();
+            let mut missing: Vec<_> = extract_symbol_names(&keyless)
+                .chain(extract_symbol_names(&alternate_actions))
+                .chain(extract_symbol_names(&long_press_actions))
+                .chain(extract_symbol_names(&press_actions))
+                .chain(extract_symbol_names(&release_actions))
+                .filter(|keysym| !symbolmap.contains_key(keysym))
+                .collect();
+            missing.sort();
+            missing.dedup();
+            for keysym in missing {
+                warning_handler.handle_warning(
+                    LayoutWarning::KeysymNotInKeymapFile { keysym },
+                );
+            }
+        }
+
+        // The shifted keysym lives on the same key,
+        // so the button must not need more than one.
+        let mut shift_levels = ShiftLevels::new();
+        for (name, action) in &button_actions {
+            let shifted = match self.buttons.get(*name)
+                .and_then(|meta| meta.shift_keysym.as_ref())
+            {
+                Some(shifted) => shifted,
+                None => continue,
+            };
+            match action {
+                action::Action::Submit { text: _, keys } if keys.len() == 1 => {
+                    if xkb::keysym_from_name(shifted, xkb::KEYSYM_NO_FLAGS)
+                        == xkb::KEY_NoSymbol
+                    {
+                        warning_handler.handle_warning(
+                            LayoutWarning::UnknownShiftKeysym {
+                                button: name.to_string(),
+                                keysym: shifted.clone(),
+                            },
+                        );
+                    } else {
+                        shift_levels.insert(keys[0].0.clone(), shifted.clone());
+                    }
+                },
+                _ => warning_handler.handle_warning(
+                    LayoutWarning::UnusableShiftKeysym {
+                        button: name.to_string(),
+                    },
+                ),
+            }
+        }
+
+        let keymap_names = {
+            let mut get_name = |field: &'static str, value: &Option<String>| {
+                let value = value.as_ref()?;
+                match KeymapNames::is_valid_name(value) {
+                    true => Some(value.clone()),
+                    false => {
+                        warning_handler.handle_warning(
+                            LayoutWarning::UnusableKeymapName {
+                                field,
+                                value: value.clone(),
+                            },
+                        );
+                        None
+                    },
+                }
+            };
+            let defaults = KeymapNames::default();
+            KeymapNames {
+                rules: get_name("xkb_rules", &self.xkb_rules)
+                    .unwrap_or(defaults.rules),
+                model: get_name("xkb_model", &self.xkb_model),
+                layout: get_name("xkb_layout", &self.xkb_layout)
+                    .unwrap_or(defaults.layout),
+            }
+        };
 
-            HashMap::from_iter(views.into_iter().map(|(name, view)| (
+        let generated = match &own_keys {
+            Some((keymap, _keys)) => Ok(vec![keymap.to_string()]),
+            None => generate_keymaps(
+                symbolmap.clone(),
+                &shift_levels,
+                &base_keys,
+                &keymap_names,
+            ),
+        };
+        let (keymaps, has_keys) = match generated {
+            Ok(v) => (v, true),
+            Err(e) => match keymap_fallback {
+                false => return (Err(e), warning_handler),
+                true => {
+                    warning_handler.handle_warning(
+                        LayoutWarning::KeymapFailed { error: e.to_string() },
+                    );
+                    match generate_empty_keymap(&keymap_names) {
+                        Ok(keymap) => (vec![keymap], false),
+                        Err(e) => return (Err(e), warning_handler),
+                    }
+                },
+            },
+        };
+
+        let find_keycodes = |name: &str, action: &action::Action| {
+            match action {
+                // None of the keys are in the keymap.
+                _ if !has_keys => Vec::new(),
+                // Missing keysyms were reported already.
+                _ if own_keymap => symbol_names(action).into_iter()
+                    .filter_map(|keysym| symbolmap.get(keysym.0.as_str()))
+                    .cloned()
+                    .collect(),
+                crate::action::Action::Submit { text: _, keys } => {
+                    keys.iter().map(|named_keysym| {
+                        symbolmap.get(named_keysym.0.as_str())
+                            .unwrap_or_else(|| panic!(
+                                "keysym {} in key {} missing from symbol map",
+                                named_keysym.0,
+                                name,
+                            ))
+                            .clone()
+                    }).collect()
+                },
+                action::Action::Erase => vec![
+                    symbolmap.get("BackSpace")
+                        .expect("BackSpace missing from symbol map")
+                        .clone(),
+                ],
+                action::Action::Enter => vec![
+                    symbolmap.get("Return")
+                        .expect("Return missing from symbol map")
+                        .clone(),
+                ],
+                action::Action::Clipboard(op) => {
+                    let keysym = op.keysym();
+                    vec![
+                        symbolmap.get(keysym.0.as_str())
+                            .unwrap_or_else(|| panic!(
+                                "{} missing from symbol map",
+                                keysym.0,
+                            ))
+                            .clone(),
+                    ]
+                },
+                _ => Vec::new(),
+            }
+        };
+
+        let mut button_states = HashMap::<String, Key>::from_iter(
+            button_actions.into_iter().map(|(name, action)| (
+                name.into(),
+                Key {
+                    keycodes: find_keycodes(name, &action),
+                    action,
+                },
+            ))
+        );
+
+        let alternates = HashMap::<&str, layout::Alternate>::from_iter(
+            alternate_actions.into_iter().map(|(name, action)| (
+                name,
+                layout::Alternate {
+                    flag: alternate_flags.remove(name)
+                        .expect("Alternate flag not stored"),
+                    keycodes: find_keycodes(name, &action),
+                    action,
+                },
+            ))
+        );
+
+        let long_presses = HashMap::<&str, layout::Effect>::from_iter(
+            long_press_actions.into_iter().map(|(name, action)| (
+                name,
+                layout::Effect {
+                    keycodes: find_keycodes(name, &action),
+                    action,
+                },
+            ))
+        );
+
+        let mut edges = HashMap::<&str, layout::Edges>::new();
+        for (name, action) in press_actions {
+            edges.entry(name).or_default().on_press = Some(layout::Effect {
+                keycodes: find_keycodes(name, &action),
+                action,
+            });
+        }
+        for (name, action) in release_actions {
+            edges.entry(name).or_default().on_release = Some(layout::Effect {
+                keycodes: find_keycodes(name, &action),
+                action,
+            });
+        }
+
+        let mut keyed: Vec<(&str, &str)> = self.buttons.iter()
+            .filter_map(|(name, meta)| Some((name.as_str(), meta.key.as_deref()?)))
+            .collect();
+        keyed.sort();
+        let key_codes = match &own_keys {
+            Some((keymap, _keys)) => {
+                let keys: Vec<_> = keyed.iter().map(|(_name, key)| *key).collect();
+                find_keys_by_name(keymap, &keys)
+            },
+            None => None,
+        };
+        match key_codes {
+            Some(codes) => for ((name, key), code) in keyed.iter().zip(codes) {
+                match (button_states.get_mut(*name), code) {
+                    (Some(state), Some(code)) => {
+                        state.keycodes = vec![KeyCode { code, keymap_idx: 0 }];
+                    },
+                    (Some(_state), None) => warning_handler.handle_warning(
+                        LayoutWarning::KeyNotInKeymapFile {
+                            button: name.to_string(),
+                            key: key.to_string(),
+                        },
+                    ),
+                    // Not in any view
+                    (None, _) => {},
+                }
+            },
+            None => for (name, _key) in keyed {
+                warning_handler.handle_warning(
+                    LayoutWarning::KeyWithoutKeymapFile {
+                        button: name.to_string(),
+                    },
+                );
+            },
+        }
+
+        // Sorted by name, so that the indices stay the same across builds.
+        let mut state_names: Vec<_> = button_states.keys().cloned().collect();
+        state_names.sort();
+        let key_indices = state_names.into_iter()
+            .enumerate()
+            .map(|(index, name)| (name, index))
+            .collect();
+
+        let button_states_cache = button_states;
+
+        warning_handler.report_progress(BuildPhase::BuildingViews, 0.6);
+
+        let fill_rows = self.fill_rows.unwrap_or(false);
+        let spacing = self.spacing.as_ref()
+            .map(|spacing| spacing.for_arrangement(kind))
+            .unwrap_or(layout::Spacing { row: 0.0, button: 0.0 });
+        // Filled rows only need heights, so outlines are optional.
+        if fill_rows && !self.outlines.contains_key("default") {
+            self.default_outline.get_or_insert(Outline {
+                width: Length::Absolute(1.0),
+                height: Length::Absolute(1.0),
+                x: 0.0,
+                y: 0.0,
+                wide: None,
+            });
+        }
+
+        for name in self.view_directions.keys() {
+            if !self.views.contains_key(name) {
+                warning_handler.handle_warning(
+                    LayoutWarning::MissingDirectionView { view: name.clone() },
+                );
+            }
+        }
+
+        let modal_views = self.modal_views.iter()
+            .filter(|(_name, modal)| **modal)
+            .map(|(name, _modal)| name)
+            .filter(|name| match self.views.contains_key(*name) {
+                true => true,
+                false => {
+                    warning_handler.handle_warning(
+                        LayoutWarning::MissingModalView {
+                            view: name.to_string(),
+                        },
+                    );
+                    false
+                },
+            })
+            .cloned()
+            .collect();
+
+        let shift_view = self.shift_view.as_ref()
+            .and_then(|name| match self.views.get_key_value(name) {
+                Some(view) => Some(view),
+                None => {
+                    warning_handler.handle_warning(
+                        LayoutWarning::MissingShiftView { view: name.clone() },
+                    );
+                    None
+                },
+            });
+
+        // Previews are labels of the shift view, by row and by button.
+        let mut build_view = |
+            name: &String,
+            view: &Vec<ButtonIds>,
+            previews: Option<&Vec<Vec<layout::Label>>>,
+        | {
+            let direction = self.view_directions.get(name)
+                .or(self.direction.as_ref());
+            let mirrored = direction == Some(&Direction::RightToLeft);
+            // Percentages are resolved against the view
+            // made of the buttons with absolute sizes.
+            let view_size = {
+                let row_sizes = view.iter().map(|row| {
+                    row.split_ascii_whitespace()
+                        .map(|name| find_outline(
+                            &self.buttons,
+                            &self.outlines,
+                            self.default_outline.as_ref(),
+                            name,
+                        ))
+                        .map(|outline| match outline {
+                            Some(outline) => (
+                                outline.width.absolute_part(),
+                                outline.height.absolute_part(),
+                            ),
+                            None => (1.0, 1.0),
+                        })
+                        .fold((-spacing.button, 0.0), |(width, height), (w, h)| {
+                            (width + w + spacing.button, f64::max(height, h))
+                        })
+                });
+                let (width, height) = row_sizes.fold(
+                    (0.0, -spacing.row),
+                    |(width, height), (w, h)| {
+                        (f64::max(width, w), height + h + spacing.row)
+                    },
+                );
+                layout::Size { width, height }
+            };
+            let sources = ButtonSources {
+                button_info: &self.buttons,
+                outlines: &self.outlines,
+                default_outline: self.default_outline.as_ref(),
+                view_size: &view_size,
+                kind,
+            };
+            // Within a view, buttons with the same name are the same,
+            // so each is created once and copied.
+            let mut created: HashMap<&str, layout::Button>
+                = HashMap::new();
+            let rows = view.iter().enumerate().map(|(row_index, row)| {
+                let mut buttons: Vec<_> = row.split_ascii_whitespace()
+                    .map(|name| {
+                        created.entry(name).or_insert_with(|| create_button(
+                            &sources,
+                            name,
+                            ButtonEffects {
+                                key: button_states_cache.get(name.into())
+                                    .expect("Button state not created")
+                                    .clone(),
+                                alternate: alternates.get(name).cloned(),
+                                long_press: long_presses.get(name).cloned(),
+                                edges: edges.get(name).cloned(),
+                            },
+                            &mut warning_handler,
+                        )).clone()
+                    })
+                    .collect();
+                if mirrored {
+                    buttons.reverse();
+                }
+                let row_previews = previews
+                    .and_then(|previews| previews.get(row_index));
+                if let Some(row_previews) = row_previews {
+                    for (button, label) in buttons.iter_mut().zip(row_previews) {
+                        button.preview_label = Some(label.clone());
+                    }
+                }
+                if fill_rows {
+                    let gaps = spacing.button
+                        * (buttons.len() as f64 - 1.0);
+                    let width = (view_size.width - gaps)
+                        / buttons.len() as f64;
+                    for button in &mut buttons {
+                        button.size.width = width;
+                    }
+                }
+                layout::Row::new(
+                    add_offsets(
+                        buttons.into_iter(),
+                        |button| button.size.width + spacing.button,
+                    ).collect()
+                )
+            });
+            let rows = add_offsets(
+                rows,
+                |row| row.get_size().height + spacing.row,
+            ).collect();
+            (
+                name.clone(),
+                layout::View::new(rows)
+            )
+        };
+
+        let mut views = Vec::new();
+        let previews = shift_view.map(|(name, view)| {
+            let (name, view) = build_view(name, view, None);
+            let labels = view.get_rows().iter()
+                .map(|(_offset, row)| {
+                    row.get_buttons().iter()
+                        .map(|(_x, button)| button.label.clone())
+                        .collect()
+                })
+                .collect();
+            views.push((name, view));
+            labels
+        });
+        for (name, view) in &self.views {
+            if Some(name) != shift_view.map(|(name, _view)| name) {
+                views.push(build_view(name, view, previews.as_ref()));
+            }
+        }
+
+        // Buttons may stick out of the view, but not be lost entirely.
+        for (name, view) in &views {
+            let size = view.get_size();
+            for (row_offset, row) in view.get_rows() {
+                for (x_offset, button) in row.get_buttons() {
+                    let bounds = button.get_bounds();
+                    let x = row_offset.x + x_offset + bounds.x;
+                    let y = row_offset.y + bounds.y;
+                    if x >= size.width || x + bounds.width <= 0.0
+                        || y >= size.height || y + bounds.height <= 0.0
+                    {
+                        warning_handler.handle_warning(
+                            LayoutWarning::ButtonOutsideView {
+                                button: button.name.to_string_lossy().into(),
+                                view: name.clone(),
+                            },
+                        );
+                    }
+                }
+            }
+        }
+
+        // Center views on the same point.
+        let total_size = layout::View::calculate_super_size(
+            views.iter().map(|(_name, view)| view).collect()
+        );
+        let mut views: HashMap<_, _>
+            = HashMap::from_iter(views.into_iter().map(|(name, view)| (
                 name,
                 (
                     layout::c::Point {
@@ -265,11 +2209,100 @@ impl Layout {
                     },
                     view,
                 ),
-            )))
+            )));
+
+        // Sticking into the margins is fine, but not past them.
+        let panel = layout::c::Bounds {
+            x: -self.margins.side,
+            y: -self.margins.top,
+            width: total_size.width + 2.0 * self.margins.side,
+            height: total_size.height + self.margins.top + self.margins.bottom,
+        };
+        if self.extend_edge_hits.unwrap_or(false) {
+            // Presses above the view are left for whatever is there.
+            for (offset, view) in views.values_mut() {
+                view.extend_hit_area(layout::c::Bounds {
+                    x: panel.x - offset.x,
+                    y: 0.0,
+                    width: panel.width,
+                    height: panel.y + panel.height - offset.y,
+                });
+            }
+        }
+
+        for (name, (offset, view)) in &views {
+            let overflow = content_bounds(view)
+                .map(|content| get_overflow(&panel, offset, &content))
+                .unwrap_or(0.0);
+            if overflow > 0.0 {
+                warning_handler.handle_warning(LayoutWarning::ViewOverflow {
+                    view: name.clone(),
+                    overflow,
+                });
+            }
+        }
+
+        let default_view = match self.default_view {
+            Some(view) if views.contains_key(&view) => view,
+            Some(view) => {
+                warning_handler.handle_warning(
+                    LayoutWarning::MissingDefaultView { view },
+                );
+                "base".into()
+            },
+            None => "base".into(),
+        };
+
+        for name in find_dead_end_views(&views, &default_view) {
+            warning_handler.handle_warning(LayoutWarning::DeadEndView {
+                view: name,
+                default_view: default_view.clone(),
+            });
+        }
+
+        let mut view_order: Vec<String> = Vec::new();
+        for name in self.view_order.into_iter().flatten() {
+            if !views.contains_key(&name) {
+                warning_handler.handle_warning(
+                    LayoutWarning::MissingOrderedView { view: name },
+                );
+            } else if !view_order.contains(&name) {
+                view_order.push(name);
+            }
+        }
+        let mut unlisted: Vec<String> = views.keys()
+            .filter(|name| !view_order.contains(name))
+            .cloned()
+            .collect();
+        unlisted.sort();
+        view_order.extend(unlisted);
+
+        let long_press_ms = match self.long_press_ms {
+            Some(ms) if !LONG_PRESS_RANGE_MS.contains(&ms) => {
+                warning_handler.handle_warning(
+                    LayoutWarning::LongPressOutOfRange { ms },
+                );
+                layout::DEFAULT_LONG_PRESS_MS
+            },
+            Some(ms) => ms,
+            None => layout::DEFAULT_LONG_PRESS_MS,
         };
 
         (
             Ok(layout::LayoutParseData {
+                clusters: find_clusters(&self.buttons, &views),
+                metadata,
+                outlines: outline_summary,
+                view_order,
+                default_view,
+                symbolmap: match has_keys {
+                    true => symbolmap,
+                    false => HashMap::new(),
+                },
+                shift_levels: match has_keys {
+                    true => shift_levels,
+                    false => HashMap::new(),
+                },
                 views: views,
                 keymaps: keymaps.into_iter().map(|keymap_str|
                     CString::new(keymap_str)
@@ -282,141 +2315,581 @@ impl Layout {
                     bottom: self.margins.bottom,
                     right: self.margins.side,
                 },
+                autocapitalize: self.autocapitalize.unwrap_or(false),
+                long_press_ms,
+                modal_views,
+                key_indices,
             }),
             warning_handler,
         )
     }
 }
 
-fn create_action<H: logging::Handler>(
-    button_info: &HashMap<String, ButtonMeta>,
-    name: &str,
-    view_names: Vec<&String>,
-    warning_handler: &mut H,
-) -> crate::action::Action {
-    let default_meta = ButtonMeta::default();
-    let symbol_meta = button_info.get(name)
-        .unwrap_or(&default_meta);
+/// FNV-1a, which unlike the standard hasher is fixed forever.
+/// Numbers are taken as little endian 64-bit,
+/// so that they hash the same on every platform.
+struct StableHasher(u64);
 
-    fn keysym_valid(name: &str) -> bool {
-        xkb::keysym_from_name(name, xkb::KEYSYM_NO_FLAGS) != xkb::KEY_NoSymbol
-    }
-    
-    enum SubmitData {
-        Action(Action),
-        Text(String),
-        Keysym(String),
-        Modifier(Modifier),
+impl StableHasher {
+    fn new() -> Self {
+        StableHasher(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn write_u8(&mut self, i: u8) { self.write_u64(i as u64) }
+    fn write_u16(&mut self, i: u16) { self.write_u64(i as u64) }
+    fn write_u32(&mut self, i: u32) { self.write_u64(i as u64) }
+    fn write_u64(&mut self, i: u64) { self.write(&i.to_le_bytes()) }
+    fn write_usize(&mut self, i: usize) { self.write_u64(i as u64) }
+    fn write_isize(&mut self, i: isize) { self.write_u64(i as u64) }
+}
+
+/// Returns the names of the fields of a struct
+/// which derives `Deserialize`.
+fn get_field_names<T: DeserializeOwned>() -> &'static [&'static str] {
+    /// Fails on everything, but remembers the fields of a struct
+    struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de, 'a> de::Deserializer<'de> for FieldNames<'a> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: de::Visitor<'de>>(self, _visitor: V)
+            -> Result<V::Value, Self::Error>
+        {
+            Err(de::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: de::Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(de::Error::custom("only looking for fields"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str
+            string bytes byte_buf option unit unit_struct newtype_struct seq
+            tuple tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldNames(&mut fields));
+    fields
+}
+
+/// Removes the entries of a mapping which aren't fields of `T`
+fn strip_fields<T: DeserializeOwned, H: WarningHandler>(
+    value: &mut serde_yaml::Value,
+    place: &str,
+    warning_handler: &mut H,
+) {
+    let fields = get_field_names::<T>();
+    if let serde_yaml::Value::Mapping(mapping) = value {
+        mapping.retain(|key, _value| {
+            let name = key.as_str().unwrap_or("");
+            let known = fields.contains(&name);
+            if !known {
+                warning_handler.handle_warning(LayoutWarning::UnknownField {
+                    field: name.into(),
+                    place: place.into(),
+                });
+            }
+            known
+        });
+    }
+}
+
+/// Removes all fields unknown to `Layout` and the structures inside it
+fn strip_unknown_fields<H: WarningHandler>(
+    layout: &mut serde_yaml::Value,
+    warning_handler: &mut H,
+) {
+    strip_fields::<Layout, _>(layout, "layout", warning_handler);
+    if let Some(margins) = layout.get_mut("margins") {
+        strip_fields::<Margins, _>(margins, "margins", warning_handler);
+    }
+
+    let mut strip_outline = |outline: &mut serde_yaml::Value, place: &str| {
+        strip_fields::<Outline, _>(outline, place, warning_handler);
+        if let Some(wide) = outline.get_mut("wide") {
+            strip_fields::<OutlineOverride, _>(wide, place, warning_handler);
+        }
+    };
+    if let Some(outline) = layout.get_mut("default_outline") {
+        strip_outline(outline, "default_outline");
+    }
+    if let Some(serde_yaml::Value::Mapping(outlines))
+        = layout.get_mut("outlines")
+    {
+        for (name, outline) in outlines.iter_mut() {
+            let place = format!("outline {}", name.as_str().unwrap_or(""));
+            strip_outline(outline, &place);
+        }
+    }
+
+    if let Some(spacing) = layout.get_mut("spacing") {
+        strip_fields::<Spacing, _>(spacing, "spacing", warning_handler);
+        if let Some(wide) = spacing.get_mut("wide") {
+            strip_fields::<SpacingOverride, _>(
+                wide,
+                "spacing",
+                warning_handler,
+            );
+        }
+    }
+
+    if let Some(serde_yaml::Value::Mapping(buttons))
+        = layout.get_mut("buttons")
+    {
+        for (name, button) in buttons.iter_mut() {
+            let place = format!("button {}", name.as_str().unwrap_or(""));
+            strip_fields::<ButtonMeta, _>(button, &place, warning_handler);
+            if let Some(alternate) = button.get_mut("alternate") {
+                strip_fields::<Alternate, _>(
+                    alternate,
+                    &place,
+                    warning_handler,
+                );
+            }
+        }
+    }
+}
+
+/// The area covered by the drawn buttons, relative to the view
+fn content_bounds(view: &layout::View) -> Option<layout::c::Bounds> {
+    get_buttons_bounds(view, |_button| true)
+}
+
+/// The area covered by the drawn buttons which pass the filter,
+/// relative to the view
+fn get_buttons_bounds<F: Fn(&layout::Button) -> bool>(
+    view: &layout::View,
+    filter: F,
+) -> Option<layout::c::Bounds> {
+    view.get_rows().iter()
+        .flat_map(|(row_offset, row)| {
+            row.get_buttons().iter()
+                .filter(|(_x_offset, button)| filter(button))
+                .map(move |(x_offset, button)| {
+                    let bounds = button.get_bounds();
+                    layout::c::Bounds {
+                        x: row_offset.x + x_offset + bounds.x,
+                        y: row_offset.y + bounds.y,
+                        ..bounds
+                    }
+                })
+        })
+        .reduce(|a, b| {
+            let x = f64::min(a.x, b.x);
+            let y = f64::min(a.y, b.y);
+            layout::c::Bounds {
+                x,
+                y,
+                width: f64::max(a.x + a.width, b.x + b.width) - x,
+                height: f64::max(a.y + a.height, b.y + b.height) - y,
+            }
+        })
+}
+
+/// Finds the area of each cluster in each view
+fn find_clusters(
+    button_info: &HashMap<String, ButtonMeta>,
+    views: &HashMap<String, (layout::c::Point, layout::View)>,
+) -> Vec<layout::Cluster> {
+    fn get_cluster<'a>(
+        button_info: &'a HashMap<String, ButtonMeta>,
+        button: &layout::Button,
+    ) -> Option<&'a str> {
+        let name = button.name.to_str().ok()?;
+        button_info.get(name)?.cluster.as_deref()
+    }
+
+    let mut clusters = Vec::new();
+    for (view_name, (_offset, view)) in views {
+        let names: HashSet<&str> = view.get_rows().iter()
+            .flat_map(|(_row_offset, row)| row.get_buttons())
+            .filter_map(|(_x_offset, button)| get_cluster(button_info, button))
+            .collect();
+        for name in names {
+            let bounds = get_buttons_bounds(view, |button| {
+                get_cluster(button_info, button) == Some(name)
+            });
+            if let Some(bounds) = bounds {
+                clusters.push(layout::Cluster {
+                    name: name.into(),
+                    view: view_name.clone(),
+                    bounds,
+                });
+            }
+        }
+    }
+    clusters.sort_by(|a, b| (&a.view, &a.name).cmp(&(&b.view, &b.name)));
+    clusters
+}
+
+/// Returns the longest distance by which `content`,
+/// placed at `offset`, sticks out of `outer`.
+fn get_overflow(
+    outer: &layout::c::Bounds,
+    offset: &layout::c::Point,
+    content: &layout::c::Bounds,
+) -> f64 {
+    let x = offset.x + content.x;
+    let y = offset.y + content.y;
+    [
+        outer.x - x,
+        outer.y - y,
+        (x + content.width) - (outer.x + outer.width),
+        (y + content.height) - (outer.y + outer.height),
+    ].into_iter().fold(0.0, f64::max)
+}
+
+/// The settings of the whole layout which affect the actions of buttons
+struct ActionOptions<'a> {
+    view_names: Vec<&'a String>,
+    /// Already checked to exist
+    default_view: &'a str,
+    /// Layouts are not trusted enough to call arbitrary methods by default
+    dbus_enabled: bool,
+    invalid_keysym: KeysymFallback,
+    /// Keysyms for text use names like "period" where they exist
+    named_keysyms: bool,
+}
+
+impl Default for ActionOptions<'_> {
+    fn default() -> Self {
+        ActionOptions {
+            view_names: Vec::new(),
+            default_view: "base",
+            dbus_enabled: false,
+            invalid_keysym: KeysymFallback::default(),
+            named_keysyms: false,
+        }
+    }
+}
+
+fn create_action<H: WarningHandler>(
+    button_info: &HashMap<String, ButtonMeta>,
+    name: &str,
+    options: &ActionOptions,
+    warning_handler: &mut H,
+) -> crate::action::Action {
+    let default_meta = ButtonMeta::default();
+    let symbol_meta = button_info.get(name)
+        .unwrap_or(&default_meta);
+    create_action_from_meta(symbol_meta, name, options, warning_handler)
+}
+
+fn keysym_valid(name: &str) -> bool {
+    xkb::keysym_from_name(name, xkb::KEYSYM_NO_FLAGS) != xkb::KEY_NoSymbol
+}
+
+/// The code point form of keysym names, like "U20AC"
+fn codepoint_keysym_name(codepoint: char) -> String {
+    format!("U{:04X}", codepoint as u32)
+}
+
+/// Returns the name of the keysym which types the character
+fn char_keysym_name(codepoint: char, named_keysyms: bool) -> String {
+    let name = match named_keysyms {
+        true => xkb::keysym_get_name(xkb::utf32_to_keysym(codepoint as u32)),
+        false => codepoint.to_string(),
+    };
+    match keysym_valid(&name) {
+        true => name,
+        false => codepoint_keysym_name(codepoint),
+    }
+}
+
+fn create_action_from_meta<H: WarningHandler>(
+    symbol_meta: &ButtonMeta,
+    name: &str,
+    options: &ActionOptions,
+    warning_handler: &mut H,
+) -> crate::action::Action {
+    let ActionOptions {
+        ref view_names, default_view,
+        dbus_enabled, invalid_keysym, named_keysyms,
+    } = *options;
+    enum SubmitData {
+        Action(Action),
+        Text(String),
+        Keysym(String),
+        Modifier(Modifier),
     }
     
+    warn_if_empty(name, "text", &symbol_meta.text, warning_handler);
+
+    if symbol_meta.on_press.is_some() || symbol_meta.on_release.is_some() {
+        if symbol_meta.action.is_some() || symbol_meta.keysym.is_some()
+            || symbol_meta.text.is_some() || symbol_meta.modifier.is_some()
+        {
+            warning_handler.handle_warning(
+                LayoutWarning::ConflictingFields { button: name.into() },
+            );
+        }
+        // The edges are built separately and do all the work.
+        return crate::action::Action::Submit { text: None, keys: Vec::new() };
+    }
+
     let submission = match (
         &symbol_meta.action,
         &symbol_meta.keysym,
-        &symbol_meta.text,
+        non_empty(&symbol_meta.text),
         &symbol_meta.modifier,
     ) {
         (Some(action), None, None, None) => SubmitData::Action(action.clone()),
+        // Probably left over from before the action was added
+        (Some(action), keysym, text, modifier) => {
+            let fields = [
+                ("keysym", keysym.is_some()),
+                ("text", text.is_some()),
+                ("modifier", modifier.is_some()),
+            ].iter()
+                .filter(|(_field, present)| *present)
+                .map(|(field, _present)| *field)
+                .collect();
+            warning_handler.handle_warning(
+                LayoutWarning::IgnoredFields { button: name.into(), fields },
+            );
+            SubmitData::Action(action.clone())
+        },
         (None, Some(keysym), None, None) => SubmitData::Keysym(keysym.clone()),
-        (None, None, Some(text), None) => SubmitData::Text(text.clone()),
+        (None, None, Some(text), None) => {
+            let known = shortcodes::get_name(text)
+                .map(|shortcode| shortcodes::find(shortcode).is_some());
+            if let Some(false) = known {
+                warning_handler.handle_warning(
+                    LayoutWarning::UnknownShortcode {
+                        button: name.into(),
+                        text: text.clone(),
+                    },
+                );
+            }
+            SubmitData::Text(
+                apply_case(&expand_shortcode(text), symbol_meta.uppercase)
+            )
+        },
         (None, None, None, Some(modifier)) => {
             SubmitData::Modifier(modifier.clone())
         },
-        (None, None, None, None) => SubmitData::Text(name.into()),
+        (None, None, None, None) => SubmitData::Text(
+            apply_case(name, symbol_meta.uppercase)
+        ),
         _ => {
-            warning_handler.handle(
-                logging::Level::Warning,
-                &format!(
-                    "Button {} has more than one of (action, keysym, text, modifier)",
-                    name,
-                ),
+            warning_handler.handle_warning(
+                LayoutWarning::ConflictingFields { button: name.into() },
             );
             SubmitData::Text("".into())
         },
     };
 
-    fn filter_view_name<H: logging::Handler>(
+    let filter_view_name = |
         button_name: &str,
         view_name: String,
         view_names: &Vec<&String>,
         warning_handler: &mut H,
-    ) -> String {
+    | -> String {
         if view_names.contains(&&view_name) {
             view_name
         } else {
-            warning_handler.handle(
-                logging::Level::Warning,
-                &format!("Button {} switches to missing view {}",
-                    button_name,
-                    view_name,
-                ),
-            );
-            "base".into()
+            warning_handler.handle_warning(LayoutWarning::MissingView {
+                button: button_name.into(),
+                view: view_name,
+            });
+            default_view.into()
         }
-    }
+    };
 
     match submission {
         SubmitData::Action(
             Action::SetView(view_name)
         ) => crate::action::Action::SetView(
             filter_view_name(
-                name, view_name.clone(), &view_names,
+                name, view_name.clone(), view_names,
                 warning_handler,
             )
         ),
+        SubmitData::Action(Action::ToggleView { first, second }) => {
+            crate::action::Action::ToggleView {
+                first: filter_view_name(
+                    name, first.view, view_names,
+                    warning_handler,
+                ),
+                second: filter_view_name(
+                    name, second.view, view_names,
+                    warning_handler,
+                ),
+            }
+        },
         SubmitData::Action(Action::Locking {
             lock_view, unlock_view,
             pops,
             looks_locked_from,
-        }) => crate::action::Action::LockView {
-            lock: filter_view_name(
-                name,
-                lock_view.clone(),
-                &view_names,
-                warning_handler,
-            ),
-            unlock: filter_view_name(
-                name,
-                unlock_view.clone(),
-                &view_names,
-                warning_handler,
-            ),
-            latches: pops.unwrap_or(true),
-            looks_locked_from,
+            double_tap_lock,
+        }) => {
+            if lock_view == default_view {
+                warning_handler.handle_warning(
+                    LayoutWarning::LockingIntoDefault { button: name.into() },
+                );
+            }
+            crate::action::Action::LockView {
+                lock: filter_view_name(
+                    name,
+                    lock_view.clone(),
+                    view_names,
+                    warning_handler,
+                ),
+                unlock: filter_view_name(
+                    name,
+                    unlock_view.clone(),
+                    view_names,
+                    warning_handler,
+                ),
+                latches: pops.unwrap_or(true),
+                looks_locked_from,
+                double_tap_lock: double_tap_lock.unwrap_or(false),
+            }
+        },
+        SubmitData::Action(Action::Back) => action::Action::PreviousView,
+        SubmitData::Action(Action::ShowPrefs { target }) => {
+            action::Action::ShowPreferences(match target {
+                None => action::PrefsTarget::Chooser,
+                Some(target) if target == "chooser"
+                    => action::PrefsTarget::Chooser,
+                Some(target) if target == "settings"
+                    => action::PrefsTarget::Settings,
+                Some(target) => {
+                    warning_handler.handle_warning(
+                        LayoutWarning::UnknownPrefsTarget {
+                            button: name.into(),
+                            target,
+                        },
+                    );
+                    action::PrefsTarget::Chooser
+                },
+            })
         },
-        SubmitData::Action(
-            Action::ShowPrefs
-        ) => crate::action::Action::ShowPreferences,
         SubmitData::Action(Action::Erase) => action::Action::Erase,
-        SubmitData::Keysym(keysym) => crate::action::Action::Submit {
-            text: None,
-            keys: vec!(crate::action::KeySym(
-                match keysym_valid(keysym.as_str()) {
-                    true => keysym.clone(),
-                    false => {
-                        warning_handler.handle(
-                            logging::Level::Warning,
-                            &format!(
-                                "Keysym name invalid: {}",
-                                keysym,
-                            ),
-                        );
-                        "space".into() // placeholder
+        SubmitData::Action(Action::Clear) => action::Action::ClearPending,
+        SubmitData::Action(Action::Enter) => action::Action::Enter,
+        SubmitData::Action(Action::Toggle(flag)) => {
+            action::Action::ToggleFlag(flag)
+        },
+        SubmitData::Action(Action::DBus { service, path, method }) => {
+            let warning = match (dbus_enabled, method.rsplit_once('.')) {
+                (true, Some((interface, method))) => {
+                    return action::Action::DBus {
+                        service,
+                        path,
+                        interface: interface.into(),
+                        method: method.into(),
+                    };
+                },
+                (true, None) => LayoutWarning::DBusWithoutInterface {
+                    button: name.into(),
+                    method,
+                },
+                (false, _) => LayoutWarning::DBusDisabled {
+                    button: name.into(),
+                },
+            };
+            warning_handler.handle_warning(warning);
+            action::Action::Submit {
+                text: None,
+                keys: Vec::new(),
+            }
+        },
+        SubmitData::Action(Action::Clipboard(op)) => match op.as_str() {
+            "cut" => action::Action::Clipboard(action::ClipboardOp::Cut),
+            "copy" => action::Action::Clipboard(action::ClipboardOp::Copy),
+            "paste" => action::Action::Clipboard(action::ClipboardOp::Paste),
+            "select_all" => action::Action::Clipboard(
+                action::ClipboardOp::SelectAll,
+            ),
+            _ => {
+                warning_handler.handle_warning(
+                    LayoutWarning::UnknownClipboardOp {
+                        button: name.into(),
+                        op,
                     },
+                );
+                action::Action::Submit {
+                    text: None,
+                    keys: Vec::new(),
                 }
-            )),
+            },
+        },
+        SubmitData::Action(Action::DeadKey { keysym }) => {
+            let warning = match (
+                keysym_valid(&keysym),
+                keysym.starts_with("dead_"),
+            ) {
+                (true, true) => {
+                    return action::Action::Submit {
+                        text: None,
+                        keys: vec![action::KeySym(keysym)],
+                    };
+                },
+                (true, false) => LayoutWarning::NotDeadKey {
+                    button: name.into(),
+                    keysym,
+                },
+                (false, _) => LayoutWarning::InvalidKeysym { name: keysym },
+            };
+            warning_handler.handle_warning(warning);
+            action::Action::Submit {
+                text: None,
+                keys: Vec::new(),
+            }
+        },
+        SubmitData::Keysym(keysym) => crate::action::Action::Submit {
+            text: None,
+            keys: match keysym_valid(keysym.as_str()) {
+                true => Some(keysym),
+                false => {
+                    warning_handler.handle_warning(
+                        LayoutWarning::InvalidKeysym {
+                            name: keysym.clone(),
+                        },
+                    );
+                    match invalid_keysym {
+                        KeysymFallback::Nothing => None,
+                        KeysymFallback::Space => Some("space".into()),
+                        KeysymFallback::Replacement => Some("UFFFD".into()),
+                    }
+                },
+            }.map(crate::action::KeySym).into_iter().collect(),
         },
         SubmitData::Text(text) => crate::action::Action::Submit {
-            text: CString::new(text.clone()).or_warn(
-                warning_handler,
-                logging::Problem::Warning,
-                &format!("Text {} contains problems", text),
-            ),
+            text: match CString::new(text.clone()) {
+                Ok(text) => Some(text),
+                Err(e) => {
+                    warning_handler.handle_warning(LayoutWarning::InvalidText {
+                        text: text.clone(),
+                        error: e.to_string(),
+                    });
+                    None
+                },
+            },
             keys: text.chars().map(|codepoint| {
-                let codepoint_string = codepoint.to_string();
-                crate::action::KeySym(match keysym_valid(codepoint_string.as_str()) {
-                    true => codepoint_string,
-                    false => format!("U{:04X}", codepoint as u32),
-                })
+                crate::action::KeySym(char_keysym_name(codepoint, named_keysyms))
             }).collect(),
         },
         SubmitData::Modifier(modifier) => match modifier {
@@ -430,11 +2903,10 @@ fn create_action<H: logging::Handler>(
                 action::Modifier::Mod4,
             ),
             unsupported_modifier => {
-                warning_handler.handle(
-                    logging::Level::Bug,
-                    &format!(
-                        "Modifier {:?} unsupported", unsupported_modifier,
-                    ),
+                warning_handler.handle_warning(
+                    LayoutWarning::UnsupportedModifier {
+                        modifier: format!("{:?}", unsupported_modifier),
+                    },
                 );
                 action::Action::Submit {
                     text: None,
@@ -445,15 +2917,105 @@ fn create_action<H: logging::Handler>(
     }
 }
 
+/// Treats an empty string as not provided,
+/// so that the next candidate is used instead.
+fn non_empty(value: &Option<String>) -> Option<&String> {
+    value.as_ref().filter(|value| !value.is_empty())
+}
+
+fn warn_if_empty<H: WarningHandler>(
+    button_name: &str,
+    field: &'static str,
+    value: &Option<String>,
+    warning_handler: &mut H,
+) {
+    if let Some("") = value.as_deref() {
+        warning_handler.handle_warning(LayoutWarning::EmptyField {
+            button: button_name.into(),
+            field,
+        });
+    }
+}
+
+/// Turns a known shortcode like ":smile:" into its emoji.
+/// Any other text stays the same.
+fn expand_shortcode(text: &str) -> String {
+    shortcodes::get_name(text)
+        .and_then(shortcodes::find)
+        .map(String::from)
+        .unwrap_or_else(|| text.into())
+}
+
+fn apply_case(text: &str, uppercase: Option<bool>) -> String {
+    match uppercase {
+        Some(true) => text.to_uppercase(),
+        _ => text.into(),
+    }
+}
+
+/// Turns ids like "Shift_L" or "caps_lock" into labels
+/// like "Shift" or "Caps Lock".
+/// Other names, like "a" or "_", are not ids.
+fn label_from_id(name: &str) -> Option<String> {
+    let stem = name.strip_suffix("_L")
+        .or(name.strip_suffix("_R"))
+        .unwrap_or(name);
+    let words: Vec<&str> = stem.split('_').collect();
+    if stem == name && words.len() < 2 {
+        return None;
+    }
+    let is_word = |word: &&str| {
+        !word.is_empty() && word.chars().all(char::is_alphanumeric)
+    };
+    if !words.iter().all(is_word) {
+        return None;
+    }
+    let title_case = |word: &str| {
+        let mut chars = word.chars();
+        chars.next()
+            .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+            .unwrap_or_default()
+    };
+    Some(
+        words.into_iter()
+            .map(title_case)
+            .collect::<Vec<_>>()
+            .join(" ")
+    )
+}
+
+/// Longer labels don't fit on a button anyway
+const MAX_LABEL_CHARS: usize = 64;
+
+/// What all the buttons of a view are created from
+struct ButtonSources<'a> {
+    button_info: &'a HashMap<String, ButtonMeta>,
+    outlines: &'a HashMap<String, Outline>,
+    default_outline: Option<&'a Outline>,
+    view_size: &'a layout::Size,
+    kind: layout::ArrangementKind,
+}
+
+/// What a button does, prepared before the button is created
+struct ButtonEffects {
+    key: Key,
+    alternate: Option<layout::Alternate>,
+    long_press: Option<layout::Effect>,
+    edges: Option<layout::Edges>,
+}
+
 /// TODO: Since this will receive user-provided data,
 /// all .expect() on them should be turned into soft fails
-fn create_button<H: logging::Handler>(
-    button_info: &HashMap<String, ButtonMeta>,
-    outlines: &HashMap<String, Outline>,
+fn create_button<H: WarningHandler>(
+    sources: &ButtonSources,
     name: &str,
-    data: Key,
+    effects: ButtonEffects,
     warning_handler: &mut H,
 ) -> crate::layout::Button {
+    let ButtonSources {
+        button_info, outlines, default_outline, view_size, kind,
+    } = *sources;
+    let ButtonEffects { key: data, alternate, long_press, edges } = effects;
     let cname = CString::new(name.clone())
         .expect("Bad name");
     // don't remove, because multiple buttons with the same name are allowed
@@ -461,177 +3023,2418 @@ fn create_button<H: logging::Handler>(
     let button_meta = button_info.get(name)
         .unwrap_or(&default_meta);
 
+    // Empty text was already reported when creating the action.
+    warn_if_empty(name, "label", &button_meta.label, warning_handler);
+    warn_if_empty(name, "label_wide", &button_meta.label_wide, warning_handler);
+    warn_if_empty(name, "icon", &button_meta.icon, warning_handler);
+    warn_if_empty(name, "a11y_label", &button_meta.a11y_label, warning_handler);
+
+    let label = match kind {
+        layout::ArrangementKind::Wide => non_empty(&button_meta.label_wide)
+            .or(non_empty(&button_meta.label)),
+        layout::ArrangementKind::Base => non_empty(&button_meta.label),
+    };
+
+    let label = label.map(|label| {
+        match label.char_indices().nth(MAX_LABEL_CHARS) {
+            Some((end, _)) => {
+                warning_handler.handle_warning(
+                    LayoutWarning::LongLabel { button: name.into() },
+                );
+                &label[..end]
+            },
+            None => label.as_str(),
+        }
+    });
+
     // TODO: move conversion to the C/Rust boundary
-    let label = if let Some(label) = &button_meta.label {
-        crate::layout::Label::Text(CString::new(label.as_str())
+    let label = if let Some(label) = label {
+        crate::layout::Label::Text(CString::new(label)
             .expect("Bad label"))
-    } else if let Some(icon) = &button_meta.icon {
+    } else if let Some(icon) = non_empty(&button_meta.icon) {
         crate::layout::Label::IconName(CString::new(icon.as_str())
             .expect("Bad icon"))
-    } else if let Some(text) = &button_meta.text {
+    } else if let Some(text) = non_empty(&button_meta.text) {
+        let text = apply_case(&expand_shortcode(text), button_meta.uppercase);
         crate::layout::Label::Text(
             CString::new(text.as_str())
-                .or_warn(
-                    warning_handler,
-                    logging::Problem::Warning,
-                    &format!("Text {} is invalid", text),
-                ).unwrap_or_else(|| CString::new("").unwrap())
+                .unwrap_or_else(|e| {
+                    warning_handler.handle_warning(LayoutWarning::InvalidLabel {
+                        text: text.clone(),
+                        error: e.to_string(),
+                    });
+                    CString::new("").unwrap()
+                })
         )
     } else {
-        crate::layout::Label::Text(cname.clone())
+        match button_meta.uppercase {
+            Some(true) => crate::layout::Label::Text(
+                CString::new(apply_case(name, button_meta.uppercase))
+                    .expect("Bad name")
+            ),
+            _ => match label_from_id(name) {
+                Some(label) => {
+                    warning_handler.handle_warning(LayoutWarning::LabelFromId {
+                        button: name.into(),
+                        label: label.clone(),
+                    });
+                    crate::layout::Label::Text(
+                        CString::new(label).expect("Bad name")
+                    )
+                },
+                None => crate::layout::Label::Text(cname.clone()),
+            },
+        }
     };
 
-    let outline_name = match &button_meta.outline {
-        Some(outline) => {
-            if outlines.contains_key(outline) {
-                outline.clone()
-            } else {
-                warning_handler.handle(
-                    logging::Level::Warning,
-                    &format!("Outline named {} does not exist! Using default for button {}", outline, name)
-                );
-                "default".into()
-            }
-        }
-        None => "default".into(),
+    // The label shows where the button leads, which depends on the view.
+    let (label, toggled_label) = match &button_meta.action {
+        Some(Action::ToggleView { first, second }) => {
+            let mut make_label = |text: &String| layout::Label::Text(
+                CString::new(text.as_str())
+                    .unwrap_or_else(|e| {
+                        warning_handler.handle_warning(
+                            LayoutWarning::InvalidLabel {
+                                text: text.clone(),
+                                error: e.to_string(),
+                            },
+                        );
+                        CString::new("").unwrap()
+                    })
+            );
+            (make_label(&first.label), Some(make_label(&second.label)))
+        },
+        _ => (label, None),
     };
 
-    let outline = outlines.get(&outline_name)
-        .map(|outline| (*outline).clone())
-        .or_warn(
-            warning_handler,
-            logging::Problem::Warning,
-            "No default outline defined! Using 1x1!",
-        ).unwrap_or(Outline { width: 1f64, height: 1f64 });
+    let a11y_label = match non_empty(&button_meta.a11y_label) {
+        Some(a11y_label) => CString::new(a11y_label.as_str())
+            .unwrap_or_else(|e| {
+                warning_handler.handle_warning(LayoutWarning::InvalidLabel {
+                    text: a11y_label.clone(),
+                    error: e.to_string(),
+                });
+                cname.clone()
+            }),
+        None => match &label {
+            layout::Label::Text(text) => text.clone(),
+            // Icon names don't describe the button to a person
+            layout::Label::IconName(_) => non_empty(&button_meta.text)
+                .map(|text| {
+                    apply_case(&expand_shortcode(text), button_meta.uppercase)
+                })
+                .and_then(|text| CString::new(text).ok())
+                .unwrap_or_else(|| cname.clone()),
+        },
+    };
+
+    let outline_name = get_outline_name(button_meta, outlines);
+    if let Some(outline) = &button_meta.outline {
+        if outline != outline_name {
+            warning_handler.handle_warning(LayoutWarning::MissingOutline {
+                button: name.into(),
+                outline: outline.clone(),
+            });
+        }
+    }
+
+    let outline = find_outline(button_info, outlines, default_outline, name)
+        .cloned()
+        .unwrap_or_else(|| {
+            warning_handler.handle_warning(LayoutWarning::NoDefaultOutline);
+            Outline {
+                width: Length::Absolute(1f64),
+                height: Length::Absolute(1f64),
+                x: 0f64,
+                y: 0f64,
+                wide: None,
+            }
+        });
+
+    let repeat = layout::Repeat {
+        delay_ms: button_meta.repeat_delay_ms,
+        interval_ms: match button_meta.repeat_interval_ms {
+            Some(0) => {
+                warning_handler.handle_warning(
+                    LayoutWarning::NonPositiveRepeatInterval {
+                        button: name.into(),
+                    },
+                );
+                None
+            },
+            other => other,
+        },
+    };
 
     layout::Button {
         name: cname,
         outline_name: CString::new(outline_name).expect("Bad outline"),
         // TODO: do layout before creating buttons
         size: layout::Size {
-            width: outline.width,
-            height: outline.height,
+            width: outline.width.resolve(view_size.width),
+            height: outline.height.resolve(view_size.height),
         },
+        offset: layout::c::Point { x: outline.x, y: outline.y },
         label: label,
+        toggled_label,
+        preview_label: None,
+        a11y_label,
+        repeat,
+        debounce_ms: button_meta.debounce_ms,
+        silent: button_meta.silent.unwrap_or(false),
+        only_in_empty_field: button_meta.only_in_empty_field.unwrap_or(false),
+        // Other actions decide about views on their own
+        keep_view: button_meta.keep_view.unwrap_or(false)
+            && matches!(data.action, crate::action::Action::Submit { .. }),
+        extra: button_meta.extra.clone().unwrap_or_default(),
+        ime_aware: button_meta.ime_aware.unwrap_or(false),
+        disabled: button_meta.disabled.unwrap_or(false),
         action: data.action,
         keycodes: data.keycodes,
+        alternate,
+        long_press,
+        edges,
     }
 }
 
-fn extract_symbol_names<'a>(actions: &'a [(&str, action::Action)])
-    -> impl Iterator<Item=String> + 'a
-{
-    actions.iter()
-        .filter_map(|(_name, act)| {
-            match act {
-                action::Action::Submit {
-                    text: _, keys,
-                } => Some(keys.clone()),
-                action::Action::Erase => Some(vec!(action::KeySym("BackSpace".into()))),
-                _ => None,
-            }
-        })
-        .flatten()
+/// Falls back to "default" if the named outline doesn't exist
+fn get_outline_name<'a>(
+    button_meta: &'a ButtonMeta,
+    outlines: &HashMap<String, Outline>,
+) -> &'a str {
+    match &button_meta.outline {
+        Some(outline) if outlines.contains_key(outline) => outline.as_str(),
+        _ => "default",
+    }
+}
+
+fn find_outline<'a>(
+    button_info: &HashMap<String, ButtonMeta>,
+    outlines: &'a HashMap<String, Outline>,
+    default_outline: Option<&'a Outline>,
+    name: &str,
+) -> Option<&'a Outline> {
+    let default_meta = ButtonMeta::default();
+    let button_meta = button_info.get(name)
+        .unwrap_or(&default_meta);
+    match get_outline_name(button_meta, outlines) {
+        "default" => outlines.get("default").or(default_outline),
+        outline_name => outlines.get(outline_name),
+    }
+}
+
+/// Keysyms which the action needs in the keymap
+fn symbol_names(act: &action::Action) -> Vec<action::KeySym> {
+    match act {
+        action::Action::Submit {
+            text: _, keys,
+        } => keys.clone(),
+        action::Action::Erase => vec!(action::KeySym("BackSpace".into())),
+        action::Action::Enter => vec!(action::KeySym("Return".into())),
+        action::Action::Clipboard(op) => vec!(op.keysym()),
+        _ => Vec::new(),
+    }
+}
+
+fn extract_symbol_names<'a>(actions: &'a [(&str, action::Action)])
+    -> impl Iterator<Item=String> + 'a
+{
+    actions.iter()
+        .flat_map(|(_name, act)| symbol_names(act))
         .map(|named_keysym| named_keysym.0)
 }
 
+/// Returns the views which buttons lead to from the default view,
+/// but which can't be left for the default view,
+/// neither directly, nor through other views. Sorted by name.
+/// Only buttons which can be seen and pressed count.
+fn find_dead_end_views(
+    views: &HashMap<String, (layout::c::Point, layout::View)>,
+    default_view: &str,
+) -> Vec<String> {
+    let mut targets: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut escaped: HashSet<&str> = HashSet::new();
+    escaped.insert(default_view);
+    for (name, (_offset, view)) in views {
+        let size = view.get_size();
+        let view_targets = targets.entry(name.as_str()).or_default();
+        for (row_offset, row) in view.get_rows() {
+            for (x_offset, button) in row.get_buttons() {
+                let bounds = button.get_bounds();
+                let x = row_offset.x + x_offset + bounds.x;
+                let y = row_offset.y + bounds.y;
+                let pressable = bounds.width > 0.0 && bounds.height > 0.0
+                    && x < size.width && x + bounds.width > 0.0
+                    && y < size.height && y + bounds.height > 0.0;
+                if !pressable {
+                    continue;
+                }
+                let edges = button.edges.iter()
+                    .flat_map(|edges| edges.on_press.iter().chain(&edges.on_release))
+                    .map(|effect| &effect.action);
+                for action in Some(&button.action).into_iter().chain(edges) {
+                    match action {
+                        action::Action::SetView(view) => {
+                            view_targets.push(view.as_str());
+                        },
+                        action::Action::ToggleView { first, second } => {
+                            view_targets.push(first.as_str());
+                            view_targets.push(second.as_str());
+                        },
+                        action::Action::LockView { lock, unlock, .. } => {
+                            view_targets.push(lock.as_str());
+                            view_targets.push(unlock.as_str());
+                        },
+                        // Leads back the way the user came
+                        action::Action::PreviousView => {
+                            escaped.insert(name.as_str());
+                        },
+                        _ => {},
+                    }
+                }
+            }
+        }
+    }
+
+    loop {
+        let newly_escaped: Vec<&str> = targets.iter()
+            .filter(|(name, _targets)| !escaped.contains(*name))
+            .filter(|(_name, targets)| {
+                targets.iter().any(|target| escaped.contains(target))
+            })
+            .map(|(name, _targets)| *name)
+            .collect();
+        if newly_escaped.is_empty() {
+            break;
+        }
+        escaped.extend(newly_escaped);
+    }
+
+    // Views which can't be entered can't trap anyone.
+    let mut reached: HashSet<&str> = HashSet::new();
+    let mut to_visit = vec![default_view];
+    while let Some(name) = to_visit.pop() {
+        if reached.insert(name) {
+            to_visit.extend(targets.get(name).into_iter().flatten());
+        }
+    }
+
+    let mut dead_ends: Vec<String> = views.keys()
+        .filter(|name| reached.contains(name.as_str()))
+        .filter(|name| !escaped.contains(name.as_str()))
+        .cloned()
+        .collect();
+    dead_ends.sort();
+    dead_ends
+}
+
+/// Returns (keysym, button, other button) for keysyms emitted
+/// by more than one button, where the buttons submit different text.
+/// Each keysym is paired with the first button in name order.
+fn find_shared_keysyms<'a>(actions: &'a [(&str, action::Action)])
+    -> Vec<(&'a str, &'a str, &'a str)>
+{
+    let mut actions: Vec<_> = actions.iter().collect();
+    actions.sort_by_key(|(name, _)| *name);
+
+    let mut first_use: HashMap<&str, (&str, &CString)> = HashMap::new();
+    let mut shared = Vec::new();
+    for (name, act) in actions {
+        if let action::Action::Submit { text: Some(text), keys } = act {
+            for key in keys {
+                match first_use.get(key.0.as_str()) {
+                    Some((first_name, first_text)) => {
+                        if *first_text != text {
+                            shared.push((key.0.as_str(), *first_name, *name));
+                        }
+                    },
+                    None => {
+                        first_use.insert(key.0.as_str(), (*name, text));
+                    },
+                }
+            }
+        }
+    }
+    shared
+}
+
+/// Returns (outline, other outline) for outlines with the same definition.
+/// Each one is paired with the first identical one in name order.
+fn find_identical_outlines(outlines: &HashMap<String, Outline>)
+    -> Vec<(&str, &str)>
+{
+    let mut outlines: Vec<_> = outlines.iter().collect();
+    outlines.sort_by_key(|(name, _)| *name);
+
+    let mut identical = Vec::new();
+    for (index, (name, outline)) in outlines.iter().enumerate() {
+        let first = outlines[..index].iter()
+            .find(|(_name, other)| other == outline);
+        if let Some((first, _)) = first {
+            identical.push((first.as_str(), name.as_str()));
+        }
+    }
+    identical
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    use std::env;
+    
+    use crate::logging::ProblemPanic;
+
+    /// Keeps only the structured warnings
+    struct CollectWarnings(Vec<LayoutWarning>);
+
+    impl logging::Handler for CollectWarnings {
+        fn handle(&mut self, _level: logging::Level, _message: &str) {}
+    }
+
+    impl WarningHandler for CollectWarnings {
+        fn handle_warning(&mut self, warning: LayoutWarning) {
+            self.0.push(warning)
+        }
+    }
+
+    /// Keeps only the progress reports
+    struct CollectProgress(Vec<(BuildPhase, f64)>);
+
+    impl logging::Handler for CollectProgress {
+        fn handle(&mut self, _level: logging::Level, _message: &str) {}
+    }
+
+    impl WarningHandler for CollectProgress {
+        fn report_progress(&mut self, phase: BuildPhase, fraction: f64) {
+            self.0.push((phase, fraction))
+        }
+    }
+
+    fn path_from_root(file: &'static str) -> PathBuf {
+        let source_dir = env::var("SOURCE_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|e| {
+                if let env::VarError::NotPresent = e {
+                    let this_file = file!();
+                    PathBuf::from(this_file)
+                        .parent().unwrap()
+                        .parent().unwrap()
+                        .into()
+                } else {
+                    panic!("{:?}", e);
+                }
+            });
+        source_dir.join(file)
+    }
+
+    #[test]
+    fn test_parse_path() {
+        assert_eq!(
+            Layout::from_file(path_from_root("tests/layout.yaml")).unwrap(),
+            Layout {
+                margins: Margins { top: 0f64, bottom: 0f64, side: 0f64 },
+                views: hashmap!(
+                    "base".into() => vec!("test".into()),
+                ),
+                buttons: hashmap!{
+                    "test".into() => ButtonMeta {
+                        label: Some("test".into()),
+                        ..ButtonMeta::default()
+                    }
+                },
+                outlines: hashmap!{
+                    "default".into() => Outline {
+                        width: Length::Absolute(0f64), height: Length::Absolute(0f64), x: 0f64, y: 0f64,
+                        wide: None,
+                    },
+                },
+                ..Layout::default()
+            }
+        );
+    }
+
+    /// Check if the default protection works
+    #[test]
+    fn test_empty_views() {
+        let out = Layout::from_file(path_from_root("tests/layout2.yaml"));
+        match out {
+            Ok(_) => assert!(false, "Data mistakenly accepted"),
+            Err(e) => {
+                let mut handled = false;
+                if let Error::Yaml(ye) = &e {
+                    handled = ye.to_string()
+                        .starts_with("missing field `views`");
+                };
+                if !handled {
+                    println!("Unexpected error {:?}", e);
+                    assert!(false)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_extra_field() {
+        let out = Layout::from_file(path_from_root("tests/layout3.yaml"));
+        match out {
+            Ok(_) => assert!(false, "Data mistakenly accepted"),
+            Err(e) => {
+                let mut handled = false;
+                if let Error::Yaml(ye) = &e {
+                    handled = ye.to_string()
+                        .starts_with("unknown field `bad_field`");
+                };
+                if !handled {
+                    println!("Unexpected error {:?}", e);
+                    assert!(false)
+                }
+            }
+        }
+    }
+    
+    /// Layouts can be put together without YAML
+    #[test]
+    fn test_layout_in_memory() {
+        let layout = Layout {
+            views: hashmap!{
+                "base".into() => vec!["a BackSpace".into()],
+            },
+            buttons: hashmap!{
+                "BackSpace".into() => ButtonMeta {
+                    action: Some(Action::Erase),
+                    ..ButtonMeta::default()
+                },
+            },
+            outlines: hashmap!{
+                "default".into() => Outline {
+                    width: Length::Absolute(2.0), height: Length::Absolute(1.0), x: 0.0, y: 0.0,
+                    wide: None,
+                },
+            },
+            ..Layout::default()
+        };
+        let out = layout.build(layout::ArrangementKind::Base, ProblemPanic).0.unwrap();
+        let buttons = out.views["base"].1
+            .get_rows()[0].1
+            .get_buttons();
+        assert_eq!(buttons.len(), 2);
+        assert_eq!(buttons[1].0, 2.0);
+        assert_eq!(buttons[1].1.action, crate::action::Action::Erase);
+    }
+
+    fn make_offset_layout(x: f64) -> Layout {
+        Layout {
+            views: hashmap!{
+                "base".into() => vec!["a b".into()],
+            },
+            buttons: hashmap!{
+                "a".into() => ButtonMeta {
+                    outline: Some("handle".into()),
+                    ..ButtonMeta::default()
+                },
+            },
+            outlines: hashmap!{
+                "default".into() => Outline {
+                    width: Length::Absolute(1.0), height: Length::Absolute(1.0), x: 0.0, y: 0.0,
+                    wide: None,
+                },
+                "handle".into() => Outline {
+                    width: Length::Absolute(1.0), height: Length::Absolute(1.0), x, y: 0.0,
+                    wide: None,
+                },
+            },
+            ..Layout::default()
+        }
+    }
+
+    /// Buttons partially outside the view are fine,
+    /// as long as they stay inside the margins
+    #[test]
+    fn test_layout_negative_offset() {
+        let (out, handler) = make_offset_layout(-0.5)
+            .build(layout::ArrangementKind::Base, CollectWarnings(Vec::new()));
+        assert_eq!(
+            handler.0,
+            vec![LayoutWarning::ViewOverflow {
+                view: "base".into(),
+                overflow: 0.5,
+            }],
+        );
+        let out = out.unwrap();
+        let button = &out.views["base"].1
+            .get_rows()[0].1
+            .get_buttons()[0].1;
+        assert_eq!(
+            button.get_bounds(),
+            layout::c::Bounds { x: -0.5, y: 0.0, width: 1.0, height: 1.0 },
+        );
+    }
+
+    #[test]
+    fn test_layout_outside_view() {
+        let (out, handler) = make_offset_layout(-1.5)
+            .build(layout::ArrangementKind::Base, CollectWarnings(Vec::new()));
+        assert!(out.is_ok());
+        assert_eq!(
+            handler.0,
+            vec![
+                LayoutWarning::ButtonOutsideView {
+                    button: "a".into(),
+                    view: "base".into(),
+                },
+                LayoutWarning::ViewOverflow {
+                    view: "base".into(),
+                    overflow: 1.5,
+                },
+            ],
+        );
+    }
+
+    /// The root default outline replaces a missing "default" entry
+    #[test]
+    fn test_layout_default_outline() {
+        let out = Layout::from_file(path_from_root("tests/layout_default_outline.yaml"))
+            .unwrap()
+            .build(layout::ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        assert_eq!(
+            out.views["base"].1
+                .get_rows()[0].1
+                .get_buttons()[0].1
+                .size,
+            layout::Size { width: 2.0, height: 3.0 },
+        );
+    }
+
+    #[test]
+    fn test_layout_no_outlines() {
+        let make_layout = |missing_outlines| Layout {
+            views: hashmap!{
+                "base".into() => vec!["a b".into()],
+            },
+            missing_outlines,
+            ..Default::default()
+        };
+
+        let (out, warnings) = make_layout(None)
+            .build(layout::ArrangementKind::Base, CollectWarnings(Vec::new()));
+        let out = out.unwrap();
+        assert_eq!(warnings.0, vec![LayoutWarning::NoOutlines]);
+        assert_eq!(
+            out.views["base"].1
+                .get_rows()[0].1
+                .get_buttons()[1].1
+                .size,
+            layout::Size {
+                width: STANDARD_OUTLINE.0,
+                height: STANDARD_OUTLINE.1,
+            },
+        );
+
+        let out = make_layout(Some(MissingOutlines::Error))
+            .build(layout::ArrangementKind::Base, ProblemPanic).0;
+        assert!(matches!(out, Err(FormattingError::NoOutlines)));
+    }
+
+    #[test]
+    fn test_empty_label() {
+        let layout = Layout {
+            views: hashmap!{
+                "base".into() => vec!["a".into()],
+            },
+            buttons: hashmap!{
+                "a".into() => ButtonMeta {
+                    label: Some("".into()),
+                    ..ButtonMeta::default()
+                },
+            },
+            default_outline: Some(Outline {
+                width: Length::Absolute(1.0), height: Length::Absolute(1.0), x: 0.0, y: 0.0,
+                wide: None,
+            }),
+            ..Layout::default()
+        };
+        let (out, warnings) = layout.build(
+            layout::ArrangementKind::Base,
+            CollectWarnings(Vec::new()),
+        );
+        let out = out.unwrap();
+        let button = &out.views["base"].1
+            .get_rows()[0].1
+            .get_buttons()[0].1;
+        assert_eq!(
+            button.label,
+            crate::layout::Label::Text(CString::new("a").unwrap()),
+        );
+        assert_eq!(
+            warnings.0,
+            vec![LayoutWarning::EmptyField {
+                button: "a".into(),
+                field: "label",
+            }],
+        );
+    }
+
+    #[test]
+    fn test_long_label() {
+        let mut layout = make_single_button_layout();
+        layout.buttons.insert("a".into(), ButtonMeta {
+            label: Some("a".repeat(MAX_LABEL_CHARS) + "ä"),
+            ..ButtonMeta::default()
+        });
+        let (out, warnings) = layout.build(
+            layout::ArrangementKind::Base,
+            CollectWarnings(Vec::new()),
+        );
+        let out = out.unwrap();
+        let button = &out.views["base"].1
+            .get_rows()[0].1
+            .get_buttons()[0].1;
+        assert_eq!(
+            button.label,
+            crate::layout::Label::Text(
+                CString::new("a".repeat(MAX_LABEL_CHARS)).unwrap()
+            ),
+        );
+        assert_eq!(
+            warnings.0,
+            vec![LayoutWarning::LongLabel { button: "a".into() }],
+        );
+    }
+
+    #[test]
+    fn test_label_wide() {
+        let make_layout = || Layout {
+            views: hashmap!{
+                "base".into() => vec!["Return".into()],
+            },
+            buttons: hashmap!{
+                "Return".into() => ButtonMeta {
+                    label: Some("↵".into()),
+                    label_wide: Some("Return".into()),
+                    ..ButtonMeta::default()
+                },
+            },
+            default_outline: Some(Outline {
+                width: Length::Absolute(1.0), height: Length::Absolute(1.0), x: 0.0, y: 0.0,
+                wide: None,
+            }),
+            ..Layout::default()
+        };
+        let get_label = |kind| {
+            let out = make_layout().build(kind, ProblemPanic).0.unwrap();
+            let button = &out.views["base"].1
+                .get_rows()[0].1
+                .get_buttons()[0].1;
+            button.label.clone()
+        };
+        assert_eq!(
+            get_label(layout::ArrangementKind::Base),
+            crate::layout::Label::Text(CString::new("↵").unwrap()),
+        );
+        assert_eq!(
+            get_label(layout::ArrangementKind::Wide),
+            crate::layout::Label::Text(CString::new("Return").unwrap()),
+        );
+    }
+
+    fn make_single_button_layout() -> Layout {
+        Layout {
+            views: hashmap!{
+                "base".into() => vec!["a".into()],
+            },
+            default_outline: Some(Outline {
+                width: Length::Absolute(1.0), height: Length::Absolute(1.0), x: 0.0, y: 0.0,
+                wide: None,
+            }),
+            ..Layout::default()
+        }
+    }
+
+    fn fail_keymaps(
+        _symbolmap: HashMap<String, KeyCode>,
+        _shift_levels: &ShiftLevels,
+        _base_keys: &[(u32, String)],
+        _names: &KeymapNames,
+    ) -> Result<Vec<String>, FormattingError> {
+        Err(FormattingError::Format(
+            std::io::Error::new(std::io::ErrorKind::Other, "test")
+        ))
+    }
+
+    #[test]
+    fn test_keymap_strict() {
+        let layout = make_single_button_layout();
+        let out = layout.build_with(
+            layout::ArrangementKind::Base,
+            fail_keymaps,
+            false,
+            None,
+            false,
+            ProblemPanic,
+        ).0;
+        assert!(out.is_err());
+    }
+
+    #[test]
+    fn test_keymap_fallback() {
+        let layout = make_single_button_layout();
+        let (out, warnings) = layout.build_with(
+            layout::ArrangementKind::Base,
+            fail_keymaps,
+            true,
+            None,
+            false,
+            CollectWarnings(Vec::new()),
+        );
+        let out = out.unwrap();
+        assert!(matches!(
+            warnings.0[..],
+            [LayoutWarning::KeymapFailed { .. }],
+        ));
+        assert_eq!(out.keymaps.len(), 1);
+        let button = &out.views["base"].1
+            .get_rows()[0].1
+            .get_buttons()[0].1;
+        assert_eq!(button.keycodes, Vec::new());
+        assert!(matches!(
+            button.action,
+            action::Action::Submit { text: Some(_), keys: _ },
+        ));
+    }
+
+    #[test]
+    fn test_base_keymap_full() {
+        let base_symbols = free_keycodes(&[]).into_iter()
+            .enumerate()
+            .map(|(i, code)| (
+                format!("U{:04X}", 0x100 + i),
+                KeyCode { code, keymap_idx: 0 },
+            ))
+            .collect();
+        let base = generate_keymaps(
+            base_symbols,
+            &ShiftLevels::new(),
+            &[],
+            &KeymapNames::default(),
+        ).unwrap();
+
+        let (out, warnings) = make_single_button_layout()
+            .build_with_keymap_fallback(
+                layout::ArrangementKind::Base,
+                Some(&base[0]),
+                false,
+                CollectWarnings(Vec::new()),
+            );
+        let out = out.unwrap();
+        assert_eq!(warnings.0, vec![LayoutWarning::BaseKeymapFull]);
+        let button = &out.views["base"].1
+            .get_rows()[0].1
+            .get_buttons()[0].1;
+        assert_eq!(
+            button.keycodes,
+            vec![KeyCode { code: 9, keymap_idx: 0 }],
+        );
+    }
+
+    #[test]
+    fn test_keymap_names() {
+        let mut layout = make_single_button_layout();
+        layout.xkb_rules = Some("evdev".into());
+        layout.xkb_model = Some("pc105".into());
+        layout.xkb_layout = Some("us".into());
+        let out = layout.build(layout::ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let keymap = out.keymaps[0].to_str().unwrap();
+        assert!(keymap.contains("xkb_keycodes \"evdev(pc105)\""));
+        assert!(keymap.contains("xkb_symbols \"us\""));
+
+        let mut layout = make_single_button_layout();
+        layout.xkb_layout = Some("".into());
+        let (out, warnings) = layout.build(
+            layout::ArrangementKind::Base,
+            CollectWarnings(Vec::new()),
+        );
+        let keymap = out.unwrap().keymaps[0].to_str().unwrap().to_owned();
+        assert!(keymap.contains("xkb_symbols \"squeekboard\""));
+        assert_eq!(
+            warnings.0,
+            vec![LayoutWarning::UnusableKeymapName {
+                field: "xkb_layout",
+                value: "".into(),
+            }],
+        );
+    }
+
+    #[test]
+    fn test_structured_warnings() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a b c"
+outlines:
+    default: { width: 1, height: 1 }
+buttons:
+    a:
+        action:
+            set_view: "missing"
+    b:
+        keysym: "NotAKeysym"
+        outline: "nonexistent"
+    c:
+        text: "c"
+        keysym: "c"
+"#).unwrap();
+        let (_out, warnings) = layout.build(
+            layout::ArrangementKind::Base,
+            CollectWarnings(Vec::new()),
+        );
+        let mut warnings = warnings.0;
+        warnings.sort_by_key(|w| w.to_string());
+        assert_eq!(
+            warnings,
+            vec![
+                LayoutWarning::MissingView {
+                    button: "a".into(),
+                    view: "missing".into(),
+                },
+                LayoutWarning::ConflictingFields { button: "c".into() },
+                LayoutWarning::InvalidKeysym { name: "NotAKeysym".into() },
+                LayoutWarning::MissingOutline {
+                    button: "b".into(),
+                    outline: "nonexistent".into(),
+                },
+            ],
+        );
+        assert_eq!(
+            warnings[0].to_string(),
+            "Button a switches to missing view missing",
+        );
+    }
+
+    #[test]
+    fn test_dead_end_views() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "symbols emoji"
+    symbols:
+        - "1 more"
+    more:
+        - "2 symbols"
+    emoji:
+        - "smile back"
+    unused:
+        - "3"
+outlines:
+    default: { width: 1, height: 1 }
+buttons:
+    symbols: { action: { set_view: symbols } }
+    emoji: { action: { set_view: emoji } }
+    more: { action: { set_view: more } }
+    back: { action: back }
+    smile: { text: "🙂" }
+"#).unwrap();
+        let (_out, warnings) = layout.build(
+            layout::ArrangementKind::Base,
+            CollectWarnings(Vec::new()),
+        );
+        let dead_end = |view: &str| LayoutWarning::DeadEndView {
+            view: view.into(),
+            default_view: "base".into(),
+        };
+        assert_eq!(warnings.0, vec![dead_end("more"), dead_end("symbols")]);
+    }
+
+    #[test]
+    fn test_key_indices() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a b a"
+    upper:
+        - "A b"
+outlines:
+    default: { width: 1, height: 1 }
+"#).unwrap();
+        let out = layout.build(layout::ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        assert_eq!(
+            out.key_indices,
+            hashmap!{
+                "A".into() => 0,
+                "a".into() => 1,
+                "b".into() => 2,
+            },
+        );
+    }
+
+    #[test]
+    fn test_action_wins() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "abc"
+    letters:
+        - "a"
+outlines:
+    default: { width: 1, height: 1 }
+buttons:
+    abc:
+        action:
+            set_view: "letters"
+        label: "ABC"
+        keysym: "a"
+        text: "abc"
+"#).unwrap();
+        let (out, warnings) = layout.build(
+            layout::ArrangementKind::Base,
+            CollectWarnings(Vec::new()),
+        );
+        let out = out.unwrap();
+        assert_eq!(
+            warnings.0,
+            vec![LayoutWarning::IgnoredFields {
+                button: "abc".into(),
+                fields: vec!["keysym", "text"],
+            }],
+        );
+        assert_eq!(
+            warnings.0[0].to_string(),
+            "Button abc has an action, ignoring keysym, text",
+        );
+        let button = &out.views["base"].1
+            .get_rows()[0].1
+            .get_buttons()[0].1;
+        assert_eq!(button.action, action::Action::SetView("letters".into()));
+    }
+
+    #[test]
+    fn test_clear_action() {
+        let buttons = hashmap!{
+            "clear".into() => ButtonMeta {
+                action: Some(Action::Clear),
+                ..ButtonMeta::default()
+            },
+        };
+        let action = create_action(
+            &buttons, "clear",
+            &ActionOptions::default(),
+            &mut ProblemPanic,
+        );
+        assert_eq!(action, action::Action::ClearPending);
+        // Nothing to put in the keymap
+        assert_eq!(
+            extract_symbol_names(&[("clear", action)]).count(),
+            0,
+        );
+    }
+
+    #[test]
+    fn test_dbus_action() {
+        let buttons: HashMap<String, ButtonMeta> = serde_yaml::from_str(r#"
+help:
+    action:
+        dbus:
+            service: org.example.Help
+            path: /org/example/Help
+            method: org.example.Help.Open
+"#).unwrap();
+        assert_eq!(
+            create_action(
+                &buttons, "help",
+                &ActionOptions { dbus_enabled: true, ..Default::default() },
+                &mut ProblemPanic,
+            ),
+            action::Action::DBus {
+                service: "org.example.Help".into(),
+                path: "/org/example/Help".into(),
+                interface: "org.example.Help".into(),
+                method: "Open".into(),
+            },
+        );
+
+        let mut warnings = CollectWarnings(Vec::new());
+        assert_eq!(
+            create_action(
+                &buttons, "help",
+                &ActionOptions::default(),
+                &mut warnings,
+            ),
+            action::Action::Submit { text: None, keys: Vec::new() },
+        );
+        assert_eq!(
+            warnings.0,
+            vec![LayoutWarning::DBusDisabled { button: "help".into() }],
+        );
+    }
+
+    #[test]
+    fn test_invalid_keysym_fallback() {
+        let buttons = hashmap!{
+            "a".into() => ButtonMeta {
+                keysym: Some("NotAKeysym".into()),
+                ..ButtonMeta::default()
+            },
+        };
+        let mut warnings = CollectWarnings(Vec::new());
+        assert_eq!(
+            create_action(
+                &buttons, "a",
+                &ActionOptions::default(),
+                &mut warnings,
+            ),
+            action::Action::Submit { text: None, keys: Vec::new() },
+        );
+        assert_eq!(
+            warnings.0,
+            vec![LayoutWarning::InvalidKeysym { name: "NotAKeysym".into() }],
+        );
+        assert_eq!(
+            create_action(
+                &buttons, "a",
+                &ActionOptions {
+                    invalid_keysym: KeysymFallback::Replacement,
+                    ..Default::default()
+                },
+                &mut CollectWarnings(Vec::new()),
+            ),
+            action::Action::Submit {
+                text: None,
+                keys: vec![action::KeySym("UFFFD".into())],
+            },
+        );
+    }
+
+    #[test]
+    fn test_clipboard_action() {
+        let buttons: HashMap<String, ButtonMeta> = serde_yaml::from_str(r#"
+cut:
+    action: { clipboard: cut }
+copy:
+    action: { clipboard: copy }
+paste:
+    action: { clipboard: paste }
+all:
+    action: { clipboard: select_all }
+undo:
+    action: { clipboard: undo }
+"#).unwrap();
+        let ops = [
+            ("cut", action::ClipboardOp::Cut),
+            ("copy", action::ClipboardOp::Copy),
+            ("paste", action::ClipboardOp::Paste),
+            ("all", action::ClipboardOp::SelectAll),
+        ];
+        for (name, op) in ops {
+            assert_eq!(
+                create_action(
+                    &buttons, name,
+                    &ActionOptions::default(),
+                    &mut ProblemPanic,
+                ),
+                action::Action::Clipboard(op),
+            );
+        }
+
+        let mut warnings = CollectWarnings(Vec::new());
+        assert_eq!(
+            create_action(
+                &buttons, "undo",
+                &ActionOptions::default(),
+                &mut warnings,
+            ),
+            action::Action::Submit { text: None, keys: Vec::new() },
+        );
+        assert_eq!(
+            warnings.0,
+            vec![LayoutWarning::UnknownClipboardOp {
+                button: "undo".into(),
+                op: "undo".into(),
+            }],
+        );
+    }
+
+    #[test]
+    fn test_show_prefs_action() {
+        let buttons: HashMap<String, ButtonMeta> = serde_yaml::from_str(r#"
+bare:
+    action: show_prefs
+empty:
+    action: { show_prefs: {} }
+settings:
+    action: { show_prefs: { target: settings } }
+unknown:
+    action: { show_prefs: { target: control_center } }
+"#).unwrap();
+        assert_eq!(
+            create_action(
+                &buttons, "bare",
+                &ActionOptions::default(),
+                &mut ProblemPanic,
+            ),
+            action::Action::ShowPreferences(action::PrefsTarget::Chooser),
+        );
+        assert_eq!(
+            create_action(
+                &buttons, "empty",
+                &ActionOptions::default(),
+                &mut ProblemPanic,
+            ),
+            action::Action::ShowPreferences(action::PrefsTarget::Chooser),
+        );
+        assert_eq!(
+            create_action(
+                &buttons, "settings",
+                &ActionOptions::default(),
+                &mut ProblemPanic,
+            ),
+            action::Action::ShowPreferences(action::PrefsTarget::Settings),
+        );
+
+        let mut warnings = CollectWarnings(Vec::new());
+        assert_eq!(
+            create_action(
+                &buttons, "unknown",
+                &ActionOptions::default(),
+                &mut warnings,
+            ),
+            action::Action::ShowPreferences(action::PrefsTarget::Chooser),
+        );
+        assert_eq!(
+            warnings.0,
+            vec![LayoutWarning::UnknownPrefsTarget {
+                button: "unknown".into(),
+                target: "control_center".into(),
+            }],
+        );
+    }
+
+    #[test]
+    fn test_locking_into_base() {
+        let buttons: HashMap<String, ButtonMeta> = serde_yaml::from_str(r#"
+shift:
+    action:
+        locking:
+            lock_view: base
+            unlock_view: upper
+"#).unwrap();
+        let base = "base".to_string();
+        let upper = "upper".to_string();
+        let mut warnings = CollectWarnings(Vec::new());
+        assert_eq!(
+            create_action(
+                &buttons, "shift",
+                &ActionOptions {
+                    view_names: vec![&base, &upper],
+                    ..Default::default()
+                },
+                &mut warnings,
+            ),
+            action::Action::LockView {
+                lock: "base".into(),
+                unlock: "upper".into(),
+                latches: true,
+                looks_locked_from: Vec::new(),
+                double_tap_lock: false,
+            },
+        );
+        assert_eq!(
+            warnings.0,
+            vec![LayoutWarning::LockingIntoDefault { button: "shift".into() }],
+        );
+    }
+
+    #[test]
+    fn test_locking_into_default() {
+        let buttons: HashMap<String, ButtonMeta> = serde_yaml::from_str(r#"
+shift:
+    action:
+        locking:
+            lock_view: base
+            unlock_view: digits
+digits:
+    action:
+        locking:
+            lock_view: digits
+            unlock_view: base
+"#).unwrap();
+        let base = "base".to_string();
+        let digits = "digits".to_string();
+        let options = ActionOptions {
+            view_names: vec![&base, &digits],
+            default_view: "digits",
+            ..Default::default()
+        };
+        let mut warnings = CollectWarnings(Vec::new());
+        create_action(&buttons, "shift", &options, &mut warnings);
+        assert_eq!(warnings.0, Vec::new());
+        create_action(&buttons, "digits", &options, &mut warnings);
+        assert_eq!(
+            warnings.0,
+            vec![LayoutWarning::LockingIntoDefault { button: "digits".into() }],
+        );
+    }
+
+    #[test]
+    fn test_dead_key_action() {
+        let buttons: HashMap<String, ButtonMeta> = serde_yaml::from_str(r#"
+acute:
+    action:
+        dead_key:
+            keysym: dead_acute
+grave:
+    action:
+        dead_key:
+            keysym: grave
+"#).unwrap();
+        let action = create_action(
+            &buttons, "acute",
+            &ActionOptions::default(),
+            &mut ProblemPanic,
+        );
+        assert_eq!(
+            action,
+            action::Action::Submit {
+                text: None,
+                keys: vec![action::KeySym("dead_acute".into())],
+            },
+        );
+        // Needs a place in the keymap
+        assert_eq!(
+            extract_symbol_names(&[("acute", action)]).collect::<Vec<_>>(),
+            vec!["dead_acute".to_string()],
+        );
+
+        let mut warnings = CollectWarnings(Vec::new());
+        assert_eq!(
+            create_action(
+                &buttons, "grave",
+                &ActionOptions::default(),
+                &mut warnings,
+            ),
+            action::Action::Submit { text: None, keys: Vec::new() },
+        );
+        assert_eq!(
+            warnings.0,
+            vec![LayoutWarning::NotDeadKey {
+                button: "grave".into(),
+                keysym: "grave".into(),
+            }],
+        );
+    }
+
+    #[test]
+    fn test_shortcode() {
+        let buttons: HashMap<String, ButtonMeta> = serde_yaml::from_str(r#"
+smile:
+    text: ":smile:"
+nose:
+    text: ":nose:"
+colon:
+    text: ":"
+"#).unwrap();
+        assert_eq!(
+            create_action(
+                &buttons, "smile",
+                &ActionOptions::default(),
+                &mut ProblemPanic,
+            ),
+            action::Action::Submit {
+                text: Some(CString::new("\u{1F604}").unwrap()),
+                keys: vec![action::KeySym("U1F604".into())],
+            },
+        );
+        // Not a shortcode at all
+        create_action(
+            &buttons, "colon",
+            &ActionOptions::default(),
+            &mut ProblemPanic,
+        );
+
+        let mut warnings = CollectWarnings(Vec::new());
+        assert_eq!(
+            create_action(
+                &buttons, "nose",
+                &ActionOptions::default(),
+                &mut warnings,
+            ),
+            action::Action::Submit {
+                text: Some(CString::new(":nose:").unwrap()),
+                keys: vec![
+                    action::KeySym("U003A".into()),
+                    action::KeySym("n".into()),
+                    action::KeySym("o".into()),
+                    action::KeySym("s".into()),
+                    action::KeySym("e".into()),
+                    action::KeySym("U003A".into()),
+                ],
+            },
+        );
+        assert_eq!(
+            warnings.0,
+            vec![LayoutWarning::UnknownShortcode {
+                button: "nose".into(),
+                text: ":nose:".into(),
+            }],
+        );
+    }
+
+    fn make_relative_layout(width: &str) -> Layout {
+        serde_yaml::from_str(&format!(r#"
+views:
+    base:
+        - "a"
+        - "b"
+outlines:
+    default: {{ width: 100, height: 10 }}
+    tenth: {{ width: "{}", height: 10 }}
+buttons:
+    b:
+        outline: tenth
+"#, width)).unwrap()
+    }
+
+    #[test]
+    fn test_layout_relative_width() {
+        let out = make_relative_layout("10%")
+            .build(layout::ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let button = &out.views["base"].1
+            .get_rows()[1].1
+            .get_buttons()[0].1;
+        assert_eq!(button.size.width, 10.0);
+    }
+
+    #[test]
+    fn test_layout_relative_width_malformed() {
+        let (out, warnings) = make_relative_layout("10 percent")
+            .build(layout::ArrangementKind::Base, CollectWarnings(Vec::new()));
+        let out = out.unwrap();
+        let button = &out.views["base"].1
+            .get_rows()[1].1
+            .get_buttons()[0].1;
+        assert_eq!(button.size.width, 1.0);
+        assert_eq!(
+            warnings.0,
+            vec![LayoutWarning::NotPercentage {
+                outline: "tenth".into(),
+                size: "10 percent".into(),
+            }],
+        );
+    }
+
+    #[test]
+    fn test_outlines_summary() {
+        let layout = Layout {
+            outlines: hashmap!{
+                "wide".into() => Outline {
+                    width: Length::Absolute(2.0), height: Length::Absolute(1.0), x: 0.0, y: 0.0,
+                    wide: None,
+                },
+                "altline".into() => Outline {
+                    width: Length::Absolute(1.5), height: Length::Absolute(1.0), x: 0.0, y: -0.5,
+                    wide: None,
+                },
+                "half".into() => Outline {
+                    width: Length::Relative("50%".into()), height: Length::Absolute(1.0), x: 0.0, y: 0.0,
+                    wide: None,
+                },
+            },
+            default_outline: Some(Outline {
+                width: Length::Absolute(1.0), height: Length::Absolute(1.0), x: 0.0, y: 0.0,
+                wide: None,
+            }),
+            ..Layout::default()
+        };
+        let summary: Vec<_> = layout.outlines_summary().into_iter()
+            .map(|outline| (
+                outline.name.into_string().unwrap(),
+                outline.bounds,
+                outline.relative,
+            ))
+            .collect();
+        let absolute = layout::Size { width: 0.0, height: 0.0 };
+        assert_eq!(
+            summary,
+            vec![
+                ("altline".into(), layout::c::Bounds {
+                    x: 0.0, y: -0.5, width: 1.5, height: 1.0,
+                }, absolute.clone()),
+                ("default".into(), layout::c::Bounds {
+                    x: 0.0, y: 0.0, width: 1.0, height: 1.0,
+                }, absolute.clone()),
+                ("half".into(), layout::c::Bounds {
+                    x: 0.0, y: 0.0, width: 0.0, height: 1.0,
+                }, layout::Size { width: 0.5, height: 0.0 }),
+                ("wide".into(), layout::c::Bounds {
+                    x: 0.0, y: 0.0, width: 2.0, height: 1.0,
+                }, absolute),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_build_strict() {
+        let mut layout = make_single_button_layout();
+        assert!(make_single_button_layout()
+            .build_strict(layout::ArrangementKind::Base)
+            .is_ok()
+        );
+
+        layout.buttons.insert("a".into(), ButtonMeta {
+            label: Some("".into()),
+            ..ButtonMeta::default()
+        });
+        match layout.build_strict(layout::ArrangementKind::Base) {
+            Err(StrictBuildError::Warnings(warnings)) => assert_eq!(
+                warnings,
+                vec!["Button a has an empty label, ignoring".to_string()],
+            ),
+            other => panic!("Unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_default_view() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a"
+    digits:
+        - "1"
+default_view: "digits"
+default_outline: { width: 1, height: 1 }
+"#).unwrap();
+        let out = layout.build(layout::ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        assert_eq!(out.default_view, "digits");
+    }
+
+    #[test]
+    fn test_view_order() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a"
+    symbols:
+        - "b"
+    numbers:
+        - "1"
+view_order: ["numbers", "emoji", "base"]
+default_outline: { width: 1, height: 1 }
+"#).unwrap();
+        let (out, warnings) = layout.build(
+            layout::ArrangementKind::Base,
+            CollectWarnings(Vec::new()),
+        );
+        assert_eq!(out.unwrap().view_order, vec!["numbers", "base", "symbols"]);
+        assert_eq!(
+            warnings.0,
+            vec![LayoutWarning::MissingOrderedView { view: "emoji".into() }],
+        );
+    }
+
+    #[test]
+    fn test_default_view_missing() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a"
+default_view: "digits"
+default_outline: { width: 1, height: 1 }
+"#).unwrap();
+        let (out, warnings) = layout.build(
+            layout::ArrangementKind::Base,
+            CollectWarnings(Vec::new()),
+        );
+        assert_eq!(out.unwrap().default_view, "base");
+        assert_eq!(
+            warnings.0,
+            vec![LayoutWarning::MissingDefaultView { view: "digits".into() }],
+        );
+    }
+
+    #[test]
+    fn test_rename_view() {
+        let mut layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "sym shift"
+    symbols2:
+        - "abc"
+    upper:
+        - "abc"
+view_order: ["base", "symbols2", "upper"]
+view_directions: { symbols2: rtl }
+shift_view: "symbols2"
+modal_views: { symbols2: true }
+buttons:
+    sym:
+        action:
+            set_view: "symbols2"
+    shift:
+        action:
+            locking:
+                lock_view: "upper"
+                unlock_view: "base"
+                looks_locked_from: ["symbols2"]
+    abc:
+        action:
+            set_view: "base"
+"#).unwrap();
+        assert_eq!(
+            layout.rename_view("symbols2", "upper"),
+            Err("View upper already exists".into()),
+        );
+        assert_eq!(
+            layout.rename_view("missing", "numbers"),
+            Err("No view named missing".into()),
+        );
+        assert!(layout.views.contains_key("symbols2"));
+
+        layout.rename_view("symbols2", "numbers").unwrap();
+        assert!(!layout.views.contains_key("symbols2"));
+        assert_eq!(layout.views["numbers"], vec!["abc".to_string()]);
+        assert_eq!(
+            layout.view_order,
+            Some(vec!["base".into(), "numbers".into(), "upper".into()]),
+        );
+        assert_eq!(layout.modal_views, hashmap!{ "numbers".into() => true });
+        assert_eq!(layout.shift_view, Some("numbers".into()));
+        assert_eq!(
+            layout.view_directions,
+            hashmap!{ "numbers".into() => Direction::RightToLeft },
+        );
+        assert_eq!(
+            layout.buttons["sym"].action,
+            Some(Action::SetView("numbers".into())),
+        );
+        assert_eq!(
+            layout.buttons["shift"].action,
+            Some(Action::Locking {
+                lock_view: "upper".into(),
+                unlock_view: "base".into(),
+                pops: None,
+                looks_locked_from: vec!["numbers".into()],
+                double_tap_lock: None,
+            }),
+        );
+        assert_eq!(
+            layout.buttons["abc"].action,
+            Some(Action::SetView("base".into())),
+        );
+    }
+
+    #[test]
+    fn test_check_outline_references() {
+        let mut layout = make_single_button_layout();
+        assert_eq!(layout.check_outline_references(), Vec::<String>::new());
+
+        layout.buttons.insert("a".into(), ButtonMeta {
+            outline: Some("missing".into()),
+            ..ButtonMeta::default()
+        });
+        assert_eq!(
+            layout.check_outline_references(),
+            vec![
+                "Outline named missing does not exist! Using default for button a"
+                    .to_string(),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_validate_keysyms_against_runtime() {
+        let mut layout = make_single_button_layout();
+        layout.buttons = hashmap!{
+            "a".into() => ButtonMeta {
+                keysym: Some("Return".into()),
+                alternate: Some(Alternate {
+                    when: "flag".into(),
+                    keysym: Some("XF86NotFromAnyVersion".into()),
+                    text: None,
+                }),
+                ..ButtonMeta::default()
+            },
+            "b".into() => ButtonMeta {
+                action: Some(Action::DeadKey { keysym: "dead_nothing".into() }),
+                ..ButtonMeta::default()
+            },
+            "c".into() => ButtonMeta {
+                keysym: Some("c".into()),
+                shift_keysym: Some("C_but_bigger".into()),
+                long_press_keysym: Some("c_but_longer".into()),
+                ..ButtonMeta::default()
+            },
+        };
+        assert_eq!(
+            layout.validate_keysyms_against_runtime(),
+            vec![
+                "Button a uses keysym XF86NotFromAnyVersion, unknown to this system"
+                    .to_string(),
+                "Button b uses keysym dead_nothing, unknown to this system"
+                    .to_string(),
+                "Button c uses keysym C_but_bigger, unknown to this system"
+                    .to_string(),
+                "Button c uses keysym c_but_longer, unknown to this system"
+                    .to_string(),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_a11y_label() {
+        let mut layout = make_single_button_layout();
+        layout.views.insert("base".into(), vec!["a BackSpace Return".into()]);
+        layout.buttons = hashmap!{
+            "BackSpace".into() => ButtonMeta {
+                icon: Some("edit-clear-symbolic".into()),
+                a11y_label: Some("Delete".into()),
+                ..ButtonMeta::default()
+            },
+            "Return".into() => ButtonMeta {
+                icon: Some("key-enter".into()),
+                ..ButtonMeta::default()
+            },
+            "a".into() => ButtonMeta {
+                label: Some("á".into()),
+                ..ButtonMeta::default()
+            },
+        };
+        let out = layout.build(layout::ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let buttons = out.views["base"].1
+            .get_rows()[0].1
+            .get_buttons();
+        let a11y_labels: Vec<_> = buttons.iter()
+            .map(|(_, button)| button.a11y_label.to_str().unwrap())
+            .collect();
+        assert_eq!(a11y_labels, vec!["á", "Delete", "Return"]);
+    }
+
+    #[test]
+    fn test_keymap_stable() {
+        fn build() -> Vec<CString> {
+            let layout = Layout::from_file(
+                path_from_root("tests/layout_keymap_stable.yaml")
+            ).unwrap();
+            layout.build(layout::ArrangementKind::Base, ProblemPanic).0
+                .unwrap()
+                .keymaps
+        }
+        assert_eq!(build(), build());
+    }
+
+    #[test]
+    fn test_content_overflow() {
+        let layout: Layout = serde_yaml::from_str(r#"
+margins: { top: 0, bottom: 0, side: 0.25 }
+views:
+    base:
+        - "a b"
+outlines:
+    default: { width: 1, height: 1 }
+    shifted: { width: 1, height: 1, x: 0.5 }
+buttons:
+    b:
+        outline: shifted
+"#).unwrap();
+        let (out, warnings) = layout.build(
+            layout::ArrangementKind::Base,
+            CollectWarnings(Vec::new()),
+        );
+        out.unwrap();
+        assert_eq!(
+            warnings.0,
+            vec![LayoutWarning::ViewOverflow {
+                view: "base".into(),
+                overflow: 0.25,
+            }],
+        );
+    }
+
+    #[test]
+    fn test_layout_grid() {
+        let layout: Layout = serde_yaml::from_str(r#"
+grid_unit: 0.5
+views:
+    base:
+        - "a b"
+outlines:
+    default: { width: { cells: 3 }, height: { cells: 2 } }
+    narrow: { width: { cells: 1 }, height: { cells: 2 } }
+buttons:
+    b:
+        outline: narrow
+"#).unwrap();
+        let out = layout.build(layout::ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let buttons = out.views["base"].1
+            .get_rows()[0].1
+            .get_buttons();
+        assert_eq!(
+            buttons[0].1.size,
+            layout::Size { width: 1.5, height: 1.0 },
+        );
+        assert_eq!(buttons[1].0, 1.5);
+        assert_eq!(
+            buttons[1].1.size,
+            layout::Size { width: 0.5, height: 1.0 },
+        );
+    }
+
+    #[test]
+    fn test_layout_grid_mixed() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a"
+outlines:
+    default: { width: { cells: 3 }, height: 2 }
+"#).unwrap();
+        let (out, warnings) = layout.build(
+            layout::ArrangementKind::Base,
+            CollectWarnings(Vec::new()),
+        );
+        out.unwrap();
+        assert_eq!(
+            warnings.0,
+            vec![
+                LayoutWarning::MixedGridCells { outline: "default".into() },
+                LayoutWarning::MissingGridUnit { outline: "default".into() },
+            ],
+        );
+    }
+
+    #[test]
+    fn test_layout_alternate() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "KP_7 NumLock"
+outlines:
+    default: { width: 1, height: 1 }
+buttons:
+    KP_7:
+        keysym: KP_Home
+        alternate:
+            when: numlock
+            keysym: KP_7
+    NumLock:
+        action:
+            toggle: numlock
+"#).unwrap();
+        let out = layout.build(layout::ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let buttons = out.views["base"].1
+            .get_rows()[0].1
+            .get_buttons();
+        assert_eq!(
+            buttons[1].1.action,
+            action::Action::ToggleFlag("numlock".into()),
+        );
+
+        let button = &buttons[0].1;
+        let mut flags = HashSet::new();
+        let (off_action, off_keycodes) = button.get_submission(&flags);
+        assert_eq!(
+            off_action,
+            &action::Action::Submit {
+                text: None,
+                keys: vec![action::KeySym("KP_Home".into())],
+            },
+        );
+        let off_keycodes = off_keycodes.clone();
+
+        flags.insert("numlock".to_string());
+        let (on_action, on_keycodes) = button.get_submission(&flags);
+        assert_eq!(
+            on_action,
+            &action::Action::Submit {
+                text: None,
+                keys: vec![action::KeySym("KP_7".into())],
+            },
+        );
+        assert_eq!(on_keycodes.len(), 1);
+        assert_ne!(on_keycodes, &off_keycodes);
+    }
+
+    #[test]
+    fn test_layout_edges() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "copy_paste"
+    other:
+        - "copy_paste back"
+outlines:
+    default: { width: 1, height: 1 }
+buttons:
+    back: { action: back }
+    copy_paste:
+        on_press:
+            clipboard: copy
+        on_release:
+            set_view: other
+"#).unwrap();
+        let out = layout.build(layout::ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let button = &out.views["base"].1
+            .get_rows()[0].1
+            .get_buttons()[0].1;
+        let edges = button.edges.as_ref().unwrap();
+        let on_press = edges.on_press.as_ref().unwrap();
+        assert_eq!(
+            on_press.action,
+            action::Action::Clipboard(action::ClipboardOp::Copy),
+        );
+        assert_eq!(on_press.keycodes.len(), 1);
+        assert_eq!(
+            edges.on_release.as_ref().map(|effect| &effect.action),
+            Some(&action::Action::SetView("other".into())),
+        );
+    }
+
+    #[test]
+    fn test_layout_fill_rows() {
+        let layout: Layout = serde_yaml::from_str(r#"
+fill_rows: true
+views:
+    base:
+        - "1 2 3"
+        - "4 5 6"
+        - "0"
+"#).unwrap();
+        let out = layout.build(layout::ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let view = &out.views["base"].1;
+        assert_eq!(view.get_size(), layout::Size { width: 3.0, height: 3.0 });
+
+        let rows = view.get_rows();
+        let row_offsets: Vec<f64> = rows.iter()
+            .map(|(offset, _row)| offset.y)
+            .collect();
+        assert_eq!(row_offsets, vec![0.0, 1.0, 2.0]);
+        for (_offset, row) in &rows[..2] {
+            let places: Vec<(f64, f64)> = row.get_buttons().iter()
+                .map(|(x, button)| (*x, button.size.width))
+                .collect();
+            assert_eq!(places, vec![(0.0, 1.0), (1.0, 1.0), (2.0, 1.0)]);
+        }
+        assert_eq!(rows[2].1.get_buttons()[0].1.size.width, 3.0);
+    }
+
+    #[test]
+    fn test_layout_spacing() {
+        let yaml = r#"
+views:
+    base:
+        - "a b"
+        - "c"
+default_outline: { width: 2, height: 1 }
+spacing: { row: 0.5, button: 1, wide: { button: 2 } }
+"#;
+        let get_places = |kind| {
+            let layout: Layout = serde_yaml::from_str(yaml).unwrap();
+            let out = layout.build(kind, ProblemPanic).0.unwrap();
+            let view = &out.views["base"].1;
+            let rows = view.get_rows();
+            let button_offsets: Vec<f64> = rows[0].1.get_buttons().iter()
+                .map(|(x, _button)| *x)
+                .collect();
+            (view.get_size(), button_offsets, rows[1].0.y)
+        };
+        assert_eq!(
+            get_places(layout::ArrangementKind::Base),
+            (layout::Size { width: 5.0, height: 2.5 }, vec![0.0, 3.0], 1.5),
+        );
+        // The row spacing is not replaced
+        assert_eq!(
+            get_places(layout::ArrangementKind::Wide),
+            (layout::Size { width: 6.0, height: 2.5 }, vec![0.0, 4.0], 1.5),
+        );
+    }
+
+    #[test]
+    fn test_layout_direction() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a b"
+    numbers:
+        - "1 2"
+direction: rtl
+view_directions:
+    numbers: ltr
+default_outline: { width: 1, height: 1 }
+"#).unwrap();
+        let out = layout.build(layout::ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let get_names = |view: &str| {
+            out.views[view].1.get_rows()[0].1.get_buttons().iter()
+                .map(|(_x, button)| button.name.to_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(get_names("base"), vec!["b", "a"]);
+        assert_eq!(get_names("numbers"), vec!["1", "2"]);
+    }
+
+    #[test]
+    fn test_layout_cluster() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a Up b"
+        - "Left Down Right"
+outlines:
+    default: { width: 1, height: 1 }
+buttons:
+    Up: { keysym: Up, cluster: arrows }
+    Left: { keysym: Left, cluster: arrows }
+"#).unwrap();
+        let out = layout.build(layout::ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        assert_eq!(
+            out.clusters,
+            vec![layout::Cluster {
+                name: "arrows".into(),
+                view: "base".into(),
+                bounds: layout::c::Bounds {
+                    x: 0.0, y: 0.0,
+                    width: 2.0, height: 2.0,
+                },
+            }],
+        );
+    }
+
+    #[test]
+    fn test_layout_outline_wide() {
+        fn build(kind: layout::ArrangementKind) -> layout::Size {
+            let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a"
+outlines:
+    default:
+        width: 1
+        height: 1
+        wide: { width: 2 }
+"#).unwrap();
+            let out = layout.build(kind, ProblemPanic).0.unwrap();
+            out.views["base"].1
+                .get_rows()[0].1
+                .get_buttons()[0].1
+                .size.clone()
+        }
+        assert_eq!(
+            build(layout::ArrangementKind::Base),
+            layout::Size { width: 1.0, height: 1.0 },
+        );
+        assert_eq!(
+            build(layout::ArrangementKind::Wide),
+            layout::Size { width: 2.0, height: 1.0 },
+        );
+    }
+
+    #[test]
+    fn test_layout_silent() {
+        let mut layout = make_single_button_layout();
+        layout.views.insert("base".into(), vec!["a b".into()]);
+        layout.buttons.insert("a".into(), ButtonMeta {
+            silent: Some(true),
+            ..ButtonMeta::default()
+        });
+        let out = layout.build(layout::ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let buttons = out.views["base"].1
+            .get_rows()[0].1
+            .get_buttons();
+        assert_eq!(buttons[0].1.silent, true);
+        assert_eq!(buttons[1].1.silent, false);
+    }
+
+    #[test]
+    fn test_layout_repeat() {
+        let layout = Layout {
+            views: hashmap!{
+                "base".into() => vec!["a b".into()],
+            },
+            buttons: hashmap!{
+                "a".into() => ButtonMeta {
+                    repeat_delay_ms: Some(800),
+                    repeat_interval_ms: Some(200),
+                    ..ButtonMeta::default()
+                },
+            },
+            default_outline: Some(Outline {
+                width: Length::Absolute(1.0), height: Length::Absolute(1.0), x: 0.0, y: 0.0,
+                wide: None,
+            }),
+            ..Layout::default()
+        };
+        let out = layout.build(layout::ArrangementKind::Base, ProblemPanic).0.unwrap();
+        let buttons = out.views["base"].1
+            .get_rows()[0].1
+            .get_buttons();
+        assert_eq!(
+            buttons[0].1.repeat,
+            layout::Repeat { delay_ms: Some(800), interval_ms: Some(200) },
+        );
+        assert_eq!(buttons[1].1.repeat, layout::Repeat::default());
+    }
+
+    #[test]
+    fn test_layout_enter() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "Return"
+buttons:
+    Return:
+        action: enter
+default_outline: { width: 1, height: 1 }
+"#).unwrap();
+        let out = layout.build(layout::ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let button = &out.views["base"].1
+            .get_rows()[0].1
+            .get_buttons()[0].1;
+        assert_eq!(button.action, action::Action::Enter);
+        assert_eq!(button.keycodes.len(), 1);
+
+        let return_key = vec![action::KeySym("Return".into())];
+        assert_eq!(
+            button.action.clone().resolve_enter(true),
+            action::Action::Submit {
+                text: Some(CString::new("\n").unwrap()),
+                keys: return_key.clone(),
+            },
+        );
+        assert_eq!(
+            button.action.clone().resolve_enter(false),
+            action::Action::Submit { text: None, keys: return_key },
+        );
+    }
+
+    #[test]
+    fn test_layout_debounce() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a b"
+buttons:
+    a:
+        debounce_ms: 40
+default_outline: { width: 1, height: 1 }
+"#).unwrap();
+        let out = layout.build(layout::ArrangementKind::Base, ProblemPanic).0.unwrap();
+        let buttons = out.views["base"].1
+            .get_rows()[0].1
+            .get_buttons();
+        assert_eq!(buttons[0].1.debounce_ms, Some(40));
+        assert_eq!(buttons[1].1.debounce_ms, None);
+    }
+
+    #[test]
+    fn test_layout_extra() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a b"
+buttons:
+    a:
+        extra: { macro: "greeting", "x-plugin.color": "red" }
+default_outline: { width: 1, height: 1 }
+"#).unwrap();
+        let out = layout.build(layout::ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let buttons = out.views["base"].1.get_rows()[0].1.get_buttons();
+        assert_eq!(
+            buttons[0].1.extra.get("macro").map(String::as_str),
+            Some("greeting"),
+        );
+        assert_eq!(buttons[0].1.extra.len(), 2);
+        assert!(buttons[1].1.extra.is_empty());
+    }
+
+    #[test]
+    fn test_layout_grid_rows() {
+        let build = |yaml: &str| {
+            let layout: Layout = serde_yaml::from_str(yaml).unwrap();
+            let out = layout.build(layout::ArrangementKind::Base, ProblemPanic).0
+                .unwrap();
+            out.views["base"].1.get_rows().iter()
+                .map(|(_offset, row)| {
+                    row.get_buttons().iter()
+                        .map(|(x, button)| (*x, button.name.clone()))
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+        let grid = build(r#"
+views:
+    base:
+        - ["1", "2", "3"]
+        - ["4", "5", "BackSpace"]
+default_outline: { width: 1, height: 1 }
+"#);
+        let text = build(r#"
+views:
+    base:
+        - "1 2 3"
+        - "4 5 BackSpace"
+default_outline: { width: 1, height: 1 }
+"#);
+        assert_eq!(grid.len(), 2);
+        assert_eq!(grid, text);
+
+        let spaced: Result<Layout, _> = serde_yaml::from_str(r#"
+views:
+    base:
+        - ["1 2"]
+"#);
+        assert!(spaced.is_err());
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    
-    use std::env;
-    
-    use crate::logging::ProblemPanic;
+    #[test]
+    fn test_layout_keep_view() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a b numbers"
+    numbers:
+        - "1 back"
+buttons:
+    back: { action: back }
+    a: { keep_view: true }
+    numbers: { action: { set_view: numbers }, keep_view: true }
+default_outline: { width: 1, height: 1 }
+"#).unwrap();
+        let out = layout.build(layout::ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let buttons = out.views["base"].1.get_rows()[0].1.get_buttons();
+        let keep_view: Vec<bool> = buttons.iter()
+            .map(|(_x, button)| button.keep_view)
+            .collect();
+        assert_eq!(keep_view, vec![true, false, false]);
+    }
 
-    fn path_from_root(file: &'static str) -> PathBuf {
-        let source_dir = env::var("SOURCE_DIR")
-            .map(PathBuf::from)
-            .unwrap_or_else(|e| {
-                if let env::VarError::NotPresent = e {
-                    let this_file = file!();
-                    PathBuf::from(this_file)
-                        .parent().unwrap()
-                        .parent().unwrap()
-                        .into()
-                } else {
-                    panic!("{:?}", e);
-                }
-            });
-        source_dir.join(file)
+    #[test]
+    fn test_layout_shift_keysym() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a b"
+buttons:
+    a: { shift_keysym: A }
+    b: { text: "bc", shift_keysym: B }
+default_outline: { width: 1, height: 1 }
+"#).unwrap();
+        let (out, handler) = layout
+            .build(layout::ArrangementKind::Base, CollectWarnings(Vec::new()));
+        let out = out.unwrap();
+        let keymap = out.keymaps[0].to_str().unwrap();
+        assert!(keymap.contains("[ a, A ]"));
+        assert!(!keymap.contains(", B ]"));
+        // Multi-character text doesn't fit on one key
+        assert_eq!(
+            handler.0,
+            vec![LayoutWarning::UnusableShiftKeysym { button: "b".into() }],
+        );
     }
 
     #[test]
-    fn test_parse_path() {
+    fn test_build_progress() {
+        let (out, handler) = Layout::from_file(path_from_root("tests/layout.yaml"))
+            .unwrap()
+            .build(layout::ArrangementKind::Base, CollectProgress(Vec::new()));
+        out.unwrap();
+        let phases: Vec<BuildPhase> = handler.0.iter()
+            .map(|(phase, _fraction)| *phase)
+            .collect();
         assert_eq!(
-            Layout::from_file(path_from_root("tests/layout.yaml")).unwrap(),
-            Layout {
-                margins: Margins { top: 0f64, bottom: 0f64, side: 0f64 },
-                views: hashmap!(
-                    "base".into() => vec!("test".into()),
-                ),
-                buttons: hashmap!{
-                    "test".into() => ButtonMeta {
-                        icon: None,
-                        keysym: None,
-                        action: None,
-                        text: None,
-                        modifier: None,
-                        label: Some("test".into()),
-                        outline: None,
-                    }
-                },
-                outlines: hashmap!{
-                    "default".into() => Outline { width: 0f64, height: 0f64 }, 
-                },
-            }
+            phases,
+            vec![
+                BuildPhase::Parsing,
+                BuildPhase::GeneratingKeymap,
+                BuildPhase::BuildingViews,
+            ],
         );
+        let fractions: Vec<f64> = handler.0.iter()
+            .map(|(_phase, fraction)| *fraction)
+            .collect();
+        assert!(fractions.windows(2).all(|pair| pair[0] < pair[1]));
     }
 
-    /// Check if the default protection works
     #[test]
-    fn test_empty_views() {
-        let out = Layout::from_file(path_from_root("tests/layout2.yaml"));
-        match out {
-            Ok(_) => assert!(false, "Data mistakenly accepted"),
-            Err(e) => {
-                let mut handled = false;
-                if let Error::Yaml(ye) = &e {
-                    handled = ye.to_string()
-                        .starts_with("missing field `views`");
-                };
-                if !handled {
-                    println!("Unexpected error {:?}", e);
-                    assert!(false)
-                }
-            }
-        }
+    fn test_layout_shift_view() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a b"
+        - "c d"
+    upper:
+        - "A B"
+        - "C"
+shift_view: upper
+default_outline: { width: 1, height: 1 }
+"#).unwrap();
+        let out = layout.build(layout::ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let previews = |view: &str, row: usize| -> Vec<Option<layout::Label>> {
+            out.views[view].1.get_rows()[row].1.get_buttons().iter()
+                .map(|(_x, button)| button.preview_label.clone())
+                .collect()
+        };
+        let text = |text: &str| Some(layout::Label::Text(CString::new(text).unwrap()));
+        assert_eq!(previews("base", 0), vec![text("A"), text("B")]);
+        // Nothing in the shift view at row 1, column 1
+        assert_eq!(previews("base", 1), vec![text("C"), None]);
+        assert_eq!(previews("upper", 0), vec![None, None]);
     }
 
     #[test]
-    fn test_extra_field() {
-        let out = Layout::from_file(path_from_root("tests/layout3.yaml"));
-        match out {
-            Ok(_) => assert!(false, "Data mistakenly accepted"),
-            Err(e) => {
-                let mut handled = false;
-                if let Error::Yaml(ye) = &e {
-                    handled = ye.to_string()
-                        .starts_with("unknown field `bad_field`");
-                };
-                if !handled {
-                    println!("Unexpected error {:?}", e);
-                    assert!(false)
-                }
-            }
-        }
+    fn test_layout_shift_view_missing() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a"
+shift_view: upper
+default_outline: { width: 1, height: 1 }
+"#).unwrap();
+        let (out, warnings) = layout.build(
+            layout::ArrangementKind::Base,
+            CollectWarnings(Vec::new()),
+        );
+        let out = out.unwrap();
+        let button = &out.views["base"].1
+            .get_rows()[0].1
+            .get_buttons()[0].1;
+        assert_eq!(button.preview_label, None);
+        assert_eq!(
+            warnings.0,
+            vec![LayoutWarning::MissingShiftView { view: "upper".into() }],
+        );
     }
-    
+
+    #[test]
+    fn test_layout_modal_view_missing() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a"
+modal_views: { base: true, emoji: true }
+default_outline: { width: 1, height: 1 }
+"#).unwrap();
+        let (out, warnings) = layout.build(
+            layout::ArrangementKind::Base,
+            CollectWarnings(Vec::new()),
+        );
+        assert_eq!(
+            out.unwrap().modal_views,
+            HashSet::from(["base".to_string()]),
+        );
+        assert_eq!(
+            warnings.0,
+            vec![LayoutWarning::MissingModalView { view: "emoji".into() }],
+        );
+    }
+
+    #[test]
+    fn test_layout_long_press() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "period"
+buttons:
+    period: { text: ".", long_press_text: "…" }
+default_outline: { width: 1, height: 1 }
+"#).unwrap();
+        let out = layout.build(layout::ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let button = &out.views["base"].1
+            .get_rows()[0].1
+            .get_buttons()[0].1;
+        let submitted = |action: &action::Action| match action {
+            action::Action::Submit { text: Some(text), keys: _ } => text.clone(),
+            other => panic!("Not a submission: {:?}", other),
+        };
+        assert_eq!(submitted(&button.action), CString::new(".").unwrap());
+        let long_press = button.long_press.as_ref().unwrap();
+        assert_eq!(submitted(&long_press.action), CString::new("…").unwrap());
+        assert_eq!(long_press.keycodes.len(), 1);
+        assert_ne!(long_press.keycodes, button.keycodes);
+    }
+
+    #[test]
+    fn test_label_from_id() {
+        assert_eq!(label_from_id("Shift_L").as_deref(), Some("Shift"));
+        assert_eq!(label_from_id("caps_lock").as_deref(), Some("Caps Lock"));
+        assert_eq!(label_from_id("a"), None);
+        assert_eq!(label_from_id("_"), None);
+        assert_eq!(label_from_id("_L"), None);
+
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "Shift_L Control_R"
+buttons:
+    Control_R: { label: "Ctrl" }
+default_outline: { width: 1, height: 1 }
+"#).unwrap();
+        let (out, warnings) = layout
+            .build(layout::ArrangementKind::Base, CollectWarnings(Vec::new()));
+        let out = out.unwrap();
+        let labels: Vec<_> = out.views["base"].1
+            .get_rows()[0].1
+            .get_buttons().iter()
+            .map(|(_x, button)| button.label.clone())
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                layout::Label::Text(CString::new("Shift").unwrap()),
+                layout::Label::Text(CString::new("Ctrl").unwrap()),
+            ],
+        );
+        assert_eq!(
+            warnings.0,
+            vec![LayoutWarning::LabelFromId {
+                button: "Shift_L".into(),
+                label: "Shift".into(),
+            }],
+        );
+    }
+
+    #[test]
+    fn test_codepoint_fallback_chars() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a period euro smile"
+buttons:
+    period: { text: "." }
+    euro: { text: "€" }
+    smile: { text: "🙂a" }
+"#).unwrap();
+        // "a" is a keysym name, "." and the rest aren't
+        assert_eq!(layout.codepoint_fallback_chars(), vec!['.', '€', '🙂']);
+
+        let layout = Layout {
+            named_keysyms: Some(true),
+            ..layout
+        };
+        // Those two have names: "period" and "EuroSign"
+        assert_eq!(layout.codepoint_fallback_chars(), vec!['🙂']);
+    }
+
     #[test]
     fn test_layout_punctuation() {
         let out = Layout::from_file(path_from_root("tests/layout_key1.yaml"))
             .unwrap()
-            .build(ProblemPanic).0
+            .build(layout::ArrangementKind::Base, ProblemPanic).0
             .unwrap();
         assert_eq!(
             out.views["base"].1
@@ -642,11 +5445,98 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_layout_from_fd() {
+        use std::os::unix::io::AsRawFd;
+
+        let file = fs::File::open(path_from_root("tests/layout_bom_crlf.yaml"))
+            .unwrap();
+        let layout = unsafe { Layout::from_fd(file.as_raw_fd()) }.unwrap();
+        assert_eq!(
+            layout.views["base"],
+            vec!["a b".to_string(), "c".to_string()],
+        );
+        // Still open
+        assert!(file.metadata().is_ok());
+    }
+
+    #[test]
+    fn test_keymap_file() {
+        let path = path_from_root("tests/layout_keymap_file.yaml");
+        let layout = Layout::from_file(path.clone()).unwrap();
+        let (out, warnings) = layout.build(
+            layout::ArrangementKind::Base,
+            CollectWarnings(Vec::new()),
+        );
+        let out = out.unwrap();
+        assert_eq!(warnings.0, Vec::new());
+        assert_eq!(
+            out.keymaps,
+            vec![CString::new(
+                fs::read_to_string(path_from_root("tests/layout_keymap_file.xkb"))
+                    .unwrap()
+            ).unwrap()],
+        );
+        let keycodes: Vec<_> = out.views["base"].1
+            .get_rows()[0].1
+            .get_buttons().iter()
+            .map(|(_x, button)| button.keycodes.clone())
+            .collect();
+        assert_eq!(
+            keycodes,
+            vec![
+                vec![KeyCode { code: 38, keymap_idx: 0 }],
+                vec![KeyCode { code: 10, keymap_idx: 0 }],
+            ],
+        );
+
+        let mut layout = Layout::from_file(path).unwrap();
+        layout.buttons.get_mut("one").unwrap().key = Some("<AE02>".into());
+        let (_out, warnings) = layout.build(
+            layout::ArrangementKind::Base,
+            CollectWarnings(Vec::new()),
+        );
+        assert_eq!(
+            warnings.0,
+            vec![LayoutWarning::KeyNotInKeymapFile {
+                button: "one".into(),
+                key: "<AE02>".into(),
+            }],
+        );
+    }
+
+    #[test]
+    fn test_keymap_file_missing() {
+        let mut layout = make_single_button_layout();
+        layout.keymap_file = Some("nonexistent.xkb".into());
+        let path = path_from_root("tests/layout.yaml");
+        assert!(matches!(
+            layout.read_keymap_file(&path),
+            Err(Error::KeymapFile(_, _)),
+        ));
+    }
+
+    #[test]
+    fn test_layout_bom_crlf() {
+        let out = Layout::from_file(path_from_root("tests/layout_bom_crlf.yaml"))
+            .unwrap()
+            .build(layout::ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let names: Vec<Vec<_>> = out.views["base"].1.get_rows().iter()
+            .map(|(_offset, row)| {
+                row.get_buttons().iter()
+                    .map(|(_x, button)| button.name.to_str().unwrap().to_owned())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(names, vec![vec!["a", "b"], vec!["c"]]);
+    }
+
     #[test]
     fn test_layout_unicode() {
         let out = Layout::from_file(path_from_root("tests/layout_key2.yaml"))
             .unwrap()
-            .build(ProblemPanic).0
+            .build(layout::ArrangementKind::Base, ProblemPanic).0
             .unwrap();
         assert_eq!(
             out.views["base"].1
@@ -662,7 +5552,7 @@ mod tests {
     fn test_layout_unicode_multi() {
         let out = Layout::from_file(path_from_root("tests/layout_key3.yaml"))
             .unwrap()
-            .build(ProblemPanic).0
+            .build(layout::ArrangementKind::Base, ProblemPanic).0
             .unwrap();
         assert_eq!(
             out.views["base"].1
@@ -678,7 +5568,7 @@ mod tests {
     fn test_layout_erase() {
         let out = Layout::from_file(path_from_root("tests/layout_erase.yaml"))
             .unwrap()
-            .build(ProblemPanic).0
+            .build(layout::ArrangementKind::Base, ProblemPanic).0
             .unwrap();
         assert_eq!(
             out.views["base"].1
@@ -689,6 +5579,139 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_button_name_whitespace() {
+        let (out, handler) = Layout::from_file(path_from_root("tests/layout_button_space.yaml"))
+            .unwrap()
+            .build(layout::ArrangementKind::Base, CollectWarnings(Vec::new()));
+        assert!(out.is_ok());
+        assert_eq!(
+            handler.0,
+            vec![LayoutWarning::ButtonNameWhitespace {
+                button: "two words".into(),
+            }],
+        );
+    }
+
+    #[test]
+    fn test_autocapitalize() {
+        let build = |autocapitalize| {
+            let mut layout = make_single_button_layout();
+            layout.autocapitalize = autocapitalize;
+            layout.build(layout::ArrangementKind::Base, ProblemPanic).0
+                .unwrap()
+                .autocapitalize
+        };
+        assert_eq!(build(Some(true)), true);
+        assert_eq!(build(None), false);
+    }
+
+    #[test]
+    fn test_long_press_ms() {
+        let build = |long_press_ms| {
+            let mut layout = make_single_button_layout();
+            layout.long_press_ms = long_press_ms;
+            let (out, warnings) = layout.build(
+                layout::ArrangementKind::Base,
+                CollectWarnings(Vec::new()),
+            );
+            (out.unwrap().long_press_ms, warnings.0)
+        };
+        assert_eq!(build(Some(800)), (800, Vec::new()));
+        assert_eq!(build(None), (layout::DEFAULT_LONG_PRESS_MS, Vec::new()));
+        assert_eq!(
+            build(Some(10)),
+            (
+                layout::DEFAULT_LONG_PRESS_MS,
+                vec![LayoutWarning::LongPressOutOfRange { ms: 10 }],
+            ),
+        );
+    }
+
+    #[test]
+    fn test_metadata() {
+        let layout = Layout::from_file(path_from_root("tests/layout_metadata.yaml"))
+            .unwrap();
+        assert_eq!(layout.name_display, Some("German".into()));
+        assert_eq!(layout.author, Some("Jane Doe".into()));
+        assert_eq!(layout.license, Some("CC0-1.0".into()));
+        let out = layout.build(layout::ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        assert_eq!(
+            out.metadata,
+            layout::Metadata {
+                name_display: Some(CString::new("German").unwrap()),
+                author: Some(CString::new("Jane Doe").unwrap()),
+                license: Some(CString::new("CC0-1.0").unwrap()),
+            },
+        );
+    }
+
+    #[test]
+    fn test_lenient() {
+        let path = path_from_root("tests/layout_unknown_field.yaml");
+        assert!(Layout::from_file(path.clone()).is_err());
+
+        let mut warnings = CollectWarnings(Vec::new());
+        let layout = Layout::from_file_lenient(path, &mut warnings).unwrap();
+        assert_eq!(layout.buttons["test"].text, Some("t".into()));
+        let unknown = |field: &str, place: &str| LayoutWarning::UnknownField {
+            field: field.into(),
+            place: place.into(),
+        };
+        assert_eq!(
+            warnings.0,
+            vec![
+                unknown("future_setting", "layout"),
+                unknown("corner_radius", "outline default"),
+                unknown("sparkle", "button test"),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let parse = |text| serde_yaml::from_str::<Layout>(text).unwrap();
+        let layout = parse(r#"
+views:
+    base: ["a b"]
+outlines:
+    default: { width: 1, height: 1 }
+buttons:
+    a: { text: "x" }
+    b: { keysym: "b" }
+"#);
+        let reformatted = parse(r#"
+# Same thing
+buttons:
+    b:
+        keysym: b
+    a:
+        text: x
+
+outlines: {default: {width: 1.0, height: 1}}
+views: {base: ["a b"]}
+"#);
+        let changed = parse(r#"
+views:
+    base: ["a b"]
+outlines:
+    default: { width: 1, height: 2 }
+buttons:
+    a: { text: "x" }
+    b: { keysym: "b" }
+"#);
+        assert_eq!(layout.content_hash(), reformatted.content_hash());
+        assert_ne!(layout.content_hash(), changed.content_hash());
+    }
+
+    #[test]
+    fn test_missing_buttons() {
+        let layout = Layout::from_file(path_from_root("tests/layout_missing_button.yaml"))
+            .unwrap();
+        assert_eq!(layout.missing_buttons(), vec!["bckspace".to_string()]);
+    }
+
     #[test]
     fn unicode_keysym() {
         let keysym = xkb::keysym_from_name(
@@ -705,17 +5728,12 @@ mod tests {
             create_action(
                 &hashmap!{
                     ".".into() => ButtonMeta {
-                        icon: None,
-                        keysym: None,
-                        text: None,
-                        action: None,
-                        modifier: None,
                         label: Some("test".into()),
-                        outline: None,
+                        ..ButtonMeta::default()
                     }
                 },
                 ".",
-                Vec::new(),
+                &ActionOptions::default(),
                 &mut ProblemPanic,
             ),
             crate::action::Action::Submit {
@@ -725,11 +5743,105 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_key_named_keysyms() {
+        assert_eq!(
+            create_action(
+                &HashMap::new(), ".",
+                &ActionOptions { named_keysyms: true, ..Default::default() },
+                &mut ProblemPanic,
+            ),
+            crate::action::Action::Submit {
+                text: Some(CString::new(".").unwrap()),
+                keys: vec!(crate::action::KeySym("period".into())),
+            },
+        );
+        // No name other than the code point
+        assert_eq!(
+            create_action(
+                &HashMap::new(), "\u{1F604}",
+                &ActionOptions { named_keysyms: true, ..Default::default() },
+                &mut ProblemPanic,
+            ),
+            crate::action::Action::Submit {
+                text: Some(CString::new("\u{1F604}").unwrap()),
+                keys: vec!(crate::action::KeySym("U1F604".into())),
+            },
+        );
+    }
+
+    #[test]
+    fn test_key_uppercase() {
+        let buttons = hashmap!{
+            "a".into() => ButtonMeta {
+                uppercase: Some(true),
+                ..ButtonMeta::default()
+            },
+            "ß".into() => ButtonMeta {
+                uppercase: Some(true),
+                ..ButtonMeta::default()
+            },
+            "1".into() => ButtonMeta {
+                uppercase: Some(true),
+                ..ButtonMeta::default()
+            },
+        };
+        assert_eq!(
+            create_action(
+                &HashMap::new(), "a",
+                &ActionOptions::default(),
+                &mut ProblemPanic,
+            ),
+            crate::action::Action::Submit {
+                text: Some(CString::new("a").unwrap()),
+                keys: vec!(crate::action::KeySym("a".into())),
+            },
+        );
+        assert_eq!(
+            create_action(
+                &buttons, "a",
+                &ActionOptions::default(),
+                &mut ProblemPanic,
+            ),
+            crate::action::Action::Submit {
+                text: Some(CString::new("A").unwrap()),
+                keys: vec!(crate::action::KeySym("A".into())),
+            },
+        );
+        // Some characters become longer
+        assert_eq!(
+            create_action(
+                &buttons, "ß",
+                &ActionOptions::default(),
+                &mut ProblemPanic,
+            ),
+            crate::action::Action::Submit {
+                text: Some(CString::new("SS").unwrap()),
+                keys: vec!(
+                    crate::action::KeySym("S".into()),
+                    crate::action::KeySym("S".into()),
+                ),
+            },
+        );
+        // No uppercase form at all
+        assert_eq!(
+            create_action(
+                &buttons, "1",
+                &ActionOptions::default(),
+                &mut ProblemPanic,
+            ),
+            crate::action::Action::Submit {
+                text: Some(CString::new("1").unwrap()),
+                keys: vec!(crate::action::KeySym("1".into())),
+            },
+        );
+    }
+
     #[test]
     fn test_layout_margins() {
         let out = Layout::from_file(path_from_root("tests/layout_margins.yaml"))
             .unwrap()
-            .build(ProblemPanic).0
+            .build(layout::ArrangementKind::Base, ProblemPanic).0
             .unwrap();
         assert_eq!(
             out.margins,
@@ -760,6 +5872,58 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_shared_keysyms() {
+        let actions = [
+            (
+                "ac",
+                action::Action::Submit {
+                    text: Some(CString::new("ac").unwrap()),
+                    keys: vec![
+                        action::KeySym("a".into()),
+                        action::KeySym("c".into()),
+                    ],
+                },
+            ),
+            (
+                "a",
+                action::Action::Submit {
+                    text: Some(CString::new("a").unwrap()),
+                    keys: vec![action::KeySym("a".into())],
+                },
+            ),
+            (
+                "c",
+                action::Action::Submit {
+                    text: Some(CString::new("ac").unwrap()),
+                    keys: vec![action::KeySym("c".into())],
+                },
+            ),
+        ];
+        assert_eq!(
+            find_shared_keysyms(&actions[..]),
+            vec![("a", "a", "ac")],
+        );
+    }
+
+    #[test]
+    fn test_identical_outlines() {
+        let layout: Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a"
+outlines:
+    default: { width: 1, height: 1 }
+    altline: { width: 2, height: 1 }
+    change-view: { width: 2, height: 1 }
+    wide: { width: 2, height: 1, wide: { width: 3 } }
+"#).unwrap();
+        assert_eq!(
+            find_identical_outlines(&layout.outlines),
+            vec![("altline", "change-view")],
+        );
+    }
+
     #[test]
     fn test_extract_symbols_erase() {
         let actions = [(
@@ -0,0 +1,90 @@
+/* Copyright (C) 2026 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+/*! Emoji shortcodes, like `:smile:`, for writing emoji layouts */
+
+/// Names as commonly used in chat applications.
+/// Sorted by name.
+const SHORTCODES: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("angry", "😠"),
+    ("blush", "😊"),
+    ("broken_heart", "💔"),
+    ("clap", "👏"),
+    ("cry", "😢"),
+    ("fire", "🔥"),
+    ("grin", "😁"),
+    ("heart", "❤️"),
+    ("heart_eyes", "😍"),
+    ("joy", "😂"),
+    ("kissing_heart", "😘"),
+    ("laughing", "😆"),
+    ("neutral_face", "😐"),
+    ("ok_hand", "👌"),
+    ("pray", "🙏"),
+    ("rage", "😡"),
+    ("relaxed", "☺️"),
+    ("scream", "😱"),
+    ("slightly_smiling_face", "🙂"),
+    ("sleeping", "😴"),
+    ("smile", "😄"),
+    ("smiley", "😃"),
+    ("smirk", "😏"),
+    ("sob", "😭"),
+    ("sparkles", "✨"),
+    ("star", "⭐"),
+    ("stuck_out_tongue", "😛"),
+    ("sunglasses", "😎"),
+    ("sweat_smile", "😅"),
+    ("tada", "🎉"),
+    ("thinking", "🤔"),
+    ("thumbsdown", "👎"),
+    ("thumbsup", "👍"),
+    ("unamused", "😒"),
+    ("wave", "👋"),
+    ("wink", "😉"),
+    ("yum", "😋"),
+];
+
+/// Returns the name between the colons
+/// if the text has the form of a shortcode
+pub fn get_name(text: &str) -> Option<&str> {
+    let name = text.strip_prefix(':')?.strip_suffix(':')?;
+    let is_name_char = |c: char| {
+        c.is_ascii_lowercase() || c.is_ascii_digit() || "_+-".contains(c)
+    };
+    match !name.is_empty() && name.chars().all(is_name_char) {
+        true => Some(name),
+        false => None,
+    }
+}
+
+/// Finds the emoji for the name of a shortcode
+pub fn find(name: &str) -> Option<&'static str> {
+    SHORTCODES.binary_search_by_key(&name, |(name, _emoji)| name)
+        .ok()
+        .map(|index| SHORTCODES[index].1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_sorted() {
+        let names: Vec<_> = SHORTCODES.iter().map(|(name, _)| *name).collect();
+        let mut sorted = names.clone();
+        sorted.sort();
+        assert_eq!(names, sorted);
+    }
+
+    #[test]
+    fn colons_only() {
+        assert_eq!(get_name(":"), None);
+        assert_eq!(get_name("::"), None);
+        assert_eq!(get_name(":a b:"), None);
+        assert_eq!(get_name(":+1:"), Some("+1"));
+    }
+}
@@ -5,14 +5,14 @@ use cairo;
 use crate::action::{ Action, Modifier };
 use crate::keyboard;
 use crate::layout::{ Button, ButtonPosition, Label, LatchedState, Layout };
-use crate::layout::c::{ Bounds, EekGtkKeyboard, Point };
+use crate::layout::c::{ Bounds, EekGtkKeyboard, Point, Transformation };
 use crate::submission::c::Submission as CSubmission;
 
 use glib::translate::FromGlibPtrNone;
 use gtk::prelude::WidgetExt;
 
 use std::collections::HashSet;
-use std::ffi::CStr;
+use std::ffi::{ CStr, CString };
 use std::ptr;
 
 mod c {
@@ -60,6 +60,7 @@ mod c {
             outline_name: *const c_char,
             locked_class: *const c_char,
             pressed: u64,
+            disabled: u64,
         ) -> GtkStyleContext;
 
         #[allow(improper_ctypes)]
@@ -87,36 +88,57 @@ mod c {
         let cr = unsafe { cairo::Context::from_raw_none(cr) };
         let active_modifiers = submission.get_active_modifiers();
 
-        layout.foreach_visible_button(|offset, button, (row, position_in_row)| {
-            // TODO: this iterator copies string indices way too much.
-            // For efficiency, it would be better to draw pressed buttons from the list first,
-            // and then iterate the rest without having to look up their indices.
-            let state = layout.state.active_buttons.get(&ButtonPosition {
-                view: layout.state.current_view.clone(),
-                row,
-                position_in_row,
-            });
-
-            let locked = LockedStyle::from_action(
-                &button.action,
-                &active_modifiers,
-                layout.get_view_latched(),
-                &layout.state.current_view,
-            );
-            if state.pressed == keyboard::PressType::Pressed
+        layout.foreach_visible_button(|offset, button, index| {
+            let (pressed, locked)
+                = get_style(layout, &active_modifiers, button, index);
+            if pressed == keyboard::PressType::Pressed
                 || locked != LockedStyle::Free
             {
                 render_button_at_position(
                     renderer, &cr,
-                    x_offset, y_offset,
-                    x_scale, y_scale,
+                    &Transformation {
+                        origin_x: x_offset,
+                        origin_y: y_offset,
+                        scale_x: x_scale,
+                        scale_y: y_scale,
+                    },
                     offset,
                     button,
-                    state.pressed, locked,
+                    &ButtonLook {
+                        label: button.get_label(&layout.state.current_view),
+                        pressed,
+                        locked,
+                        disabled: layout.is_button_disabled(
+                            &button.name.to_string_lossy(),
+                        ),
+                    },
                 );
             }
         })
     }
+
+    /// Calls `callback` with the name of each button
+    /// which `get_active_keys` returns.
+    /// The name is only valid during the call.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_foreach_active_key(
+        layout: *const Layout,
+        submission: CSubmission,
+        callback: extern "C" fn(name: *const c_char, user_data: *mut c_void),
+        user_data: *mut c_void,
+    ) {
+        let layout = unsafe { &*layout };
+        let submission = submission.clone_ref();
+        let submission = submission.borrow();
+        let active_modifiers = submission.get_active_modifiers();
+        for name in get_active_keys(layout, &active_modifiers) {
+            // Names come from CStrings, so they can't contain a nul.
+            if let Ok(name) = CString::new(name) {
+                callback(name.as_ptr(), user_data);
+            }
+        }
+    }
     
     #[no_mangle]
     pub extern "C"
@@ -133,17 +155,73 @@ mod c {
         layout.foreach_visible_button(|offset, button, _index| {
             render_button_at_position(
                 renderer, &cr,
-                x_offset, y_offset,
-                x_scale, y_scale,
+                &Transformation {
+                    origin_x: x_offset,
+                    origin_y: y_offset,
+                    scale_x: x_scale,
+                    scale_y: y_scale,
+                },
                 offset,
                 button,
-                keyboard::PressType::Released,
-                LockedStyle::Free,
+                &ButtonLook {
+                    label: button.get_label(&layout.state.current_view),
+                    pressed: keyboard::PressType::Released,
+                    locked: LockedStyle::Free,
+                    disabled: layout.is_button_disabled(
+                        &button.name.to_string_lossy(),
+                    ),
+                },
             );
         })
     }
 }
 
+fn get_style(
+    layout: &Layout,
+    active_modifiers: &HashSet<Modifier>,
+    button: &Button,
+    (row, position_in_row): (usize, usize),
+) -> (keyboard::PressType, LockedStyle) {
+    // TODO: this copies string indices way too much.
+    // For efficiency, it would be better to draw pressed buttons from the list first,
+    // and then iterate the rest without having to look up their indices.
+    let state = layout.state.active_buttons.get(&ButtonPosition {
+        view: layout.state.current_view.clone(),
+        row,
+        position_in_row,
+    });
+
+    let locked = LockedStyle::from_action(
+        &button.action,
+        active_modifiers,
+        layout.get_view_latched(),
+        &layout.state.current_view,
+    );
+    (state.pressed, locked)
+}
+
+/// Returns the names of the visible buttons which are pressed or locked,
+/// in the order of their names.
+/// A button appearing more than once in the view is listed once.
+pub fn get_active_keys(
+    layout: &Layout,
+    active_modifiers: &HashSet<Modifier>,
+) -> Vec<String> {
+    let mut names = Vec::new();
+    layout.foreach_visible_button(|_offset, button, index| {
+        let (pressed, locked)
+            = get_style(layout, active_modifiers, button, index);
+        if pressed == keyboard::PressType::Pressed
+            || locked != LockedStyle::Free
+        {
+            names.push(button.name.to_string_lossy().into_owned());
+        }
+    });
+    names.sort();
+    names.dedup();
+    names
+}
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 enum LockedStyle {
     Free,
@@ -178,38 +256,54 @@ impl LockedStyle {
     }
 }
 
-/// Renders a button at a position (button's own bounds ignored)
+/// What changes in a button's appearance depending on the state
+struct ButtonLook<'a> {
+    label: &'a Label,
+    pressed: keyboard::PressType,
+    locked: LockedStyle,
+    disabled: bool,
+}
+
+/// Renders a button at a position, shifted by the button's own bounds
 fn render_button_at_position(
     renderer: c::EekRenderer,
     cr: &cairo::Context,
-    x_offset: f64, y_offset: f64,
-    x_scale: f64, y_scale: f64,
+    transformation: &Transformation,
     position: Point,
     button: &Button,
-    pressed: keyboard::PressType,
-    locked: LockedStyle,
+    look: &ButtonLook,
 ) {
+    let Transformation {
+        origin_x: x_offset,
+        origin_y: y_offset,
+        scale_x: x_scale,
+        scale_y: y_scale,
+    } = *transformation;
+    let bounds = button.get_bounds();
     cr.save().unwrap();
-    cr.translate(position.x * x_scale + x_offset, position.y * y_scale + y_offset);
+    cr.translate(
+        (position.x + bounds.x) * x_scale + x_offset,
+        (position.y + bounds.y) * y_scale + y_offset,
+    );
     cr.rectangle(
         0.0, 0.0,
-        button.size.width * x_scale,
-        button.size.height * y_scale,
+        bounds.width * x_scale,
+        bounds.height * y_scale,
     );
     cr.clip();
 
     let scale_factor = unsafe {
         c::eek_renderer_get_scale_factor(renderer)
     };
-    let bounds = button.get_bounds();
+    // The offset is already applied by the translation.
     let scaled_and_offset_bounds = Bounds {
-        x: bounds.x * x_scale + x_offset,
-        y: bounds.y * y_scale + y_offset,
+        x: x_offset,
+        y: y_offset,
         width: bounds.width * x_scale,
         height: bounds.height * y_scale,
     };
 
-    let (label_c, icon_name_c) = match &button.label {
+    let (label_c, icon_name_c) = match look.label {
         Label::Text(text) => (text.as_ptr(), ptr::null()),
         Label::IconName(name) => {
             let l = unsafe {
@@ -224,8 +318,7 @@ fn render_button_at_position(
     with_button_context(
         renderer,
         button,
-        pressed,
-        locked,
+        look,
         |ctx| unsafe {
             // TODO: split into separate procedures:
             // draw outline, draw label, draw icon.
@@ -248,12 +341,11 @@ fn render_button_at_position(
 fn with_button_context<R, F: FnOnce(&c::GtkStyleContext) -> R>(
     renderer: c::EekRenderer,
     button: &Button,
-    pressed: keyboard::PressType,
-    locked: LockedStyle,
+    look: &ButtonLook,
     operation: F,
 ) -> R {
     let outline_name_c = button.outline_name.as_ptr();
-    let locked_class_c = match locked {
+    let locked_class_c = match look.locked {
         LockedStyle::Free => ptr::null(),
         LockedStyle::Locked => unsafe {
             CStr::from_bytes_with_nul_unchecked(b"locked\0").as_ptr()
@@ -269,7 +361,8 @@ fn with_button_context<R, F: FnOnce(&c::GtkStyleContext) -> R>(
             button.name.as_ptr(),
             outline_name_c,
             locked_class_c,
-            pressed as u64,
+            look.pressed.to_u32() as u64,
+            look.disabled as u64,
         )
     };
     
@@ -295,6 +388,45 @@ pub fn queue_redraw(keyboard: EekGtkKeyboard) {
 mod test {
     use super::*;
 
+    use crate::data::parsing;
+    use crate::imservice::ContentPurpose;
+    use crate::layout::ArrangementKind;
+    use crate::logging::ProblemPanic;
+
+    #[test]
+    fn test_active_keys_once() {
+        let data: parsing::Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "Shift a"
+    upper:
+        - "Shift A Shift"
+outlines:
+    default: { width: 1, height: 1 }
+buttons:
+    Shift:
+        action:
+            locking:
+                lock_view: upper
+                unlock_view: base
+"#).unwrap();
+        let data = data.build(ArrangementKind::Base, ProblemPanic).0.unwrap();
+        let mut layout = Layout::new(
+            data,
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+        );
+        assert_eq!(
+            get_active_keys(&layout, &HashSet::new()),
+            Vec::<String>::new(),
+        );
+        layout.state.current_view = "upper".into();
+        assert_eq!(
+            get_active_keys(&layout, &HashSet::new()),
+            vec!["Shift".to_string()],
+        );
+    }
+
     #[test]
     fn test_exit_only() {
         assert_eq!(
@@ -304,6 +436,7 @@ mod test {
                     unlock: "a".into(),
                     latches: true,
                     looks_locked_from: vec!["b".into()],
+                    double_tap_lock: false,
                 },
                 &HashSet::new(),
                 &LatchedState::FromView("b".into()),
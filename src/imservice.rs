@@ -405,6 +405,15 @@ impl IMService {
         self.current.active
     }
 
+    /// True also when the application doesn't share the surrounding text
+    pub fn is_field_empty(&self) -> bool {
+        self.current.surrounding_text.as_bytes().is_empty()
+    }
+
+    pub fn is_multiline(&self) -> bool {
+        self.current.content_hint.contains(ContentHint::MULTILINE)
+    }
+
     fn send_event(&self) {
         let state = &self.current;
         let timestamp = Instant::now();
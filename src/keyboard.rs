@@ -3,13 +3,18 @@
 
 use crate::action::Action;
 use crate::layout;
+use crate::logging;
+use crate::submission::Timestamp;
 use crate::util;
 use std::collections::HashMap;
+use std::env;
 use std::fmt;
 use std::io;
 use std::mem;
 use std::ptr;
 use std::string::FromUtf8Error;
+use std::sync::OnceLock;
+use xkbcommon::xkb;
 
 // Traits
 use std::io::Write;
@@ -21,6 +26,27 @@ pub enum PressType {
     Pressed = 1,
 }
 
+impl PressType {
+    /// The value passed to C and to Wayland,
+    /// as in `wl_keyboard.key_state`: 0 for released, 1 for pressed.
+    /// Stays the same if the variants change.
+    pub fn to_u32(self) -> u32 {
+        match self {
+            PressType::Released => 0,
+            PressType::Pressed => 1,
+        }
+    }
+
+    /// The reverse of `to_u32`
+    pub fn from_u32(num: u32) -> Option<PressType> {
+        match num {
+            0 => Some(PressType::Released),
+            1 => Some(PressType::Pressed),
+            _ => None,
+        }
+    }
+}
+
 /// The extended, unambiguous layout-keycode
 #[derive(Debug, Clone, PartialEq)]
 pub struct KeyCode {
@@ -69,6 +95,14 @@ pub struct Key {
 #[derive(Debug, Clone)]
 pub struct KeyState {
     pub pressed: PressType,
+    /// When the last accepted press happened.
+    /// Only tracked for buttons which ignore presses coming too soon,
+    /// or which do something else when held.
+    pub pressed_at: Option<Timestamp>,
+    /// The press came too soon after the last one, and submits nothing
+    pub bounced: bool,
+    /// The long press effect was submitted, so releasing submits nothing
+    pub held: bool,
 }
 
 impl KeyState {
@@ -89,25 +123,96 @@ impl KeyState {
     }
 }
 
-/// Sorts an iterator by converting it to a Vector and back
+/// Describes a key press for debugging.
+/// Unless `show_text` is set, anything that could reveal the typed text
+/// is left out, including keycodes: only the kind of button remains.
+fn describe_press(
+    name: &str,
+    keycodes: &[KeyCode],
+    action: &Action,
+    show_text: bool,
+) -> String {
+    let keysyms = match action {
+        Action::Submit { text: _, keys } => keys.as_slice(),
+        _ => &[],
+    };
+    if show_text {
+        let codes: Vec<String> = keycodes.iter()
+            .map(|KeyCode { code, keymap_idx }| format!("{}@{}", code, keymap_idx))
+            .collect();
+        let syms: Vec<&str> = keysyms.iter().map(|k| k.0.as_str()).collect();
+        format!(
+            "Pressed {}: keycodes [{}], keysyms [{}]",
+            name, codes.join(", "), syms.join(", "),
+        )
+    } else {
+        let kind = match action {
+            Action::Submit { .. } => "text",
+            _ => "special",
+        };
+        format!("Pressed <redacted> {} button, {} keycodes", kind, keycodes.len())
+    }
+}
+
+/// Prints the pressed key if SQUEEKBOARD_LOG_KEYS is set.
+/// Names, keycodes and keysyms are only printed
+/// if SQUEEKBOARD_LOG_KEYS_TEXT is set too,
+/// because they reveal what's being typed.
+/// The environment is only read on the first press.
+pub fn log_press(name: &str, keycodes: &[KeyCode], action: &Action) {
+    // None when not logging, otherwise whether to show the text
+    static LOG_KEYS: OnceLock<Option<bool>> = OnceLock::new();
+    let show_text = LOG_KEYS.get_or_init(|| {
+        env::var_os("SQUEEKBOARD_LOG_KEYS").map(|_|
+            env::var_os("SQUEEKBOARD_LOG_KEYS_TEXT").is_some()
+        )
+    });
+    let show_text = match show_text {
+        Some(show_text) => *show_text,
+        None => return,
+    };
+    log_print!(
+        logging::Level::Debug,
+        "{}",
+        describe_press(name, keycodes, action, show_text),
+    );
+}
+
+/// Sorts an iterator by converting it to a Vector and back.
+/// Repeated items are dropped.
 fn sorted<'a, I: Iterator<Item=String>>(
     iter: I
 ) -> impl Iterator<Item=String> {
     let mut v: Vec<String> = iter.collect();
     v.sort();
+    v.dedup();
     v.into_iter()
 }
 
-/// Generates a mapping where each key gets a keycode, starting from ~~8~~
+/// Keycodes which generated keys can use, starting from ~~8~~
 /// HACK: starting from 9, because 8 results in keycode 0,
-/// which the compositor likes to discard
+/// which the compositor likes to discard.
+pub fn free_keycodes(reserved: &[u32]) -> Vec<u32> {
+    (9..255)
+        .filter(|code| !reserved.contains(code))
+        .collect()
+}
+
+/// Generates a mapping where each key gets a free keycode.
+/// Keycodes in `reserved` are skipped,
+/// so `free_keycodes` must leave some.
 pub fn generate_keycodes<'a, C: IntoIterator<Item=String>>(
     key_names: C,
+    reserved: &[u32],
 ) -> HashMap<String, KeyCode> {
+    let free = free_keycodes(reserved);
+    // Cycling through nothing would never end
+    assert!(!free.is_empty(), "No free keycodes left");
     HashMap::from_iter(
         // Sort to remove a source of indeterminism in keycode assignment.
+        // Otherwise the same layout could get a different keymap each time.
         sorted(key_names.into_iter())
-            .zip(util::cycle_count(9..255))
+            .zip(util::cycle_count(free.into_iter()))
             .map(|(name, (code, keymap_idx))| (
                 String::from(name),
                 KeyCode { code, keymap_idx },
@@ -119,6 +224,9 @@ pub fn generate_keycodes<'a, C: IntoIterator<Item=String>>(
 pub enum FormattingError {
     Utf(FromUtf8Error),
     Format(io::Error),
+    /// The layout has no outlines and asks not to make one up,
+    /// so there's nothing to build
+    NoOutlines,
 }
 
 impl fmt::Display for FormattingError {
@@ -126,6 +234,10 @@ impl fmt::Display for FormattingError {
         match self {
             FormattingError::Utf(e) => write!(f, "UTF: {}", e),
             FormattingError::Format(e) => write!(f, "Format: {}", e),
+            FormattingError::NoOutlines => write!(
+                f,
+                "No outlines defined, add at least a \"default\" one",
+            ),
         }
     }
 }
@@ -136,11 +248,84 @@ impl From<io::Error> for FormattingError {
     }
 }
 
+/// Names given to the sections of generated keymaps,
+/// for compositors which expect particular XKB identifiers.
+/// They don't change which keys are in the keymap.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeymapNames {
+    /// Names the key codes section, like "evdev"
+    pub rules: String,
+    /// Added to the key codes section name, like "pc105"
+    pub model: Option<String>,
+    /// Names the symbols section, like "us"
+    pub layout: String,
+}
+
+impl Default for KeymapNames {
+    fn default() -> Self {
+        KeymapNames {
+            rules: "squeekboard".into(),
+            model: None,
+            layout: "squeekboard".into(),
+        }
+    }
+}
+
+impl KeymapNames {
+    /// Whether the name can be written in a keymap section header
+    pub fn is_valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && name.chars().all(|c| {
+                c.is_ascii_graphic() && !"\"\\(){}".contains(c)
+            })
+    }
+
+    fn keycodes_name(&self) -> String {
+        match &self.model {
+            Some(model) => format!("{}({})", self.rules, model),
+            None => self.rules.clone(),
+        }
+    }
+}
+
+/// A single key in the symbols section of a keymap
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeymapEntry {
+    /// Without the angle brackets, e.g. "I9"
+    pub key_name: String,
+    /// Keysym name. Without it, the key is declared as having no symbol.
+    pub symbol_name: Option<String>,
+    /// Keysym name on level 2, selected with Shift.
+    pub shift_symbol_name: Option<String>,
+}
+
+impl KeymapEntry {
+    pub fn to_xkb_string(&self) -> String {
+        let symbol_name = self.symbol_name.as_deref().unwrap_or("NoSymbol");
+        match &self.shift_symbol_name {
+            Some(shifted) => format!(
+                "key <{}> {{ type= \"TWO_LEVEL\", [ {}, {} ] }};",
+                self.key_name,
+                symbol_name,
+                shifted,
+            ),
+            None => format!(
+                "key <{}> {{ [ {} ] }};",
+                self.key_name,
+                symbol_name,
+            ),
+        }
+    }
+}
+
 /// Index is the key code, String is the occupant.
 /// Starts all empty.
 /// https://gitlab.freedesktop.org/xorg/xserver/-/issues/260
 type SingleKeyMap = [Option<String>; 256];
 
+/// Keysym names on level 2, keyed by the level 1 keysym name
+pub type ShiftLevels = HashMap<String, String>;
+
 fn single_key_map_new() -> SingleKeyMap {
     // Why can't we just initialize arrays without tricks -_- ?
     // Inspired by
@@ -157,9 +342,15 @@ fn single_key_map_new() -> SingleKeyMap {
     }
 }
 
-pub fn generate_keymaps(symbolmap: HashMap::<String, KeyCode>)
-    -> Result<Vec<String>, FormattingError>
-{
+/// Keys from `base_keys` are present in every keymap.
+/// They should not share keycodes with `symbolmap`.
+/// Keys from `symbolmap` found in `shift_levels` get a second level.
+pub fn generate_keymaps(
+    symbolmap: HashMap::<String, KeyCode>,
+    shift_levels: &ShiftLevels,
+    base_keys: &[(u32, String)],
+    names: &KeymapNames,
+) -> Result<Vec<String>, FormattingError> {
     let mut bins: Vec<SingleKeyMap> = Vec::new();
     
     for (name, KeyCode { code, keymap_idx }) in symbolmap.into_iter() {
@@ -172,26 +363,95 @@ pub fn generate_keymaps(symbolmap: HashMap::<String, KeyCode>)
         bins[keymap_idx][code as usize] = Some(name);
     }
 
+    if bins.is_empty() && !base_keys.is_empty() {
+        bins.push(single_key_map_new());
+    }
+    for bin in &mut bins {
+        for (code, name) in base_keys {
+            bin[*code as usize] = Some(name.clone());
+        }
+    }
+
     let mut out = Vec::new();
     for bin in bins {
-        out.push(generate_keymap(&bin)?);
+        out.push(generate_keymap(&bin, shift_levels, names)?);
     }
     Ok(out)
 }
 
-/// Generates a de-facto single level keymap.
+/// Reads the keysym of every key in the keymap,
+/// with the keycode between 9 and 254.
+/// Only the first level of the first layout is taken.
+/// Returns None if the keymap doesn't compile.
+pub fn read_keymap_keys(keymap_str: &str) -> Option<Vec<(u32, String)>> {
+    let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+    let keymap = xkb::Keymap::new_from_string(
+        &context,
+        keymap_str.into(),
+        xkb::KEYMAP_FORMAT_TEXT_V1,
+        xkb::KEYMAP_COMPILE_NO_FLAGS,
+    )?;
+    let state = xkb::State::new(&keymap);
+    let first = u32::max(keymap.min_keycode(), 9);
+    let last = u32::min(keymap.max_keycode(), 254);
+    Some(
+        (first..=last)
+            .map(|code| (code, state.key_get_one_sym(code)))
+            .filter(|(_code, keysym)| *keysym != xkb::KEY_NoSymbol)
+            .map(|(code, keysym)| (code, xkb::keysym_get_name(keysym)))
+            .collect()
+    )
+}
+
+/// Finds the keycodes of keys by their names, e.g. "AE01".
+/// Angle brackets around the names are ignored.
+/// Returns None if the keymap doesn't compile.
+pub fn find_keys_by_name(keymap_str: &str, names: &[&str])
+    -> Option<Vec<Option<u32>>>
+{
+    let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+    let keymap = xkb::Keymap::new_from_string(
+        &context,
+        keymap_str.into(),
+        xkb::KEYMAP_FORMAT_TEXT_V1,
+        xkb::KEYMAP_COMPILE_NO_FLAGS,
+    )?;
+    Some(
+        names.iter()
+            .map(|name| {
+                let name = name.strip_prefix('<')
+                    .and_then(|name| name.strip_suffix('>'))
+                    .unwrap_or(name);
+                keymap.key_by_name(name)
+            })
+            .collect()
+    )
+}
+
+/// Generates a keymap without any keys
+pub fn generate_empty_keymap(names: &KeymapNames)
+    -> Result<String, FormattingError>
+{
+    generate_keymap(&single_key_map_new(), &ShiftLevels::new(), names)
+}
+
+/// Generates a keymap where only keys found in `shift_levels`
+/// have a second level.
 /// Key codes must not repeat and must remain between 9 and 255.
 fn generate_keymap(
     symbolmap: &SingleKeyMap,
+    shift_levels: &ShiftLevels,
+    names: &KeymapNames,
 ) -> Result<String, FormattingError> {
     let mut buf: Vec<u8> = Vec::new();
     writeln!(
         buf,
         "xkb_keymap {{
 
-    xkb_keycodes \"squeekboard\" {{
+    xkb_keycodes \"{}\" {{
         minimum = 8;
-        maximum = 255;"
+        maximum = 255;",
+        names.keycodes_name(),
     )?;
 
     let pairs: Vec<(&String, usize)> = symbolmap.iter()
@@ -220,18 +480,18 @@ fn generate_keymap(
         indicator 1 = \"Caps Lock\"; // Xwayland won't accept without it.
     }};
     
-    xkb_symbols \"squeekboard\" {{
-"
+    xkb_symbols \"{}\" {{
+",
+        names.layout,
     )?;
     
     for (name, keycode) in pairs {
-        write!(
-            buf,
-            "
-key <I{}> {{ [ {} ] }};",
-            keycode,
-            name,
-        )?;
+        let entry = KeymapEntry {
+            key_name: format!("I{}", keycode),
+            symbol_name: Some(name.clone()),
+            shift_symbol_name: shift_levels.get(name).cloned(),
+        };
+        write!(buf, "\n{}", entry.to_xkb_string())?;
     }
 
     writeln!(
@@ -248,7 +508,10 @@ key <I{}> {{ [ {} ] }};",
             level_name[Level1]= \"Any\";
         }};
         type \"TWO_LEVEL\" {{
+            modifiers= Shift;
+            map[Shift]= Level2;
             level_name[Level1]= \"Base\";
+            level_name[Level2]= \"Shift\";
         }};
         type \"ALPHABETIC\" {{
             level_name[Level1]= \"Base\";
@@ -279,15 +542,31 @@ key <I{}> {{ [ {} ] }};",
 mod tests {
     use super::*;
     
+    use crate::action::KeySym;
     use xkbcommon::xkb;
 
+    #[test]
+    fn press_type_u32() {
+        for press in [PressType::Released, PressType::Pressed] {
+            assert_eq!(PressType::from_u32(press.to_u32()), Some(press));
+        }
+        assert_eq!(PressType::Released.to_u32(), 0);
+        assert_eq!(PressType::Pressed.to_u32(), 1);
+        assert_eq!(PressType::from_u32(2), None);
+    }
+
     #[test]
     fn test_keymap_single_resolve() {
         let mut key_map = single_key_map_new();
         key_map[9] = Some("a".into());
         key_map[10] = Some("c".into());
 
-        let keymap_str = generate_keymap(&key_map).unwrap();
+        let keymap_str = generate_keymap(
+            &key_map,
+            &ShiftLevels::new(),
+            &KeymapNames::default(),
+        )
+            .unwrap();
 
         let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
 
@@ -304,11 +583,69 @@ mod tests {
         assert_eq!(state.key_get_one_sym(10), xkb::KEY_c);
     }
 
+    #[test]
+    fn test_keymap_entry() {
+        let entry = KeymapEntry {
+            key_name: "I9".into(),
+            symbol_name: Some("a".into()),
+            shift_symbol_name: None,
+        };
+        assert_eq!(entry.to_xkb_string(), "key <I9> { [ a ] };");
+
+        let entry = KeymapEntry {
+            key_name: "I10".into(),
+            symbol_name: None,
+            shift_symbol_name: None,
+        };
+        assert_eq!(entry.to_xkb_string(), "key <I10> { [ NoSymbol ] };");
+    }
+
+    #[test]
+    fn test_keymap_shift_level() {
+        let entry = KeymapEntry {
+            key_name: "I9".into(),
+            symbol_name: Some("a".into()),
+            shift_symbol_name: Some("A".into()),
+        };
+        assert_eq!(
+            entry.to_xkb_string(),
+            "key <I9> { type= \"TWO_LEVEL\", [ a, A ] };",
+        );
+
+        let keymaps = generate_keymaps(
+            hashmap!(
+                "a".into() => KeyCode { keymap_idx: 0, code: 9 },
+                "b".into() => KeyCode { keymap_idx: 0, code: 10 },
+            ),
+            &hashmap!("a".into() => "A".into()),
+            &[],
+            &KeymapNames::default(),
+        ).unwrap();
+
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+
+        let keymap = xkb::Keymap::new_from_string(
+            &context,
+            keymaps[0].clone(),
+            xkb::KEYMAP_FORMAT_TEXT_V1,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        ).expect("Failed to create keymap");
+
+        assert_eq!(keymap.num_levels_for_key(9, 0), 2);
+        assert_eq!(keymap.num_levels_for_key(10, 0), 1);
+        assert_eq!(keymap.key_get_syms_by_level(9, 0, 1), &[xkb::KEY_A]);
+    }
+
     #[test]
     fn test_keymap_second_resolve() {
-        let keymaps = generate_keymaps(hashmap!(
-            "a".into() => KeyCode { keymap_idx: 1, code: 9 },
-        )).unwrap();
+        let keymaps = generate_keymaps(
+            hashmap!(
+                "a".into() => KeyCode { keymap_idx: 1, code: 9 },
+            ),
+            &ShiftLevels::new(),
+            &[],
+            &KeymapNames::default(),
+        ).unwrap();
 
         let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
 
@@ -324,12 +661,89 @@ mod tests {
         assert_eq!(state.key_get_one_sym(9), xkb::KEY_a);
     }
 
+    #[test]
+    fn test_keymap_merge_base() {
+        let base = generate_keymaps(
+            hashmap!(
+                "Escape".into() => KeyCode { keymap_idx: 0, code: 9 },
+            ),
+            &ShiftLevels::new(),
+            &[],
+            &KeymapNames::default(),
+        ).unwrap();
+        let base_keys = read_keymap_keys(&base[0]).unwrap();
+        assert_eq!(base_keys, vec![(9, "Escape".to_string())]);
+
+        let reserved: Vec<u32> = base_keys.iter()
+            .map(|(code, _name)| *code)
+            .collect();
+        let symbolmap = generate_keycodes(vec!["a".to_string()], &reserved);
+        assert_eq!(symbolmap["a"], KeyCode { keymap_idx: 0, code: 10 });
+
+        let keymaps = generate_keymaps(
+            symbolmap,
+            &ShiftLevels::new(),
+            &base_keys,
+            &KeymapNames::default(),
+        ).unwrap();
+
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+
+        let keymap = xkb::Keymap::new_from_string(
+            &context,
+            keymaps[0].clone(),
+            xkb::KEYMAP_FORMAT_TEXT_V1,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        ).expect("Failed to create keymap");
+
+        let state = xkb::State::new(&keymap);
+
+        assert_eq!(state.key_get_one_sym(9), xkb::KEY_Escape);
+        assert_eq!(state.key_get_one_sym(10), xkb::KEY_a);
+    }
+
+    #[test]
+    fn test_describe_press_redacted() {
+        let keycodes = [KeyCode { code: 9, keymap_idx: 0 }];
+        let action = Action::Submit {
+            text: None,
+            keys: vec![KeySym("a".into())],
+        };
+        assert_eq!(
+            describe_press("a", &keycodes, &action, false),
+            "Pressed <redacted> text button, 1 keycodes",
+        );
+        assert_eq!(
+            describe_press("a", &keycodes, &action, true),
+            "Pressed a: keycodes [9@0], keysyms [a]",
+        );
+        assert_eq!(
+            describe_press("BackSpace", &[], &Action::Erase, false),
+            "Pressed <redacted> special button, 0 keycodes",
+        );
+    }
+
+    #[test]
+    fn test_keycodes_repeated() {
+        let keycodes = generate_keycodes(
+            vec!["b".to_string(), "a".into(), "b".into()],
+            &[],
+        );
+        assert_eq!(
+            keycodes,
+            hashmap!{
+                "a".into() => KeyCode { code: 9, keymap_idx: 0 },
+                "b".into() => KeyCode { code: 10, keymap_idx: 0 },
+            },
+        );
+    }
+
     #[test]
     fn test_symbolmap_overflow() {
         // The 257th key (U1101) is interesting.
         // Use Unicode encoding for being able to use in xkb keymaps.
         let keynames = (0..258).map(|num| format!("U{:04X}", 0x1000 + num));
-        let keycodes = generate_keycodes(keynames);
+        let keycodes = generate_keycodes(keynames, &[]);
         
         // test now
         let code = keycodes.get("U1101").expect("Did not find the tested keysym");
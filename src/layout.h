@@ -33,8 +33,58 @@ struct transformation squeek_layout_calculate_transformation(
         double allocation_width, double allocation_size);
 
 struct squeek_layout *squeek_load_layout(const char *name, uint32_t type, uint32_t variant_type, const char *overlay_name);
+// Returns NULL on failure. The descriptor is not closed.
+struct squeek_layout *squeek_load_layout_from_fd(int fd, uint32_t type, uint32_t variant_type);
+uint32_t squeek_layout_exists(const char *name, enum squeek_arrangement_kind kind);
 enum squeek_arrangement_kind squeek_layout_get_kind(const struct squeek_layout *);
 uint32_t squeek_layout_get_purpose(const struct squeek_layout *);
+uint32_t squeek_layout_get_autocapitalize(const struct squeek_layout *);
+uint32_t squeek_layout_file_changed(const char *name, uint32_t type, uint32_t variant_type, const char *overlay_name);
+const char *squeek_layout_get_a11y_label_at(const struct squeek_layout *layout,
+                                            double x_widget, double y_widget,
+                                            struct transformation widget_to_layout);
+const char *squeek_layout_get_preview_label_at(const struct squeek_layout *layout,
+                                               double x_widget, double y_widget,
+                                               struct transformation widget_to_layout);
+char *squeek_layout_get_actions_json(const struct squeek_layout *layout);
+const char *squeek_layout_get_name_display(const struct squeek_layout *layout);
+const char *squeek_layout_get_author(const struct squeek_layout *layout);
+const char *squeek_layout_get_license(const struct squeek_layout *layout);
+// Bits of squeek_layout_get_required_protocols
+enum squeek_protocol_needs {
+    PROTOCOL_NEEDS_KEYSYMS = 1,
+    PROTOCOL_NEEDS_TEXT_COMMIT = 2,
+};
+uint32_t squeek_layout_get_long_press_ms(const struct squeek_layout *layout);
+uint32_t squeek_layout_get_required_protocols(const struct squeek_layout *layout);
+char *squeek_layout_get_full_keymap_string(const struct squeek_layout *layout);
+char *squeek_layout_get_button_extra(const struct squeek_layout *layout,
+                                     const char *name, const char *key);
+uint32_t squeek_layout_is_button_disabled(const struct squeek_layout *layout,
+                                          const char *name);
+void squeek_layout_set_button_disabled(struct squeek_layout *layout,
+                                       const char *name, uint32_t disabled);
+uint32_t squeek_layout_is_button_ime_aware(const struct squeek_layout *layout,
+                                           const char *name);
+// 0 means the global setting applies
+uint32_t squeek_layout_get_button_repeat_delay_ms(const struct squeek_layout *layout,
+                                                  const char *name);
+uint32_t squeek_layout_get_button_repeat_interval_ms(const struct squeek_layout *layout,
+                                                     const char *name);
+int32_t squeek_layout_get_key_index(const struct squeek_layout *layout,
+                                    const char *name);
+void squeek_layout_foreach_view(const struct squeek_layout *layout,
+                                void (*callback)(const char *name, void *user_data),
+                                void *user_data);
+// Parts of the size given as a percentage of the view count as 0 in bounds,
+// and are passed as fractions in relative_width and relative_height
+void squeek_layout_foreach_outline(const struct squeek_layout *layout,
+                                   void (*callback)(const char *name,
+                                                    EekBounds bounds,
+                                                    double relative_width,
+                                                    double relative_height,
+                                                    void *user_data),
+                                   void *user_data);
 void squeek_layout_free(struct squeek_layout*);
 
 void squeek_layout_release(struct squeek_layout *layout,
@@ -47,6 +97,9 @@ void squeek_layout_release(struct squeek_layout *layout,
 void squeek_layout_release_all_only(struct squeek_layout *layout,
                                     struct submission *submission,
                                     uint32_t timestamp);
+void squeek_layout_hold(struct squeek_layout *layout,
+                        struct submission *submission,
+                        uint32_t timestamp);
 void squeek_layout_depress(struct squeek_layout *layout,
                            struct submission *submission,
                            double x_widget, double y_widget,
@@ -61,6 +114,10 @@ void squeek_layout_drag(struct squeek_layout *layout,
                         EekGtkKeyboard *ui_keyboard);
 void squeek_layout_draw_all_changed(struct squeek_layout *layout, EekRenderer* renderer, cairo_t     *cr, struct submission *submission,
                                     double x_origin, double y_origin, double x_scale, double y_scale);
+void squeek_layout_foreach_active_key(const struct squeek_layout *layout,
+                                      struct submission *submission,
+                                      void (*callback)(const char *name, void *user_data),
+                                      void *user_data);
 void squeek_draw_layout_base_view(struct squeek_layout *layout, EekRenderer* renderer, cairo_t     *cr,
                                   double x_origin, double y_origin, double x_scale, double y_scale);
 #endif
@@ -17,14 +17,16 @@
  * and let the renderer scale and center it within the widget.
  */
 
-use std::collections::HashMap;
+use std::collections::{ BTreeMap, HashMap, HashSet };
 use std::ffi::CString;
 use std::fmt;
 use std::vec::Vec;
+use xkbcommon::xkb;
 
-use crate::action::Action;
+use crate::action::{ Action, PrefsTarget };
 use crate::actors;
 use crate::drawing;
+use crate::keyboard;
 use crate::keyboard::{KeyState, KeyCode, PressType};
 use crate::logging;
 use crate::popover;
@@ -36,6 +38,7 @@ use crate::imservice::ContentPurpose;
 
 // Traits
 use crate::logging::Warn;
+use serde::Serialize;
 
 /// Gathers stuff defined in C or called by C
 pub mod c {
@@ -44,9 +47,11 @@ pub mod c {
     use crate::receiver;
     use crate::submission::c::Submission as CSubmission;
 
+    use glib::translate::ToGlibPtr;
     use gtk_sys;
     use std::ops::{ Add, Sub };
-    use std::os::raw::c_void;
+    use std::os::raw::{ c_char, c_void };
+    use std::ptr;
     
     use crate::util::CloneOwned;
     
@@ -99,7 +104,7 @@ pub mod c {
 
     /// Defined in eek-types.h
     #[repr(C)]
-    #[derive(Clone, Debug, PartialEq)]
+    #[derive(Clone, Debug, PartialEq, Serialize)]
     pub struct Bounds {
         pub x: f64,
         pub y: f64,
@@ -197,6 +202,298 @@ pub mod c {
         layout.shape.purpose.clone() as u32
     }
 
+    /// Returns 1 if the layout asks to start with capital letters
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_get_autocapitalize(layout: *const Layout) -> u32 {
+        let layout = unsafe { &*layout };
+        layout.shape.autocapitalize as u32
+    }
+
+    /// Returns how long a press must last to count as a long press,
+    /// in milliseconds
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_get_long_press_ms(layout: *const Layout) -> u32 {
+        let layout = unsafe { &*layout };
+        layout.shape.long_press_ms
+    }
+
+    /// Returns the human-readable name of the layout,
+    /// or NULL if the layout doesn't have one.
+    /// The text belongs to the layout.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_get_name_display(layout: *const Layout) -> *const c_char {
+        let layout = unsafe { &*layout };
+        as_ptr_or_null(&layout.shape.metadata.name_display)
+    }
+
+    /// Returns the author of the layout, or NULL if not known.
+    /// The text belongs to the layout.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_get_author(layout: *const Layout) -> *const c_char {
+        let layout = unsafe { &*layout };
+        as_ptr_or_null(&layout.shape.metadata.author)
+    }
+
+    /// Returns the license of the layout, or NULL if not known.
+    /// The text belongs to the layout.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_get_license(layout: *const Layout) -> *const c_char {
+        let layout = unsafe { &*layout };
+        as_ptr_or_null(&layout.shape.metadata.license)
+    }
+
+    fn as_ptr_or_null(text: &Option<CString>) -> *const c_char {
+        text.as_ref().map_or(ptr::null(), |text| text.as_ptr())
+    }
+
+    /// Returns which protocols the buttons use, as bits:
+    /// 1 for sending keys, 2 for committing text.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_get_required_protocols(layout: *const Layout) -> u32 {
+        let layout = unsafe { &*layout };
+        let needs = layout.shape.required_protocols();
+        needs.keysyms as u32 | (needs.text_commit as u32) << 1
+    }
+
+    /// Returns the screen reader description
+    /// of the button at the given point in the current view,
+    /// or NULL if there's no button there.
+    /// The text belongs to the layout.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_get_a11y_label_at(
+        layout: *const Layout,
+        x_widget: f64, y_widget: f64,
+        widget_to_layout: Transformation,
+    ) -> *const c_char {
+        let layout = unsafe { &*layout };
+        let point = widget_to_layout.forward(
+            Point { x: x_widget, y: y_widget }
+        );
+        let (offset, view) = layout.get_current_view_position();
+        match view.find_button_by_position(point - offset) {
+            Some((button, _)) => button.a11y_label.as_ptr(),
+            None => ptr::null(),
+        }
+    }
+
+    /// Returns the label which the button at the given point
+    /// has in the shift view, for showing on long presses,
+    /// or NULL if there's no such text label.
+    /// The text belongs to the layout.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_get_preview_label_at(
+        layout: *const Layout,
+        x_widget: f64, y_widget: f64,
+        widget_to_layout: Transformation,
+    ) -> *const c_char {
+        let layout = unsafe { &*layout };
+        let point = widget_to_layout.forward(
+            Point { x: x_widget, y: y_widget }
+        );
+        let (offset, view) = layout.get_current_view_position();
+        let button = view.find_button_by_position(point - offset)
+            .map(|(button, _)| button);
+        match button.and_then(|button| button.preview_label.as_ref()) {
+            Some(Label::Text(text)) => text.as_ptr(),
+            // Icons are left to the regular label
+            Some(Label::IconName(_)) | None => ptr::null(),
+        }
+    }
+
+    /// Returns what each button does, as JSON.
+    /// The text must be freed with g_free.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_get_actions_json(layout: *const Layout) -> *mut c_char {
+        let layout = unsafe { &*layout };
+        layout.shape.actions_json().as_str().to_glib_full()
+    }
+
+    /// Returns the symbols entries of every key in the layout's keymaps.
+    /// The text must be freed with g_free.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_get_full_keymap_string(
+        layout: *const Layout,
+    ) -> *mut c_char {
+        let layout = unsafe { &*layout };
+        layout.shape.full_keymap_string()
+            .to_string_lossy()
+            .as_ref()
+            .to_glib_full()
+    }
+
+    /// Returns the extension data stored under `key`
+    /// on the button called `name`, or NULL if there's none.
+    /// The text must be freed with g_free.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_get_button_extra(
+        layout: *const Layout,
+        name: *const c_char,
+        key: *const c_char,
+    ) -> *mut c_char {
+        let layout = unsafe { &*layout };
+        let name = crate::util::c::as_str(&name)
+            .unwrap_or(None)
+            .unwrap_or("");
+        let key = crate::util::c::as_str(&key)
+            .unwrap_or(None)
+            .unwrap_or("");
+        layout.shape.get_button_extra(name, key).to_glib_full()
+    }
+
+    /// Returns 1 if the button called `name` is disabled, 0 otherwise.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_is_button_disabled(
+        layout: *const Layout,
+        name: *const c_char,
+    ) -> u32 {
+        let layout = unsafe { &*layout };
+        let name = crate::util::c::as_str(&name)
+            .unwrap_or(None)
+            .unwrap_or("");
+        layout.is_button_disabled(name) as u32
+    }
+
+    /// Disables all buttons called `name` if `disabled` is not 0,
+    /// enables them otherwise. The caller redraws the keyboard.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_set_button_disabled(
+        layout: *mut Layout,
+        name: *const c_char,
+        disabled: u32,
+    ) {
+        let layout = unsafe { &mut *layout };
+        let name = crate::util::c::as_str(&name)
+            .unwrap_or(None)
+            .unwrap_or("");
+        layout.set_button_disabled(name, disabled != 0);
+    }
+
+    /// Returns 1 if the button called `name` lets the input method
+    /// handle composition, 0 otherwise.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_is_button_ime_aware(
+        layout: *const Layout,
+        name: *const c_char,
+    ) -> u32 {
+        let layout = unsafe { &*layout };
+        let name = crate::util::c::as_str(&name)
+            .unwrap_or(None)
+            .unwrap_or("");
+        layout.shape.is_button_ime_aware(name) as u32
+    }
+
+    /// Returns the time from press to the first repetition
+    /// of the button called `name`, in milliseconds,
+    /// or 0 if the global setting applies.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_get_button_repeat_delay_ms(
+        layout: *const Layout,
+        name: *const c_char,
+    ) -> u32 {
+        let layout = unsafe { &*layout };
+        let name = crate::util::c::as_str(&name)
+            .unwrap_or(None)
+            .unwrap_or("");
+        layout.shape.get_button_repeat(name).delay_ms.unwrap_or(0)
+    }
+
+    /// Returns the time between repetitions
+    /// of the button called `name`, in milliseconds,
+    /// or 0 if the global setting applies.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_get_button_repeat_interval_ms(
+        layout: *const Layout,
+        name: *const c_char,
+    ) -> u32 {
+        let layout = unsafe { &*layout };
+        let name = crate::util::c::as_str(&name)
+            .unwrap_or(None)
+            .unwrap_or("");
+        layout.shape.get_button_repeat(name).interval_ms.unwrap_or(0)
+    }
+
+    /// Returns the index of the button called `name`,
+    /// shared by its copies in all views, or -1 if there's no such button.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_get_key_index(
+        layout: *const Layout,
+        name: *const c_char,
+    ) -> i32 {
+        let layout = unsafe { &*layout };
+        let name = crate::util::c::as_str(&name)
+            .unwrap_or(None)
+            .unwrap_or("");
+        layout.shape.key_indices.get(name)
+            .map(|index| *index as i32)
+            .unwrap_or(-1)
+    }
+
+    /// Calls `callback` with the name of each view,
+    /// in the order for listing them, e.g. in a view picker.
+    /// The name is only valid during the call.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_foreach_view(
+        layout: *const Layout,
+        callback: extern "C" fn(name: *const c_char, user_data: *mut c_void),
+        user_data: *mut c_void,
+    ) {
+        let layout = unsafe { &*layout };
+        for name in &layout.shape.view_order {
+            // View names come from YAML strings, which may contain a nul.
+            if let Ok(name) = CString::new(name.as_str()) {
+                callback(name.as_ptr(), user_data);
+            }
+        }
+    }
+
+    /// Calls `callback` with every outline of the layout, sorted by name.
+    /// Parts of the size given as a percentage of the view
+    /// count as 0 in the bounds, and are passed separately,
+    /// as fractions of the view.
+    /// The name is only valid during the call.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_foreach_outline(
+        layout: *const Layout,
+        callback: extern "C" fn(
+            name: *const c_char,
+            bounds: Bounds,
+            relative_width: f64,
+            relative_height: f64,
+            user_data: *mut c_void,
+        ),
+        user_data: *mut c_void,
+    ) {
+        let layout = unsafe { &*layout };
+        for outline in &layout.shape.outlines {
+            callback(
+                outline.name.as_ptr(),
+                outline.bounds.clone(),
+                outline.relative.width,
+                outline.relative.height,
+                user_data,
+            );
+        }
+    }
+
     #[no_mangle]
     pub extern "C"
     fn squeek_layout_free(layout: *mut Layout) {
@@ -276,6 +573,30 @@ pub mod c {
             }
         }
 
+        /// Submits the long press effects of the buttons held long enough
+        #[no_mangle]
+        pub extern "C"
+        fn squeek_layout_hold(
+            layout: *mut Layout,
+            submission: CSubmission,
+            time: u32,
+        ) {
+            let layout = unsafe { &mut *layout };
+            let submission = submission.clone_ref();
+            let mut submission = submission.borrow_mut();
+            // The list must be copied,
+            // because it will be mutated in the loop
+            let pressed_buttons = layout.state.active_buttons.clone();
+            for (button, _key_state) in pressed_buttons.iter_pressed() {
+                seat::handle_hold_key(
+                    layout,
+                    &mut submission,
+                    Timestamp(time),
+                    button,
+                );
+            }
+        }
+
         #[no_mangle]
         pub extern "C"
         fn squeek_layout_depress(
@@ -309,10 +630,22 @@ pub mod c {
                 );
                 // maybe TODO: draw on the display buffer here
                 drawing::queue_redraw(ui_keyboard);
+                emit_feedback(layout, &button, ui_keyboard);
+            };
+        }
+
+        fn emit_feedback(
+            layout: &Layout,
+            button: &ButtonPosition,
+            ui_keyboard: EekGtkKeyboard,
+        ) {
+            let button = layout.shape.get_button(button);
+            let silent = button.map(|button| button.silent).unwrap_or(false);
+            if !silent && !seat::is_disabled(&layout.state, button) {
                 unsafe {
                     eek_gtk_keyboard_emit_feedback(ui_keyboard);
                 }
-            };
+            }
         }
 
         // FIXME: this will work funny
@@ -385,9 +718,7 @@ pub mod c {
                         &button,
                     );
                     // maybe TODO: draw on the display buffer here
-                    unsafe {
-                        eek_gtk_keyboard_emit_feedback(ui_keyboard);
-                    }
+                    emit_feedback(layout, &button, ui_keyboard);
                 }
             } else {
                 for (button, _key_state) in pressed_buttons {
@@ -444,6 +775,15 @@ pub enum Label {
     IconName(CString),
 }
 
+/// Overrides of the global key repeat settings for a button
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Repeat {
+    /// Time from press to the first repetition
+    pub delay_ms: Option<u32>,
+    /// Time between repetitions. Never 0.
+    pub interval_ms: Option<u32>,
+}
+
 /// The definition of an interactive button
 #[derive(Clone, Debug, PartialEq)]
 pub struct Button {
@@ -451,20 +791,99 @@ pub struct Button {
     pub name: CString,
     /// Label to display to the user
     pub label: Label,
+    /// Replaces `label` while the first view of `Action::ToggleView`
+    /// is shown, and the button leads to the second one
+    pub toggled_label: Option<Label>,
+    /// Label of the button in the same place in the shift view,
+    /// for showing on long presses
+    pub preview_label: Option<Label>,
+    /// Description for screen readers
+    pub a11y_label: CString,
     pub size: Size,
+    /// Where the button is drawn, relative to its place in the row.
+    /// Presses find the button where it's drawn,
+    /// and the row grows to the right and down to fit it.
+    pub offset: c::Point,
     /// The name of the visual class applied
     pub outline_name: CString,
+    pub repeat: Repeat,
+    /// Presses coming sooner than this after the last one are ignored
+    pub debounce_ms: Option<u32>,
+    /// Pressing doesn't cause sound or haptic feedback
+    pub silent: bool,
+    /// Submitting does nothing unless the text field is empty
+    pub only_in_empty_field: bool,
+    /// Pressing doesn't unlatch the view. Only set on submitting buttons.
+    pub keep_view: bool,
+    /// Data for extensions, passed on without interpreting it
+    pub extra: BTreeMap<String, String>,
+    /// The input method may handle composition for this button
+    pub ime_aware: bool,
+    /// Disabled when the layout is loaded
+    pub disabled: bool,
     // action-related stuff
     /// A cache of raw keycodes derived from Action::Submit given a keymap
     pub keycodes: Vec<KeyCode>,
     /// Static description of what the key does when pressed or released
     pub action: Action,
+    /// Replaces `action` and `keycodes` while its flag is on
+    pub alternate: Option<Alternate>,
+    /// Submitted instead of `action` when the button is held
+    pub long_press: Option<Effect>,
+    /// Replaces everything above with separate effects
+    /// for pressing and for releasing
+    pub edges: Option<Edges>,
+}
+
+/// What a button does while a layout flag is on
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alternate {
+    pub flag: String,
+    pub action: Action,
+    pub keycodes: Vec<KeyCode>,
+}
+
+/// An action together with the keycodes it needs
+#[derive(Debug, Clone, PartialEq)]
+pub struct Effect {
+    pub action: Action,
+    pub keycodes: Vec<KeyCode>,
+}
+
+/// Effects of a button which does different things on press and on release.
+/// Each one is complete on its own, like a tap of a simple button.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Edges {
+    pub on_press: Option<Effect>,
+    pub on_release: Option<Effect>,
 }
 
 impl Button {
+    /// Returns the action and keycodes in effect with the given flags on
+    pub fn get_submission(&self, flags: &HashSet<String>)
+        -> (&Action, &Vec<KeyCode>)
+    {
+        match &self.alternate {
+            Some(alternate) if flags.contains(&alternate.flag) => {
+                (&alternate.action, &alternate.keycodes)
+            },
+            _ => (&self.action, &self.keycodes),
+        }
+    }
+
+    /// Returns the label to show while the given view is current
+    pub fn get_label(&self, current_view: &str) -> &Label {
+        match (&self.action, &self.toggled_label) {
+            (Action::ToggleView { first, .. }, Some(label))
+                if first == current_view => label,
+            _ => &self.label,
+        }
+    }
+
+    /// Bounds relative to the button's place in the row
     pub fn get_bounds(&self) -> c::Bounds {
         c::Bounds {
-            x: 0.0, y: 0.0,
+            x: self.offset.x, y: self.offset.y,
             width: self.size.width, height: self.size.height,
         }
     }
@@ -492,13 +911,19 @@ impl Row {
                 == buttons.iter().map(|(f, _)| *f).collect::<Vec<_>>()
         });
 
-        let width = buttons.iter().next_back()
-            .map(|(x_offset, button)| button.size.width + x_offset)
-            .unwrap_or(0.0);
+        // Shifted buttons may reach past their places
+        let width = find_max_double(
+            buttons.iter(),
+            |(x_offset, button)| {
+                x_offset + button.size.width + f64::max(button.offset.x, 0.0)
+            },
+        );
 
         let height = find_max_double(
             buttons.iter(),
-            |(_offset, button)| button.size.height,
+            |(_offset, button)| {
+                button.size.height + f64::max(button.offset.y, 0.0)
+            },
         );
 
         Row { buttons, size: Size { width, height } }
@@ -530,6 +955,20 @@ impl Row {
 
         (&self.buttons[index].1, index)
     }
+
+    /// Finds the first button drawn over the specified point
+    /// relative to row's position's origin.
+    /// Returns its index too.
+    fn find_button_by_bounds(&self, point: &c::Point)
+        -> Option<(&Button, usize)>
+    {
+        self.buttons.iter().enumerate()
+            .find(|(_index, (x_offset, button))| {
+                let bounds = button.get_bounds();
+                c::Bounds { x: x_offset + bounds.x, ..bounds }.contains(point)
+            })
+            .map(|(index, (_x_offset, button))| (button, index))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -545,6 +984,10 @@ pub struct View {
 
     /// Total size of the view
     size: Size,
+
+    /// The area where presses find buttons, relative to the view.
+    /// Outside of the view, they find the closest button.
+    hit_area: c::Bounds,
 }
 
 impl View {
@@ -563,9 +1006,10 @@ impl View {
         // because they are all centered.
         let width = find_max_double(rows.iter(), |(_offset, row)| row.size.width);
 
-        let height = rows.iter().next_back()
-            .map(|(y_offset, row)| row.size.height + y_offset)
-            .unwrap_or(0.0);
+        let height = find_max_double(
+            rows.iter(),
+            |(y_offset, row)| row.size.height + y_offset,
+        );
 
         // Center the rows
         let rows = rows.into_iter().map(|(y_offset, row)| {(
@@ -576,7 +1020,48 @@ impl View {
                 row,
             )}).collect::<Vec<_>>();
 
-        View { rows, size: Size { width, height } }
+        // Buttons shifted to the left or up stick out of the view,
+        // but can still be pressed there
+        let drawn = rows.iter()
+            .flat_map(|(row_offset, row)| {
+                row.buttons.iter().map(move |(x_offset, button)| c::Point {
+                    x: row_offset.x + x_offset + button.offset.x,
+                    y: row_offset.y + button.offset.y,
+                })
+            });
+        let (left, top) = drawn.fold(
+            (0.0, 0.0),
+            |(left, top), corner| {
+                (f64::min(left, corner.x), f64::min(top, corner.y))
+            },
+        );
+
+        View {
+            rows,
+            size: Size { width, height },
+            hit_area: c::Bounds {
+                x: left,
+                y: top,
+                width: width - left,
+                height: height - top,
+            },
+        }
+    }
+
+    /// Lets presses in the given area find the closest buttons.
+    /// The view and the buttons sticking out of it always stay in.
+    pub fn extend_hit_area(&mut self, bounds: c::Bounds) {
+        let area = &self.hit_area;
+        let left = f64::min(bounds.x, area.x);
+        let top = f64::min(bounds.y, area.y);
+        let right = f64::max(bounds.x + bounds.width, area.x + area.width);
+        let bottom = f64::max(bounds.y + bounds.height, area.y + area.height);
+        self.hit_area = c::Bounds {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+        };
     }
     /// Finds the first button that covers the specified point
     /// relative to view's position's origin.
@@ -584,18 +1069,23 @@ impl View {
     fn find_button_by_position(&self, point: c::Point)
         -> Option<(&Button, (usize, usize))>
     {
-        // Only test bounds of the view here, letting rows/column search extend
-        // to the edges of these bounds.
-        let bounds = c::Bounds {
-            x: 0.0,
-            y: 0.0,
-            width: self.size.width,
-            height: self.size.height,
-        };
-        if !bounds.contains(&point) {
+        // Only test bounds of the hit area here, letting rows/column search
+        // extend to the edges of these bounds.
+        if !self.hit_area.contains(&point) {
             return None;
         }
 
+        // Buttons are found where they are drawn,
+        // even when shifted away from their places.
+        let drawn = self.rows.iter().enumerate()
+            .find_map(|(row_index, (row_offset, row))| {
+                row.find_button_by_bounds(&(point.clone() - row_offset))
+                    .map(|(button, index)| (button, (row_index, index)))
+            });
+        if drawn.is_some() {
+            return drawn;
+        }
+
         // Rows are sorted so we can use a binary search to find the row.
         let result = self.rows.binary_search_by(
             |(f, _)| f.y.partial_cmp(&point.y).unwrap()
@@ -646,7 +1136,7 @@ pub enum ArrangementKind {
     Wide = 1,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct Margins {
     pub top: f64,
     pub bottom: f64,
@@ -669,6 +1159,21 @@ pub struct Layout {
     pub shape: LayoutData,
 }
 
+/// Buttons which would be hard to use in an area of some size
+#[derive(Clone, Debug, PartialEq)]
+pub struct FitReport {
+    /// Buttons reaching outside the area
+    pub overflowing: Vec<ButtonPosition>,
+    /// Buttons narrower or lower than the minimum
+    pub too_small: Vec<ButtonPosition>,
+}
+
+impl FitReport {
+    pub fn fits(&self) -> bool {
+        self.overflowing.is_empty() && self.too_small.is_empty()
+    }
+}
+
 /// Button position for the pressed buttons list
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct ButtonPosition {
@@ -690,7 +1195,12 @@ enum Presence {
     Present,
 }
 
-static RELEASED: KeyState = KeyState { pressed: PressType::Released };
+static RELEASED: KeyState = KeyState {
+    pressed: PressType::Released,
+    pressed_at: None,
+    bounced: false,
+    held: false,
+};
 
 impl ActiveButtons {
     fn insert(&mut self, button: ButtonPosition, state: KeyState) -> Presence {
@@ -723,6 +1233,18 @@ pub struct LayoutState {
     // clicking any button that emits an action (erase, submit, set modifier)
     // will cause lock buttons to unlatch.
     view_latched: LatchedState,
+    /// The locking action which set the current latch, and when.
+    /// Used to tell a double tap from two separate presses,
+    /// or from presses of two different buttons.
+    latched_by: Option<(Action, Timestamp)>,
+    /// Views visited before the current one, most recent last.
+    /// A view appears at most once, so going back to it
+    /// forgets everything visited after it.
+    view_history: Vec<String>,
+    /// Flags which are on, selecting the alternate submissions of buttons
+    pub flags: HashSet<String>,
+    /// Names of buttons which ignore presses and are drawn dimmed
+    pub disabled: HashSet<String>,
     // a Vec would be enough, but who cares, this will be small & fast enough
     // TODO: turn those into per-input point *_buttons to track dragging.
     // The renderer doesn't need the list of pressed keys any more,
@@ -742,9 +1264,70 @@ pub struct LayoutParseData {
     /// Point is the offset within the panel
     /// (transformed to layout's coordinate space).
     pub views: HashMap<String, (c::Point, View)>,
+    /// The view shown first. Exists in `views`.
+    pub default_view: String,
     /// xkb keymaps applicable to the contained keys
     pub keymaps: Vec<CString>,
+    /// Keycodes of the keysyms in `keymaps`
+    pub symbolmap: HashMap<String, KeyCode>,
+    /// Second level keysyms in `keymaps`
+    pub shift_levels: keyboard::ShiftLevels,
+    /// All views, in the order for showing them to the user
+    pub view_order: Vec<String>,
     pub margins: Margins,
+    pub autocapitalize: bool,
+    pub long_press_ms: u32,
+    pub modal_views: HashSet<String>,
+    /// Sequential indices of distinct buttons, by name
+    pub key_indices: HashMap<String, usize>,
+    pub clusters: Vec<Cluster>,
+    pub metadata: Metadata,
+    /// Sorted by name
+    pub outlines: Vec<OutlineSummary>,
+}
+
+/// Information for people choosing a layout.
+/// Doesn't change how the layout works.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    /// Human-readable name of the layout
+    pub name_display: Option<CString>,
+    pub author: Option<CString>,
+    pub license: Option<CString>,
+}
+
+/// An outline defined by the layout, for tools showing the available sizes
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutlineSummary {
+    pub name: CString,
+    /// Parts of the size given as a percentage of the view count as 0 here
+    pub bounds: c::Bounds,
+    /// Parts of the size given as a percentage of the view,
+    /// as fractions of its width and height
+    pub relative: Size,
+}
+
+/// Used when the layout doesn't choose
+pub const DEFAULT_LONG_PRESS_MS: u32 = 500;
+
+/// Buttons drawn on a shared background, e.g. the arrow keys.
+/// They are still pressed one by one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cluster {
+    pub name: String,
+    pub view: String,
+    /// Covers all the buttons of the cluster in the view,
+    /// relative to the view
+    pub bounds: c::Bounds,
+}
+
+/// Protocols which the buttons of a layout submit through
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ProtocolNeeds {
+    /// Keys are sent through virtual-keyboard
+    pub keysyms: bool,
+    /// Text is committed through text-input
+    pub text_commit: bool,
 }
 
 /// Static, cacheable information for the layout
@@ -752,16 +1335,39 @@ pub struct LayoutData {
     pub margins: Margins,
     pub kind: ArrangementKind,
     pub purpose: ContentPurpose,
+    /// Text input should start with capital letters
+    pub autocapitalize: bool,
+    /// How long a button must be held to count as a long press
+    pub long_press_ms: u32,
+    /// Views which don't return to the latching view on their own
+    pub modal_views: HashSet<String>,
+    /// Sequential indices of distinct buttons, by name.
+    /// Buttons with the same name in different views share the index.
+    pub key_indices: HashMap<String, usize>,
+    /// Sorted by view, then by name
+    pub clusters: Vec<Cluster>,
+    pub metadata: Metadata,
+    /// Sorted by name
+    pub outlines: Vec<OutlineSummary>,
 
     // Views own the actual buttons which have state
     // Maybe they should own UI only,
     // and keys should be owned by a dedicated non-UI-State?
     /// Point is the offset within the layout
     pub views: HashMap<String, (c::Point, View)>,
+    /// The view shown first, and when there's nothing to go back to
+    pub default_view: String,
+    /// All views, in the order chosen by the author of the layout
+    pub view_order: Vec<String>,
 
     // Non-UI stuff
     /// xkb keymaps applicable to the contained keys. Unchangeable
     pub keymaps: Vec<CString>,
+    /// Keycodes of the keysyms in `keymaps`.
+    /// Empty if the keymaps couldn't be generated.
+    pub symbolmap: HashMap<String, KeyCode>,
+    /// Second level keysyms in `keymaps`, by the first level keysym
+    pub shift_levels: keyboard::ShiftLevels,
 }
 
 #[derive(Debug)]
@@ -822,45 +1428,457 @@ impl LayoutData {
             scale_y: v_scale,
         }
     }
-}
 
-// Unfortunately, changes are not atomic due to mutability :(
-// An error will not be recoverable
-// The usage of &mut on Rc<RefCell<KeyState>> doesn't mean anything special.
-// Cloning could also be used.
-impl Layout {
-    pub fn new(data: LayoutParseData, kind: ArrangementKind, purpose: ContentPurpose) -> Layout {
-        Layout {
-            shape: LayoutData {
-                kind,
-                views: data.views,
-                keymaps: data.keymaps,
-                margins: data.margins,
-                purpose,
-            },
-            state: LayoutState {
-                current_view: "base".to_owned(),
-                view_latched: LatchedState::Not,
-                active_buttons: ActiveButtons(HashMap::new()),
-            },
+    /// Returns every text which the buttons can submit,
+    /// including alternates, long presses,
+    /// and separate press and release actions,
+    /// e.g. for word prediction. Sorted, without repetitions.
+    pub fn submittable_texts(&self) -> Vec<String> {
+        let mut texts: Vec<String> = self.views.values()
+            .flat_map(|(_offset, view)| view.get_rows())
+            .flat_map(|(_offset, row)| row.get_buttons())
+            .flat_map(|(_offset, button)| {
+                let alternate = button.alternate.as_ref()
+                    .map(|alternate| &alternate.action);
+                let long_press = button.long_press.as_ref()
+                    .map(|effect| &effect.action);
+                let edges = button.edges.iter()
+                    .flat_map(|edges| [&edges.on_press, &edges.on_release])
+                    .flatten()
+                    .map(|effect| &effect.action);
+                Some(&button.action).into_iter()
+                    .chain(alternate)
+                    .chain(long_press)
+                    .chain(edges)
+            })
+            .filter_map(|action| match action {
+                Action::Submit { text: Some(text), .. } => {
+                    Some(text.to_string_lossy().into_owned())
+                },
+                _ => None,
+            })
+            .collect();
+        texts.sort();
+        texts.dedup();
+        texts
+    }
+
+    /// Checks the buttons as they would be drawn
+    /// in an area of the given size.
+    pub fn fits(&self, width: f64, height: f64, min_button: f64) -> FitReport {
+        let transformation = self.calculate_transformation(
+            Size { width, height },
+        );
+        let mut report = FitReport {
+            overflowing: Vec::new(),
+            too_small: Vec::new(),
+        };
+        for (position, _button) in self.button_positions() {
+            let view_offset = self.views[&position.view].0.clone();
+            let (place, button) = match self.find_button_place(&position) {
+                Some(place) => place,
+                None => continue,
+            };
+            let bounds = button.get_bounds();
+            let bounds = transformation.reverse_bounds(c::Bounds {
+                x: view_offset.x + place.x + bounds.x,
+                y: view_offset.y + place.y + bounds.y,
+                width: bounds.width,
+                height: bounds.height,
+            });
+            // Rounding errors are far below a pixel.
+            let slack = 0.5;
+            if bounds.x < -slack || bounds.y < -slack
+                || bounds.x + bounds.width > width + slack
+                || bounds.y + bounds.height > height + slack
+            {
+                report.overflowing.push(position.clone());
+            }
+            if bounds.width < min_button || bounds.height < min_button {
+                report.too_small.push(position);
+            }
         }
+        report
     }
 
-    pub fn get_current_view_position(&self) -> &(c::Point, View) {
-        &self.shape.views
-            .get(&self.state.current_view).expect("Selected nonexistent view")
+    /// Positions of all buttons, in an order which stays the same
+    /// for as long as the layout: by view name, then row, then button.
+    fn button_positions(&self) -> Vec<(ButtonPosition, &Button)> {
+        let mut views: Vec<_> = self.views.iter().collect();
+        views.sort_by_key(|(name, _)| *name);
+        views.into_iter()
+            .flat_map(|(name, (_offset, view))| {
+                view.get_rows().iter().enumerate()
+                    .flat_map(move |(row, (_offset, buttons))| {
+                        buttons.get_buttons().iter().enumerate()
+                            .map(move |(position_in_row, (_offset, button))| (
+                                ButtonPosition {
+                                    view: name.clone(),
+                                    row,
+                                    position_in_row,
+                                },
+                                button,
+                            ))
+                    })
+            })
+            .collect()
     }
 
-    pub fn get_current_view(&self) -> &View {
-        &self.shape.views.get(&self.state.current_view).expect("Selected nonexistent view").1
+    /// Captures whether each distinct button is pressed or locked,
+    /// at its index from `key_indices`,
+    /// to be compared later with `changed_since`.
+    /// Buttons with the same name share the state,
+    /// so pressing one copy counts for all of them.
+    /// Modifiers are not included, because the layout doesn't know them.
+    pub fn snapshot(&self, state: &LayoutState) -> Vec<bool> {
+        let mut snapshot = vec![false; self.key_indices.len()];
+        let locked = self.views.values()
+            .flat_map(|(_offset, view)| view.get_rows())
+            .flat_map(|(_offset, row)| row.get_buttons())
+            .map(|(_offset, button)| button)
+            .filter(|button| button.action.is_active(&state.current_view));
+        let pressed = state.active_buttons.iter_pressed()
+            .filter_map(|(position, _state)| self.get_button(position));
+        for button in locked.chain(pressed) {
+            let name = button.name.to_string_lossy();
+            if let Some(index) = self.key_indices.get(name.as_ref()) {
+                snapshot[*index] = true;
+            }
+        }
+        snapshot
     }
 
-    fn set_view(&mut self, view: String) -> Result<(), NoSuchView> {
-        if self.shape.views.contains_key(&view) {
-            self.state.current_view = view;
-            Ok(())
-        } else {
-            Err(NoSuchView)
+    /// Returns the `key_indices` of buttons which changed state
+    /// since the `previous` snapshot was taken.
+    /// A snapshot of a different layout marks every button as changed.
+    pub fn changed_since(
+        &self,
+        state: &LayoutState,
+        previous: &[bool],
+    ) -> Vec<usize> {
+        let current = self.snapshot(state);
+        if current.len() != previous.len() {
+            return (0..current.len()).collect();
+        }
+        current.iter().zip(previous)
+            .enumerate()
+            .filter(|(_index, (now, before))| now != before)
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Returns the symbols entries of all keys in the keymaps,
+    /// ordered by keymap and keycode, one per line.
+    pub fn full_keymap_string(&self) -> CString {
+        let mut keys: Vec<(&String, &KeyCode)> = self.symbolmap.iter()
+            .collect();
+        keys.sort_by_key(|(_name, keycode)| (keycode.keymap_idx, keycode.code));
+        let entries: Vec<String> = keys.into_iter()
+            .map(|(name, keycode)| keyboard::KeymapEntry {
+                key_name: format!("I{}", keycode.code),
+                symbol_name: Some(name.clone()),
+                shift_symbol_name: self.shift_levels.get(name).cloned(),
+            }.to_xkb_string())
+            .collect();
+        CString::new(entries.join("\n"))
+            .expect("Keysym name contains a NUL byte")
+    }
+
+    /// Returns the keycode which typing the character sends,
+    /// if any button types it.
+    pub fn keycode_for_char(&self, c: char) -> Option<u32> {
+        // The keysym names which text buttons may have used for it
+        let names = [
+            c.to_string(),
+            xkb::keysym_get_name(xkb::utf32_to_keysym(c as u32)),
+            format!("U{:04X}", c as u32),
+        ];
+        names.iter()
+            .find_map(|name| self.symbolmap.get(name))
+            .map(|keycode| keycode.code)
+    }
+
+    /// Finds out which protocols the buttons need to do their job,
+    /// counting alternates, long presses and edges too.
+    /// Text falls back to keys without text-input,
+    /// but then it's limited to what the keymap has.
+    pub fn required_protocols(&self) -> ProtocolNeeds {
+        let actions = self.views.values()
+            .flat_map(|(_offset, view)| view.get_rows())
+            .flat_map(|(_offset, row)| row.get_buttons())
+            .flat_map(|(_offset, button)| {
+                let edges = button.edges.iter()
+                    .flat_map(|edges| [&edges.on_press, &edges.on_release])
+                    .flatten()
+                    .map(|effect| &effect.action);
+                let alternate = button.alternate.iter()
+                    .map(|alternate| &alternate.action);
+                let long_press = button.long_press.iter()
+                    .map(|effect| &effect.action);
+                std::iter::once(&button.action)
+                    .chain(alternate)
+                    .chain(long_press)
+                    .chain(edges)
+            });
+        let mut needs = ProtocolNeeds::default();
+        for action in actions {
+            match action {
+                Action::Submit { text: Some(_), .. } => needs.text_commit = true,
+                Action::Submit { text: None, keys } => {
+                    needs.keysyms |= !keys.is_empty();
+                },
+                Action::Enter => {
+                    needs.text_commit = true;
+                    needs.keysyms = true;
+                },
+                Action::Erase
+                    | Action::ApplyModifier(_)
+                    | Action::Clipboard(_)
+                => needs.keysyms = true,
+                _ => {},
+            }
+        }
+        needs
+    }
+
+    /// Buttons with the same name carry the same data in every view,
+    /// so any of them will do.
+    fn find_button_by_name(&self, name: &str) -> Option<&Button> {
+        self.views.values()
+            .flat_map(|(_offset, view)| view.get_rows())
+            .flat_map(|(_offset, row)| row.get_buttons())
+            .map(|(_offset, button)| button)
+            .find(|button| button.name.to_bytes() == name.as_bytes())
+    }
+
+    /// Returns extension data of a button.
+    pub fn get_button_extra(&self, name: &str, key: &str) -> Option<&str> {
+        self.find_button_by_name(name)
+            .and_then(|button| button.extra.get(key))
+            .map(String::as_str)
+    }
+
+    /// False for missing buttons.
+    pub fn is_button_ime_aware(&self, name: &str) -> bool {
+        self.find_button_by_name(name)
+            .map(|button| button.ime_aware)
+            .unwrap_or(false)
+    }
+
+    /// Global settings apply to missing buttons.
+    pub fn get_button_repeat(&self, name: &str) -> Repeat {
+        self.find_button_by_name(name)
+            .map(|button| button.repeat.clone())
+            .unwrap_or_default()
+    }
+
+    /// Describes what each button does, as a JSON object
+    /// with button names as keys, e.g. for testing tools.
+    /// Buttons with the same name do the same in every view.
+    /// The usual action is included, with the long press action
+    /// in its "long_press" field, but not alternates or edges.
+    pub fn actions_json(&self) -> String {
+        let buttons: BTreeMap<String, &Button> = self.views.values()
+            .flat_map(|(_offset, view)| view.get_rows())
+            .flat_map(|(_offset, row)| row.get_buttons())
+            .map(|(_offset, button)| (
+                button.name.to_string_lossy().into_owned(),
+                button,
+            ))
+            .collect();
+        let entries: Vec<String> = buttons.iter()
+            .map(|(name, button)| {
+                let action = button.action.to_json();
+                let action = match &button.long_press {
+                    Some(effect) => format!(
+                        r#"{},"long_press":{}}}"#,
+                        action.strip_suffix('}').unwrap_or(&action),
+                        effect.action.to_json(),
+                    ),
+                    None => action,
+                };
+                format!("{}:{}", crate::util::json_string(name), action)
+            })
+            .collect();
+        format!("{{{}}}", entries.join(","))
+    }
+
+    /// Describes what gets drawn, for tools which draw layouts
+    /// without the UI, e.g. for documentation.
+    /// Views are sorted by name.
+    pub fn render_tree(&self) -> RenderTree {
+        let mut views: Vec<RenderView> = self.views.iter()
+            .map(|(name, (view_offset, view))| RenderView {
+                name: name.clone(),
+                rows: view.get_rows().iter().map(|(row_offset, row)| {
+                    row.get_buttons().iter()
+                        .map(|(x_offset, button)| {
+                            let place = view_offset.clone()
+                                + row_offset.clone()
+                                + c::Point { x: *x_offset, y: 0.0 };
+                            let bounds = button.get_bounds();
+                            RenderButton {
+                                name: button.name.to_string_lossy().into(),
+                                label: match button.get_label(name) {
+                                    Label::Text(text) => RenderLabel::Text(
+                                        text.to_string_lossy().into(),
+                                    ),
+                                    Label::IconName(name) => RenderLabel::Icon(
+                                        name.to_string_lossy().into(),
+                                    ),
+                                },
+                                outline: button.outline_name
+                                    .to_string_lossy().into(),
+                                bounds: c::Bounds {
+                                    x: place.x + bounds.x,
+                                    y: place.y + bounds.y,
+                                    ..bounds
+                                },
+                            }
+                        })
+                        .collect()
+                }).collect(),
+            })
+            .collect();
+        views.sort_by(|a, b| a.name.cmp(&b.name));
+        RenderTree { margins: self.margins.clone(), views }
+    }
+}
+
+/// A built layout, as drawn.
+/// Positions are relative to the inside of the margins.
+#[derive(Debug, Serialize)]
+pub struct RenderTree {
+    pub margins: Margins,
+    pub views: Vec<RenderView>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RenderView {
+    pub name: String,
+    /// Each row is a list of buttons
+    pub rows: Vec<Vec<RenderButton>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RenderButton {
+    pub name: String,
+    pub label: RenderLabel,
+    pub outline: String,
+    pub bounds: c::Bounds,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all="snake_case")]
+pub enum RenderLabel {
+    Text(String),
+    Icon(String),
+}
+
+// Unfortunately, changes are not atomic due to mutability :(
+// An error will not be recoverable
+// The usage of &mut on Rc<RefCell<KeyState>> doesn't mean anything special.
+// Cloning could also be used.
+impl Layout {
+    pub fn new(
+        data: LayoutParseData,
+        kind: ArrangementKind,
+        purpose: ContentPurpose,
+    ) -> Layout {
+        let disabled = data.views.values()
+            .flat_map(|(_offset, view)| view.get_rows())
+            .flat_map(|(_offset, row)| row.get_buttons())
+            .map(|(_offset, button)| button)
+            .filter(|button| button.disabled)
+            .map(|button| button.name.to_string_lossy().into_owned())
+            .collect();
+        Layout {
+            shape: LayoutData {
+                kind,
+                views: data.views,
+                keymaps: data.keymaps,
+                margins: data.margins,
+                purpose,
+                autocapitalize: data.autocapitalize,
+                long_press_ms: data.long_press_ms,
+                modal_views: data.modal_views,
+                key_indices: data.key_indices,
+                clusters: data.clusters,
+                metadata: data.metadata,
+                outlines: data.outlines,
+                default_view: data.default_view.clone(),
+                symbolmap: data.symbolmap,
+                shift_levels: data.shift_levels,
+                view_order: data.view_order,
+            },
+            state: LayoutState {
+                current_view: data.default_view,
+                view_latched: LatchedState::Not,
+                latched_by: None,
+                view_history: Vec::new(),
+                flags: HashSet::new(),
+                disabled,
+                active_buttons: ActiveButtons(HashMap::new()),
+            },
+        }
+    }
+
+    pub fn get_current_view_position(&self) -> &(c::Point, View) {
+        &self.shape.views
+            .get(&self.state.current_view).expect("Selected nonexistent view")
+    }
+
+    pub fn get_current_view(&self) -> &View {
+        &self.shape.views.get(&self.state.current_view).expect("Selected nonexistent view").1
+    }
+
+    /// Buttons with the same name are disabled together.
+    pub fn set_button_disabled(&mut self, name: &str, disabled: bool) {
+        match disabled {
+            true => self.state.disabled.insert(name.into()),
+            false => self.state.disabled.remove(name),
+        };
+    }
+
+    pub fn is_button_disabled(&self, name: &str) -> bool {
+        self.state.disabled.contains(name)
+    }
+
+    fn toggle_flag(&mut self, flag: &str) {
+        if !self.state.flags.remove(flag) {
+            self.state.flags.insert(flag.into());
+        }
+    }
+
+    fn set_view(&mut self, view: String) -> Result<(), NoSuchView> {
+        if self.shape.views.contains_key(&view) {
+            if view != self.state.current_view {
+                let history = &mut self.state.view_history;
+                match history.iter().position(|v| v == &view) {
+                    Some(idx) => history.truncate(idx),
+                    None => history.push(self.state.current_view.clone()),
+                }
+            }
+            self.state.current_view = view;
+            Ok(())
+        } else {
+            Err(NoSuchView)
+        }
+    }
+
+    /// Returns to the previous view, or to the default one if there's none.
+    /// Doesn't record the view being left.
+    fn set_previous_view(&mut self) {
+        let view = self.state.view_history.pop()
+            .unwrap_or_else(|| self.shape.default_view.clone());
+        if self.shape.views.contains_key(&view) {
+            self.state.current_view = view;
+        } else {
+            log_print!(
+                logging::Level::Bug,
+                "Bad view {} in history, ignoring",
+                view,
+            );
         }
     }
 
@@ -897,22 +1915,60 @@ impl Layout {
     fn apply_view_transition(
         &mut self,
         action: &Action,
+        time: Timestamp,
     ) {
+        let is_double_tap = match &self.state.latched_by {
+            Some((latched_by, latched_at)) => {
+                latched_by == action
+                    && time.0.wrapping_sub(latched_at.0) <= DOUBLE_TAP_TIMEOUT_MS
+            },
+            None => false,
+        };
         let (transition, new_latched) = Layout::process_action_for_view(
             action,
             &self.state.current_view,
             &self.state.view_latched,
+            is_double_tap,
         );
 
+        // Modal views are only left on purpose, and stay latched until then.
+        let is_modal = self.shape.modal_views.contains(&self.state.current_view);
+        if is_modal && transition == ViewTransition::UnlatchAll {
+            return;
+        }
+
         match transition {
             ViewTransition::UnlatchAll => self.unstick_locks(),
             ViewTransition::ChangeTo(view) => try_set_view(self, view.into()),
+            ViewTransition::Back => self.set_previous_view(),
             ViewTransition::NoChange => {},
         };
 
+        self.state.latched_by = match (&new_latched, action) {
+            (LatchedState::Not, _) => None,
+            (LatchedState::FromView(_), Action::LockView { .. })
+                => Some((action.clone(), time)),
+            (LatchedState::FromView(_), _) => self.state.latched_by.take(),
+        };
         self.state.view_latched = new_latched;
     }
 
+    /// Like `apply_view_transition`,
+    /// but leaves the view alone for buttons which keep it
+    fn apply_button_transition(
+        &mut self,
+        button_pos: &ButtonPosition,
+        action: &Action,
+        time: Timestamp,
+    ) {
+        let keeps_view = self.shape.get_button(button_pos)
+            .map(|button| button.keep_view)
+            .unwrap_or(false);
+        if !keeps_view {
+            self.apply_view_transition(action, time);
+        }
+    }
+
     /// Unlatch all latched keys,
     /// so that the new view is the one before first press.
     fn unstick_locks(&mut self) {
@@ -938,14 +1994,22 @@ impl Layout {
     /// keys go through the following stages when clicked repeatedly:
     /// unlocked+unlatched -> locked+latched -> locked+unlatched
     /// -> unlocked+unlatched
+    ///
+    /// With `double_tap_lock`, the middle stage is only reached
+    /// when `is_double_tap` is set.
     fn process_action_for_view<'a>(
         action: &'a Action,
         current_view: &str,
         latched: &LatchedState,
+        is_double_tap: bool,
     ) -> (ViewTransition<'a>, LatchedState) {
         match action {
             Action::Submit { text: _, keys: _ }
                 | Action::Erase
+                | Action::Enter
+                | Action::ClearPending
+                | Action::Clipboard(_)
+                | Action::ToggleFlag(_)
                 | Action::ApplyModifier(_)
             => {
                 let t = match latched {
@@ -958,7 +2022,17 @@ impl Layout {
                 ViewTransition::ChangeTo(view),
                 LatchedState::Not,
             ),
-            Action::LockView { lock, unlock, latches, looks_locked_from: _ } => {
+            Action::PreviousView => (ViewTransition::Back, LatchedState::Not),
+            Action::ToggleView { first, second } => (
+                ViewTransition::ChangeTo(match current_view == first {
+                    true => second,
+                    false => first,
+                }),
+                LatchedState::Not,
+            ),
+            Action::LockView {
+                lock, unlock, latches, looks_locked_from: _, double_tap_lock,
+            } => {
                 use self::ViewTransition as VT;
                 let locked = action.is_locked(current_view);
                 match (locked, latched, latches) {
@@ -972,6 +2046,10 @@ impl Layout {
                         VT::ChangeTo(lock),
                         LatchedState::FromView(view.clone()),
                     ),
+                    // Was latched, but pressed again too late to lock.
+                    (true, LatchedState::FromView(_), true)
+                        if *double_tap_lock && !is_double_tap
+                        => (VT::ChangeTo(unlock), LatchedState::Not),
                     // Was latched, now only locked.
                     (true, LatchedState::FromView(_), true)
                         => (VT::NoChange, LatchedState::Not),
@@ -988,9 +2066,14 @@ impl Layout {
     }
 }
 
+/// Longest time between presses of a `double_tap_lock` button
+/// which still locks it.
+const DOUBLE_TAP_TIMEOUT_MS: u32 = 300;
+
 #[derive(Debug, PartialEq)]
 enum ViewTransition<'a> {
     ChangeTo(&'a str),
+    Back,
     UnlatchAll,
     NoChange,
 }
@@ -1064,22 +2147,22 @@ pub struct UIBackend {
 mod seat {
     use super::*;
 
-    fn handle_press_key_cleaner(
-        shape: &LayoutData,
+    /// Sends the messages of the press part of an action
+    fn submit_press(
         submission: &mut Submission,
         time: Timestamp,
         button_pos: &ButtonPosition,
+        action: &Action,
+        keycodes: &Vec<KeyCode>,
     ) {
-        let button = shape.get_button(button_pos).unwrap();
-        let action = button.action.clone();
         match action {
             Action::Submit {
                 text: Some(text),
                 keys: _,
             } => submission.handle_press(
                 button_pos.into(),
-                SubmitData::Text(&text),
-                &button.keycodes,
+                SubmitData::Text(text),
+                keycodes,
                 time,
             ),
             Action::Submit {
@@ -1088,31 +2171,167 @@ mod seat {
             } => submission.handle_press(
                 button_pos.into(),
                 SubmitData::Keycodes,
-                &button.keycodes,
+                keycodes,
                 time,
             ),
             Action::Erase => submission.handle_press(
                 button_pos.into(),
                 SubmitData::Erase,
-                &button.keycodes,
+                keycodes,
                 time,
             ),
+            Action::Enter => {
+                let multiline = submission.is_multiline();
+                submit_press(
+                    submission,
+                    time,
+                    button_pos,
+                    &Action::Enter.resolve_enter(multiline),
+                    keycodes,
+                )
+            },
             _ => {},
         };
     }
+
+    /// Returns the action whose view and flag changes apply on press
+    fn handle_press_key_cleaner(
+        shape: &LayoutData,
+        flags: &HashSet<String>,
+        submission: &mut Submission,
+        time: Timestamp,
+        button_pos: &ButtonPosition,
+    ) -> Option<Action> {
+        let button = shape.get_button(button_pos).unwrap();
+        match &button.edges {
+            Some(edges) => {
+                let effect = edges.on_press.as_ref()?;
+                submit_press(
+                    submission,
+                    time,
+                    button_pos,
+                    &effect.action,
+                    &effect.keycodes,
+                );
+                // There's no UI to show preferences on
+                let release = Release {
+                    ui: None,
+                    time,
+                    manager: None,
+                    is_drag: false,
+                };
+                submit_release(
+                    shape,
+                    submission,
+                    &release,
+                    button_pos,
+                    &effect.action,
+                    &effect.keycodes,
+                );
+                Some(effect.action.clone())
+            },
+            None => {
+                let (action, keycodes) = button.get_submission(flags);
+                submit_press(submission, time, button_pos, action, keycodes);
+                None
+            },
+        }
+    }
     
+    /// Returns false if the button submits, but the text field
+    /// doesn't meet its condition
+    pub fn allows_submission(button: &Button, field_empty: bool) -> bool {
+        match button.action {
+            Action::Submit { .. } => !button.only_in_empty_field || field_empty,
+            _ => true,
+        }
+    }
+
+    /// Returns true if the button ignores presses until enabled
+    pub fn is_disabled(state: &LayoutState, button: Option<&Button>) -> bool {
+        match button {
+            Some(button) => state.disabled
+                .contains(button.name.to_string_lossy().as_ref()),
+            None => false,
+        }
+    }
+
+    /// Returns true if the press comes too soon after the last accepted one
+    pub fn is_bounce(
+        button: Option<&Button>,
+        state: &KeyState,
+        time: Timestamp,
+    ) -> bool {
+        match (button.and_then(|b| b.debounce_ms), state.pressed_at) {
+            (Some(window), Some(pressed_at))
+                => time.0.wrapping_sub(pressed_at.0) < window,
+            _ => false,
+        }
+    }
+
     pub fn handle_press_key(
         layout: &mut Layout,
         submission: &mut Submission,
         time: Timestamp,
         button_pos: &ButtonPosition,
     ) {
+        let button = layout.shape.get_button(button_pos);
+        if is_disabled(&layout.state, button) {
+            // Not tracked at all, there's nothing to release
+            return;
+        }
+        let previous = layout.state.active_buttons.get(button_pos).clone();
+        if is_bounce(button, &previous, time) {
+            // Still tracked as pressed,
+            // so that dragging over the button doesn't press it again.
+            layout.state.active_buttons.insert(
+                button_pos.clone(),
+                KeyState {
+                    pressed: PressType::Pressed,
+                    bounced: true,
+                    ..previous
+                },
+            );
+            return;
+        }
+        let pressed_at = button
+            .filter(|b| b.debounce_ms.is_some() || b.long_press.is_some())
+            .map(|_| time);
+
+        if let Some(button) = button {
+            let effect = match &button.edges {
+                Some(edges) => edges.on_press.as_ref()
+                    .map(|effect| (&effect.action, &effect.keycodes)),
+                None => Some(button.get_submission(&layout.state.flags)),
+            };
+            if let Some((action, keycodes)) = effect {
+                keyboard::log_press(
+                    &button.name.to_string_lossy(),
+                    keycodes,
+                    action,
+                );
+            }
+        }
+
         // Send messages
-        handle_press_key_cleaner(&layout.shape, submission, time, button_pos);
+        let action = handle_press_key_cleaner(
+            &layout.shape,
+            &layout.state.flags,
+            submission,
+            time,
+            button_pos,
+        );
+
+        if let Some(action) = action {
+            layout.apply_button_transition(button_pos, &action, time);
+            if let Action::ToggleFlag(flag) = &action {
+                layout.toggle_flag(flag);
+            }
+        }
     
         // Update state
         let find = layout.state.active_buttons.get(button_pos);
-        if let KeyState { pressed: PressType::Pressed } = find {
+        if let PressType::Pressed = find.pressed {
             log_print!(
                 logging::Level::Bug,
                 "Button {:?} was already pressed", button_pos,
@@ -1120,70 +2339,161 @@ mod seat {
         } else {
             layout.state.active_buttons.insert(
                 button_pos.clone(),
-                KeyState { pressed: PressType::Pressed },
+                KeyState {
+                    pressed: PressType::Pressed,
+                    pressed_at,
+                    bounced: false,
+                    held: false,
+                },
             );
         }
     }
 
-    fn handle_release_key_cleaner(
-        shape: &LayoutData,
+    /// Submits the long press effect of the button
+    /// once it's been held down for long enough.
+    /// The release afterwards submits nothing.
+    pub fn handle_hold_key(
+        layout: &mut Layout,
         submission: &mut Submission,
-        ui: Option<&UIBackend>,
+        time: Timestamp,
+        button_pos: &ButtonPosition,
+    ) {
+        let state = layout.state.active_buttons.get(button_pos).clone();
+        let (name, effect) = match layout.shape.get_button(button_pos) {
+            Some(Button { name, long_press: Some(effect), .. })
+                => (name, effect),
+            _ => return,
+        };
+        let is_due = match (&state.pressed, state.pressed_at) {
+            (PressType::Pressed, Some(pressed_at))
+                => time.0.wrapping_sub(pressed_at.0)
+                    >= layout.shape.long_press_ms,
+            _ => false,
+        };
+        if !is_due || state.bounced || state.held {
+            return;
+        }
+
+        keyboard::log_press(
+            &name.to_string_lossy(),
+            &effect.keycodes,
+            &effect.action,
+        );
+        submit_press(
+            submission,
+            time,
+            button_pos,
+            &effect.action,
+            &effect.keycodes,
+        );
+        // There's no UI to show preferences on
+        let release = Release {
+            ui: None,
+            time,
+            manager: None,
+            is_drag: false,
+        };
+        submit_release(
+            &layout.shape,
+            submission,
+            &release,
+            button_pos,
+            &effect.action,
+            &effect.keycodes,
+        );
+        layout.state.active_buttons.insert(
+            button_pos.clone(),
+            KeyState { held: true, ..state },
+        );
+    }
+
+    /// The circumstances of a release, the same for every action it sends
+    struct Release<'a> {
+        ui: Option<&'a UIBackend>,
         time: Timestamp,
         // TODO: intermediate measure:
         // passing state conditionally because it's only used for popover.
         // Eventually, it should be used for sumitting button events,
         // and passed always.
-        manager: Option<(&actors::popover::State, receiver::State)>,
-        button_pos: &ButtonPosition,
+        manager: Option<(&'a actors::popover::State, &'a receiver::State)>,
         is_drag: bool,
-    ) -> Action{
-        let button = shape.get_button(&button_pos).unwrap();
-        let action = button.action.clone();
+    }
 
-        // process non-view switching
-        match action.clone() {
+    /// Sends the messages of the release part of an action,
+    /// except for view switching
+    fn submit_release(
+        shape: &LayoutData,
+        submission: &mut Submission,
+        release: &Release,
+        button_pos: &ButtonPosition,
+        action: &Action,
+        keycodes: &Vec<KeyCode>,
+    ) {
+        let Release { ui, time, manager, is_drag } = *release;
+        match action {
             Action::Submit {
                 text: Some(text),
                 keys: _,
             } => submission.handle_release(
                 button_pos.into(),
-                SubmitData::Text(&text),
-                &button.keycodes,
+                SubmitData::Text(text),
+                keycodes,
                 time,
                 is_drag,
             ),
             Action::Submit {
                 text: None,
-                keys: _,
-            } => submission.handle_release(
-                button_pos.into(),
-                SubmitData::Keycodes,
-                &button.keycodes,
-                time,
-                is_drag,
-            ),
+                keys,
+            } => {
+                submission.handle_release(
+                    button_pos.into(),
+                    SubmitData::Keycodes,
+                    keycodes,
+                    time,
+                    is_drag,
+                );
+                let is_dead = !keys.is_empty()
+                    && keys.iter().all(|key| key.is_dead());
+                if is_dead && !is_drag {
+                    submission.handle_dead_key();
+                }
+            },
             Action::Erase => submission.handle_release(
                 button_pos.into(),
                 SubmitData::Erase,
-                &button.keycodes,
+                keycodes,
                 time,
                 is_drag,
             ),
+            Action::Enter => {
+                let multiline = submission.is_multiline();
+                submit_release(
+                    shape,
+                    submission,
+                    release,
+                    button_pos,
+                    &Action::Enter.resolve_enter(multiline),
+                    keycodes,
+                )
+            },
             Action::ApplyModifier(modifier) => {
                 // FIXME: key id is unneeded with stateless locks
                 let key_id = button_pos.into();
-                let gets_locked = !submission.is_modifier_active(modifier);
+                let gets_locked = !submission.is_modifier_active(*modifier);
                 match gets_locked {
                     true => submission.handle_add_modifier(
                         key_id,
-                        modifier, time,
+                        *modifier, time,
                     ),
                     false => submission.handle_drop_modifier(key_id, time),
                 }
             }
+            Action::ShowPreferences(PrefsTarget::Settings) => {
+                popover::open_settings();
+            },
             // only show when UI is present
-            Action::ShowPreferences => if let Some(ui) = &ui {
+            Action::ShowPreferences(PrefsTarget::Chooser)
+            => if let Some(ui) = &ui {
                 // only show when layout manager is available
                 if let Some((manager, app_state)) = manager {
                     let place = shape.find_button_place(button_pos);
@@ -1199,18 +2509,75 @@ mod seat {
                             ui.keyboard,
                             ui.widget_to_layout.reverse_bounds(bounds),
                             manager,
-                            app_state,
+                            app_state.clone(),
                         );
                     }
                 }
             },
+            Action::ClearPending => submission.handle_clear_pending(time),
+            Action::Clipboard(_) => submission.handle_shortcut(
+                keycodes,
+                time,
+            ),
+            Action::DBus { service, path, interface, method }
+                => submission.handle_dbus_call(service, path, interface, method),
             // Other keys are handled in view switcher before.
             _ => {}
         };
-        
-        action
     }
-    
+
+    /// Returns the action whose view and flag changes apply on release
+    fn handle_release_key_cleaner(
+        shape: &LayoutData,
+        flags: &HashSet<String>,
+        submission: &mut Submission,
+        release: &Release,
+        button_pos: &ButtonPosition,
+    ) -> Option<Action> {
+        let time = release.time;
+        let button = shape.get_button(&button_pos).unwrap();
+        match &button.edges {
+            Some(edges) => match &edges.on_release {
+                Some(effect) => {
+                    keyboard::log_press(
+                        &button.name.to_string_lossy(),
+                        &effect.keycodes,
+                        &effect.action,
+                    );
+                    submit_press(
+                        submission,
+                        time,
+                        button_pos,
+                        &effect.action,
+                        &effect.keycodes,
+                    );
+                    submit_release(
+                        shape, submission, release,
+                        button_pos,
+                        &effect.action,
+                        &effect.keycodes,
+                    );
+                    Some(effect.action.clone())
+                },
+                // Everything happened on press
+                None => None,
+            },
+            None => {
+                let (action, keycodes) = button.get_submission(flags);
+                if !allows_submission(button, submission.is_field_empty()) {
+                    return Some(action.clone());
+                }
+                submit_release(
+                    shape, submission, release,
+                    button_pos,
+                    action,
+                    keycodes,
+                );
+                Some(action.clone())
+            },
+        }
+    }
+
     /// Mutates layout and sends events.
     /// This split away from handle_release_key
     /// in order to pull at least some of the mutation away
@@ -1228,21 +2595,63 @@ mod seat {
         button_pos: &ButtonPosition,
         is_drag: bool,
     ) {
+        // Releasing after a drag away from the button cancels the press,
+        // so only a finished hold counts
+        if !is_drag {
+            handle_hold_key(layout, submission, time, button_pos);
+        }
+        let state = layout.state.active_buttons.get(button_pos).clone();
+        if let PressType::Released = state.pressed {
+            // The press was ignored because the button was disabled
+            return;
+        }
+        if state.bounced {
+            // The press was ignored, so there's nothing to release
+            layout.state.active_buttons.insert(
+                button_pos.clone(),
+                KeyState { bounced: false, ..state.into_released() },
+            );
+            return;
+        }
+
         // Send events
-        let action = handle_release_key_cleaner(
-            &layout.shape,
-            submission,
+        let release = Release {
             ui,
             time,
-            manager,
-            button_pos,
+            manager: manager.as_ref().map(|(popover, app)| (*popover, app)),
             is_drag,
-        );
+        };
+        let long_press = layout.shape.get_button(button_pos)
+            .and_then(|button| button.long_press.as_ref());
+        let action = match (state.held, long_press) {
+            // The long press effect replaced the usual one
+            (true, Some(effect)) => Some(effect.action.clone()),
+            _ => handle_release_key_cleaner(
+                &layout.shape,
+                &layout.state.flags,
+                submission,
+                &release,
+                button_pos,
+            ),
+        };
         
         // Apply state changes
-        layout.apply_view_transition(&action);
+        if let Some(action) = action {
+            layout.apply_button_transition(button_pos, &action, time);
+            if let Action::ToggleFlag(flag) = &action {
+                layout.toggle_flag(flag);
+            }
+        }
         
-        if let Presence::Missing = layout.state.active_buttons.remove(&button_pos) {
+        // The time of the press is needed to recognize the next one as a bounce
+        let presence = match state.pressed_at {
+            Some(_) => layout.state.active_buttons.insert(
+                button_pos.clone(),
+                KeyState { held: false, ..state.into_released() },
+            ),
+            None => layout.state.active_buttons.remove(&button_pos),
+        };
+        if let Presence::Missing = presence {
             log_print!(
                 logging::Level::Bug,
                 "No button to remove from pressed list: {:?}", button_pos
@@ -1255,7 +2664,11 @@ mod seat {
 mod test {
     use super::*;
 
-    use std::ffi::CString;
+    use crate::data::parsing;
+    use crate::logging::ProblemPanic;
+    use crate::vkeyboard::c::{ take_sent_keys, ZwpVirtualKeyboardV1 };
+    use std::ffi::{ CStr, CString };
+    use std::os::raw::{ c_char, c_void };
 
     pub fn make_button(
         name: String,
@@ -1263,39 +2676,932 @@ mod test {
         Button {
             name: CString::new(name.clone()).unwrap(),
             size: Size { width: 0f64, height: 0f64 },
+            offset: c::Point { x: 0f64, y: 0f64 },
             outline_name: CString::new("test").unwrap(),
+            repeat: Repeat::default(),
+            debounce_ms: None,
+            silent: false,
+            only_in_empty_field: false,
+            keep_view: false,
+            extra: BTreeMap::new(),
+            ime_aware: false,
+            disabled: false,
+            a11y_label: CString::new(name.clone()).unwrap(),
             label: Label::Text(CString::new(name).unwrap()),
+            toggled_label: None,
+            preview_label: None,
             action: Action::SetView("default".into()),
             keycodes: Vec::new(),
+            alternate: None,
+            long_press: None,
+            edges: None,
         }
     }
 
-    #[test]
-    fn latch_lock_unlock() {
-        let action = Action::LockView {
+    /// A layout made of the given views, starting in "base".
+    /// Everything else is left empty.
+    fn make_layout(views: HashMap<String, (c::Point, View)>) -> Layout {
+        Layout::new(
+            LayoutParseData {
+                views,
+                keymaps: Vec::new(),
+                clusters: Vec::new(),
+                default_view: "base".into(),
+                symbolmap: HashMap::new(),
+                shift_levels: HashMap::new(),
+                view_order: Vec::new(),
+                margins: Margins {
+                    top: 0.0,
+                    left: 0.0,
+                    right: 0.0,
+                    bottom: 0.0,
+                },
+                autocapitalize: false,
+                long_press_ms: DEFAULT_LONG_PRESS_MS,
+                modal_views: HashSet::new(),
+                key_indices: HashMap::new(),
+                metadata: Metadata::default(),
+                outlines: Vec::new(),
+            },
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+        )
+    }
+
+    #[test]
+    fn test_toggle_flag() {
+        let mut layout = make_layout(hashmap! {
+            "base".into() => (
+                c::Point { x: 0.0, y: 0.0 },
+                View::new(Vec::new()),
+            ),
+        });
+        layout.toggle_flag("numlock");
+        assert!(layout.state.flags.contains("numlock"));
+        layout.toggle_flag("numlock");
+        assert!(layout.state.flags.is_empty());
+    }
+
+    #[test]
+    fn test_debounce() {
+        let mut button = make_button("a".into());
+        button.debounce_ms = Some(50);
+        let pressed = KeyState {
+            pressed: PressType::Released,
+            pressed_at: Some(Timestamp(1000)),
+            bounced: false,
+            held: false,
+        };
+        assert!(seat::is_bounce(Some(&button), &pressed, Timestamp(1030)));
+        assert!(!seat::is_bounce(Some(&button), &pressed, Timestamp(1050)));
+        // Never pressed before
+        assert!(!seat::is_bounce(Some(&button), &RELEASED, Timestamp(1030)));
+
+        button.debounce_ms = None;
+        assert!(!seat::is_bounce(Some(&button), &pressed, Timestamp(1030)));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let data: parsing::Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "paste a"
+buttons:
+    paste:
+        action: { clipboard: paste }
+        disabled: true
+outlines:
+    default: { width: 1, height: 1 }
+"#).unwrap();
+        let data = data.build(ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let mut layout = Layout::new(
+            data,
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+        );
+        let position = |position_in_row| ButtonPosition {
+            view: "base".into(),
+            row: 0,
+            position_in_row,
+        };
+        let paste = layout.shape.get_button(&position(0)).cloned();
+        let a = layout.shape.get_button(&position(1)).cloned();
+        assert!(seat::is_disabled(&layout.state, paste.as_ref()));
+        assert!(!seat::is_disabled(&layout.state, a.as_ref()));
+
+        let name = CString::new("paste").unwrap();
+        c::squeek_layout_set_button_disabled(&mut layout, name.as_ptr(), 0);
+        assert_eq!(c::squeek_layout_is_button_disabled(&layout, name.as_ptr()), 0);
+        assert!(!seat::is_disabled(&layout.state, paste.as_ref()));
+
+        c::squeek_layout_set_button_disabled(&mut layout, name.as_ptr(), 1);
+        assert_eq!(c::squeek_layout_is_button_disabled(&layout, name.as_ptr()), 1);
+        assert!(seat::is_disabled(&layout.state, paste.as_ref()));
+
+        let mut submission = Submission::new(ZwpVirtualKeyboardV1::null(), None);
+        submission.use_layout(&layout.shape, Timestamp(0));
+        take_sent_keys();
+        let click = |layout: &mut Layout, submission: &mut Submission, position| {
+            seat::handle_press_key(layout, submission, Timestamp(0), &position);
+            seat::handle_release_key(
+                layout, submission,
+                None, Timestamp(10), None,
+                &position, false,
+            );
+        };
+        let name = CString::new("a").unwrap();
+        c::squeek_layout_set_button_disabled(&mut layout, name.as_ptr(), 1);
+        click(&mut layout, &mut submission, position(1));
+        assert_eq!(take_sent_keys(), vec![]);
+        assert!(layout.state.active_buttons.iter_pressed().next().is_none());
+
+        c::squeek_layout_set_button_disabled(&mut layout, name.as_ptr(), 0);
+        click(&mut layout, &mut submission, position(1));
+        assert_ne!(take_sent_keys(), vec![]);
+    }
+
+    #[test]
+    fn test_metadata() {
+        let data: parsing::Layout = serde_yaml::from_str(r#"
+name_display: "German"
+views:
+    base:
+        - "a"
+outlines:
+    default: { width: 1, height: 1 }
+"#).unwrap();
+        let data = data.build(ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let layout = Layout::new(
+            data,
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+        );
+        let name = c::squeek_layout_get_name_display(&layout);
+        assert_eq!(
+            unsafe { CStr::from_ptr(name) },
+            CString::new("German").unwrap().as_c_str(),
+        );
+        assert!(c::squeek_layout_get_author(&layout).is_null());
+        assert!(c::squeek_layout_get_license(&layout).is_null());
+    }
+
+    #[test]
+    fn test_outlines() {
+        extern "C" fn collect(
+            name: *const c_char,
+            bounds: c::Bounds,
+            relative_width: f64,
+            relative_height: f64,
+            user_data: *mut c_void,
+        ) {
+            let outlines = unsafe {
+                &mut *(user_data as *mut Vec<(String, c::Bounds, Size)>)
+            };
+            outlines.push((
+                unsafe { CStr::from_ptr(name) }.to_string_lossy().into(),
+                bounds,
+                Size { width: relative_width, height: relative_height },
+            ));
+        }
+
+        let data: parsing::Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a b"
+buttons:
+    b: { outline: half }
+outlines:
+    default: { width: 1, height: 1 }
+    half: { width: "50%", height: 1 }
+"#).unwrap();
+        let data = data.build(ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let layout = Layout::new(
+            data,
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+        );
+        let mut outlines: Vec<(String, c::Bounds, Size)> = Vec::new();
+        c::squeek_layout_foreach_outline(
+            &layout,
+            collect,
+            &mut outlines as *mut _ as *mut c_void,
+        );
+        assert_eq!(
+            outlines,
+            vec![
+                (
+                    "default".into(),
+                    c::Bounds { x: 0.0, y: 0.0, width: 1.0, height: 1.0 },
+                    Size { width: 0.0, height: 0.0 },
+                ),
+                (
+                    "half".into(),
+                    c::Bounds { x: 0.0, y: 0.0, width: 0.0, height: 1.0 },
+                    Size { width: 0.5, height: 0.0 },
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_long_press() {
+        let data: parsing::Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "period"
+buttons:
+    period: { text: ".", long_press_text: "…" }
+outlines:
+    default: { width: 1, height: 1 }
+"#).unwrap();
+        let data = data.build(ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let mut layout = Layout::new(
+            data,
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+        );
+        let position = ButtonPosition {
+            view: "base".into(),
+            row: 0,
+            position_in_row: 0,
+        };
+        let button = layout.shape.get_button(&position).unwrap().clone();
+        let long_press = button.long_press.clone().unwrap();
+        // Single keycodes get pressed, then released
+        let tap = |keycodes: &[KeyCode]| keycodes.iter()
+            .flat_map(|keycode| [(keycode.code - 8, 1), (keycode.code - 8, 0)])
+            .collect::<Vec<_>>();
+
+        let mut submission = Submission::new(ZwpVirtualKeyboardV1::null(), None);
+        submission.use_layout(&layout.shape, Timestamp(0));
+        take_sent_keys();
+        let release = |layout: &mut Layout, submission: &mut Submission, time|
+            seat::handle_release_key(
+                layout, submission,
+                None, Timestamp(time), None,
+                &position, false,
+            );
+
+        seat::handle_press_key(
+            &mut layout, &mut submission, Timestamp(0), &position,
+        );
+        release(&mut layout, &mut submission, 100);
+        assert_eq!(take_sent_keys(), tap(&button.keycodes));
+
+        // Released late, without a timer
+        seat::handle_press_key(
+            &mut layout, &mut submission, Timestamp(1000), &position,
+        );
+        release(&mut layout, &mut submission, 1000 + DEFAULT_LONG_PRESS_MS);
+        assert_eq!(take_sent_keys(), tap(&long_press.keycodes));
+
+        // The timer fires while the button is held
+        seat::handle_press_key(
+            &mut layout, &mut submission, Timestamp(3000), &position,
+        );
+        seat::handle_hold_key(
+            &mut layout, &mut submission,
+            Timestamp(3000 + DEFAULT_LONG_PRESS_MS - 1),
+            &position,
+        );
+        assert_eq!(take_sent_keys(), Vec::new());
+        seat::handle_hold_key(
+            &mut layout, &mut submission,
+            Timestamp(3000 + DEFAULT_LONG_PRESS_MS),
+            &position,
+        );
+        assert_eq!(take_sent_keys(), tap(&long_press.keycodes));
+        release(&mut layout, &mut submission, 5000);
+        assert_eq!(take_sent_keys(), Vec::new());
+
+        assert_eq!(
+            layout.shape.submittable_texts(),
+            vec![".".to_string(), "…".to_string()],
+        );
+        assert!(layout.shape.actions_json().contains(
+            r#""long_press":{"kind":"submit","text":"…""#,
+        ));
+    }
+
+    #[test]
+    fn test_edge_press_only() {
+        let data: parsing::Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "shift a"
+    upper:
+        - "shift a"
+buttons:
+    shift:
+        on_press:
+            locking:
+                lock_view: upper
+                unlock_view: base
+outlines:
+    default: { width: 1, height: 1 }
+"#).unwrap();
+        let data = data.build(ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let mut layout = Layout::new(
+            data,
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+        );
+        let mut submission = Submission::new(ZwpVirtualKeyboardV1::null(), None);
+        submission.use_layout(&layout.shape, Timestamp(0));
+        let mut tap = |layout: &mut Layout, position_in_row| {
+            let position = ButtonPosition {
+                view: layout.state.current_view.clone(),
+                row: 0,
+                position_in_row,
+            };
+            seat::handle_press_key(
+                layout, &mut submission, Timestamp(0), &position,
+            );
+            seat::handle_release_key(
+                layout, &mut submission,
+                None, Timestamp(0), None,
+                &position, false,
+            );
+        };
+        // The release does nothing, so the view stays latched
+        tap(&mut layout, 0);
+        assert_eq!(&layout.state.current_view, "upper");
+        assert_eq!(
+            layout.state.view_latched,
+            LatchedState::FromView("base".into()),
+        );
+        // Typing unlatches it
+        tap(&mut layout, 1);
+        assert_eq!(&layout.state.current_view, "base");
+    }
+
+    #[test]
+    fn test_dead_key_pending() {
+        let data: parsing::Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "acute e clear"
+buttons:
+    acute:
+        action:
+            dead_key:
+                keysym: dead_acute
+    clear: { action: clear }
+outlines:
+    default: { width: 1, height: 1 }
+"#).unwrap();
+        let data = data.build(ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let mut layout = Layout::new(
+            data,
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+        );
+        let mut submission = Submission::new(ZwpVirtualKeyboardV1::null(), None);
+        submission.use_layout(&layout.shape, Timestamp(0));
+        let mut tap = |layout: &mut Layout, position_in_row| {
+            let position = ButtonPosition {
+                view: "base".into(),
+                row: 0,
+                position_in_row,
+            };
+            seat::handle_press_key(
+                layout, &mut submission, Timestamp(0), &position,
+            );
+            seat::handle_release_key(
+                layout, &mut submission,
+                None, Timestamp(0), None,
+                &position, false,
+            );
+            submission.is_dead_key_pending()
+        };
+        assert!(tap(&mut layout, 0));
+        // Combined with the letter
+        assert!(!tap(&mut layout, 1));
+        assert!(tap(&mut layout, 0));
+        // Cancelled
+        assert!(!tap(&mut layout, 2));
+    }
+
+    #[test]
+    fn test_toggle_view() {
+        let data: parsing::Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "emoji"
+    emoji:
+        - "emoji"
+buttons:
+    emoji:
+        action:
+            toggle_view:
+                first: { view: "emoji", label: "E" }
+                second: { view: "base", label: "ABC" }
+outlines:
+    default: { width: 1, height: 1 }
+"#).unwrap();
+        let data = data.build(ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let mut layout = Layout::new(
+            data,
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+        );
+        let get_button = |layout: &Layout| {
+            layout.get_current_view().get_rows()[0].1.get_buttons()[0].1
+                .clone()
+        };
+        let label_text = |label: &Label| match label {
+            Label::Text(text) => text.to_str().unwrap().to_owned(),
+            Label::IconName(_) => panic!("Unexpected icon"),
+        };
+
+        let button = get_button(&layout);
+        assert_eq!(label_text(button.get_label(&layout.state.current_view)), "E");
+        layout.apply_view_transition(&button.action, Timestamp(0));
+        assert_eq!(&layout.state.current_view, "emoji");
+
+        let button = get_button(&layout);
+        assert_eq!(label_text(button.get_label(&layout.state.current_view)), "ABC");
+        layout.apply_view_transition(&button.action, Timestamp(0));
+        assert_eq!(&layout.state.current_view, "base");
+
+        let button = get_button(&layout);
+        assert_eq!(label_text(button.get_label(&layout.state.current_view)), "E");
+    }
+
+    #[test]
+    fn test_only_in_empty_field() {
+        let mut button = make_button("https".into());
+        button.action = Action::Submit {
+            text: Some(CString::new("https://").unwrap()),
+            keys: Vec::new(),
+        };
+        assert!(seat::allows_submission(&button, true));
+        assert!(seat::allows_submission(&button, false));
+
+        button.only_in_empty_field = true;
+        assert!(seat::allows_submission(&button, true));
+        assert!(!seat::allows_submission(&button, false));
+
+        // Only submitting depends on the field
+        button.action = Action::SetView("numbers".into());
+        assert!(seat::allows_submission(&button, false));
+    }
+
+    #[test]
+    fn test_edge_hits() {
+        fn find_at_left_edge(extend: &str) -> Option<(usize, usize)> {
+            let data: parsing::Layout = serde_yaml::from_str(&format!(r#"
+extend_edge_hits: {}
+margins: {{ top: 0, bottom: 0, side: 1 }}
+views:
+    base:
+        - "a b"
+    narrow:
+        - "c"
+outlines:
+    default: {{ width: 1, height: 1 }}
+"#, extend)).unwrap();
+            let data = data.build(ArrangementKind::Base, ProblemPanic).0
+                .unwrap();
+            let mut layout = Layout::new(
+                data,
+                ArrangementKind::Base,
+                ContentPurpose::Normal,
+            );
+            layout.state.current_view = "narrow".into();
+            layout.find_index_by_position(c::Point { x: 0.0, y: 0.5 })
+        }
+        assert_eq!(find_at_left_edge("false"), None);
+        assert_eq!(find_at_left_edge("true"), Some((0, 0)));
+    }
+
+    #[test]
+    fn test_offset_hits() {
+        let data: parsing::Layout = serde_yaml::from_str(r#"
+margins: { top: 0, bottom: 0, side: 0.5 }
+views:
+    base:
+        - "a b"
+buttons:
+    a: { outline: handle }
+    b: { outline: low }
+outlines:
+    default: { width: 1, height: 1 }
+    handle: { width: 1, height: 1, x: -0.5 }
+    low: { width: 1, height: 1, y: 0.5 }
+"#).unwrap();
+        let data = data.build(ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let layout = Layout::new(
+            data,
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+        );
+        assert_eq!(
+            layout.get_current_view().get_size(),
+            Size { width: 2.0, height: 1.5 },
+        );
+        // In the margin
+        assert_eq!(
+            layout.find_index_by_position(c::Point { x: -0.25, y: 0.5 }),
+            Some((0, 0)),
+        );
+        // Below the place of the button
+        assert_eq!(
+            layout.find_index_by_position(c::Point { x: 1.5, y: 1.25 }),
+            Some((0, 1)),
+        );
+    }
+
+    #[test]
+    fn test_submittable_texts() {
+        let data: parsing::Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a b BackSpace"
+    other:
+        - "a Return"
+outlines:
+    default: { width: 1, height: 1 }
+buttons:
+    b: { text: "bee" }
+    BackSpace: { action: erase }
+    Return: { keysym: Return }
+"#).unwrap();
+        let data = data.build(ArrangementKind::Base, ProblemPanic).0.unwrap();
+        let layout = Layout::new(
+            data,
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+        );
+        assert_eq!(
+            layout.shape.submittable_texts(),
+            vec!["a".to_string(), "bee".to_string()],
+        );
+    }
+
+    #[test]
+    fn test_actions_json() {
+        let data: parsing::Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a BackSpace numbers"
+    numbers:
+        - "1 base"
+outlines:
+    default: { width: 1, height: 1 }
+buttons:
+    BackSpace: { action: erase }
+    numbers: { action: { set_view: numbers } }
+    base: { action: { set_view: base } }
+"#).unwrap();
+        let data = data.build(ArrangementKind::Base, ProblemPanic).0.unwrap();
+        let layout = Layout::new(
+            data,
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+        );
+        assert_eq!(
+            layout.shape.actions_json(),
+            concat!(
+                r#"{"1":{"kind":"submit","text":"1","keys":["1"]},"#,
+                r#""BackSpace":{"kind":"erase"},"#,
+                r#""a":{"kind":"submit","text":"a","keys":["a"]},"#,
+                r#""base":{"kind":"set_view","view":"base"},"#,
+                r#""numbers":{"kind":"set_view","view":"numbers"}}"#,
+            ),
+        );
+    }
+
+    #[test]
+    fn test_changed_since() {
+        let data: parsing::Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a b c"
+    other:
+        - "b"
+outlines:
+    default: { width: 1, height: 1 }
+"#).unwrap();
+        let data = data.build(ArrangementKind::Base, ProblemPanic).0.unwrap();
+        let mut layout = Layout::new(
+            data,
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+        );
+        // One entry per distinct button, not per copy
+        let before = layout.shape.snapshot(&layout.state);
+        assert_eq!(before, vec![false, false, false]);
+
+        layout.state.active_buttons.insert(
+            ButtonPosition {
+                view: "base".into(),
+                row: 0,
+                position_in_row: 1,
+            },
+            KeyState {
+                pressed: PressType::Pressed,
+                pressed_at: None,
+                bounced: false,
+                held: false,
+            },
+        );
+        assert_eq!(
+            layout.shape.changed_since(&layout.state, &before),
+            vec![layout.shape.key_indices["b"]],
+        );
+        assert_eq!(
+            layout.shape.changed_since(&layout.state, &[]),
+            vec![0, 1, 2],
+        );
+    }
+
+    #[test]
+    fn test_keycode_for_char() {
+        let build = || parsing::Layout::from_resource("us").unwrap()
+            .build(ArrangementKind::Base, ProblemPanic).0
+            .unwrap();
+        let layout = Layout::new(
+            build(),
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+        );
+        let code = layout.shape.keycode_for_char('a').unwrap();
+        let button = layout.shape.get_button(&ButtonPosition {
+            view: "base".into(),
+            row: 1,
+            position_in_row: 0,
+        }).unwrap();
+        assert_eq!(button.name.to_str().unwrap(), "a");
+        assert_eq!(button.keycodes.iter().map(|k| k.code).collect::<Vec<_>>(), vec![code]);
+
+        let again = Layout::new(
+            build(),
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+        );
+        assert_eq!(again.shape.keycode_for_char('a'), Some(code));
+        assert_eq!(layout.shape.keycode_for_char('ж'), None);
+    }
+
+    #[test]
+    fn test_full_keymap_string() {
+        let data: parsing::Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "b a"
+buttons:
+    a: { shift_keysym: A }
+outlines:
+    default: { width: 1, height: 1 }
+"#).unwrap();
+        let data = data.build(ArrangementKind::Base, ProblemPanic).0.unwrap();
+        let layout = Layout::new(
+            data,
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+        );
+        let entry = |c: char, shifted: Option<&str>| keyboard::KeymapEntry {
+            key_name: format!("I{}", layout.shape.keycode_for_char(c).unwrap()),
+            symbol_name: Some(c.to_string()),
+            shift_symbol_name: shifted.map(String::from),
+        }.to_xkb_string();
+        assert_eq!(
+            layout.shape.full_keymap_string().to_str().unwrap(),
+            [entry('a', Some("A")), entry('b', None)].join("\n"),
+        );
+    }
+
+    #[test]
+    fn test_ime_aware() {
+        let data: parsing::Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a b"
+buttons:
+    a: { ime_aware: true }
+outlines:
+    default: { width: 1, height: 1 }
+"#).unwrap();
+        let data = data.build(ArrangementKind::Base, ProblemPanic).0.unwrap();
+        let layout = Layout::new(
+            data,
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+        );
+        assert!(layout.shape.is_button_ime_aware("a"));
+        assert!(!layout.shape.is_button_ime_aware("b"));
+        assert!(!layout.shape.is_button_ime_aware("missing"));
+    }
+
+    #[test]
+    fn test_preview_label() {
+        let data: parsing::Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a b"
+    upper:
+        - "A B"
+shift_view: upper
+outlines:
+    default: { width: 1, height: 1 }
+"#).unwrap();
+        let data = data.build(ArrangementKind::Base, ProblemPanic).0.unwrap();
+        let layout = Layout::new(
+            data,
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+        );
+        let preview = |x| {
+            let identity = c::Transformation {
+                origin_x: 0.0,
+                origin_y: 0.0,
+                scale_x: 1.0,
+                scale_y: 1.0,
+            };
+            let text = c::squeek_layout_get_preview_label_at(
+                &layout, x, 0.5, identity,
+            );
+            crate::util::c::as_str(&text).unwrap().map(String::from)
+        };
+        assert_eq!(preview(0.5), Some("A".into()));
+        assert_eq!(preview(1.5), Some("B".into()));
+        assert_eq!(preview(5.0), None);
+    }
+
+    #[test]
+    fn test_button_repeat() {
+        let data: parsing::Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "BackSpace a"
+buttons:
+    BackSpace:
+        action: erase
+        repeat_delay_ms: 800
+        repeat_interval_ms: 200
+outlines:
+    default: { width: 1, height: 1 }
+"#).unwrap();
+        let data = data.build(ArrangementKind::Base, ProblemPanic).0.unwrap();
+        let layout = Layout::new(
+            data,
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+        );
+        let delay = |name: &str| {
+            let name = CString::new(name).unwrap();
+            c::squeek_layout_get_button_repeat_delay_ms(&layout, name.as_ptr())
+        };
+        let interval = |name: &str| {
+            let name = CString::new(name).unwrap();
+            c::squeek_layout_get_button_repeat_interval_ms(
+                &layout,
+                name.as_ptr(),
+            )
+        };
+        assert_eq!((delay("BackSpace"), interval("BackSpace")), (800, 200));
+        assert_eq!((delay("a"), interval("a")), (0, 0));
+        assert_eq!((delay("missing"), interval("missing")), (0, 0));
+    }
+
+    #[test]
+    fn test_fits() {
+        let data: parsing::Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "a b c d"
+outlines:
+    default: { width: 1, height: 1 }
+"#).unwrap();
+        let data = data.build(ArrangementKind::Base, ProblemPanic).0.unwrap();
+        let layout = Layout::new(
+            data,
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+        );
+        assert!(layout.shape.fits(400.0, 100.0, 40.0).fits());
+
+        let report = layout.shape.fits(120.0, 100.0, 40.0);
+        assert_eq!(report.overflowing, Vec::new());
+        assert_eq!(report.too_small.len(), 4);
+        assert_eq!(
+            report.too_small[0],
+            ButtonPosition { view: "base".into(), row: 0, position_in_row: 0 },
+        );
+    }
+
+    #[test]
+    fn test_required_protocols() {
+        let needs = |yaml: &str| {
+            let data: parsing::Layout = serde_yaml::from_str(yaml).unwrap();
+            let data = data.build(ArrangementKind::Base, ProblemPanic).0
+                .unwrap();
+            Layout::new(data, ArrangementKind::Base, ContentPurpose::Normal)
+                .shape.required_protocols()
+        };
+        assert_eq!(
+            needs(r#"
+views:
+    base:
+        - "a b"
+outlines:
+    default: { width: 1, height: 1 }
+"#),
+            ProtocolNeeds { keysyms: false, text_commit: true },
+        );
+        assert_eq!(
+            needs(r#"
+views:
+    base:
+        - "Left BackSpace"
+outlines:
+    default: { width: 1, height: 1 }
+buttons:
+    Left: { keysym: Left }
+    BackSpace: { action: erase }
+"#),
+            ProtocolNeeds { keysyms: true, text_commit: false },
+        );
+    }
+
+    #[test]
+    fn test_render_tree() {
+        let view = View::new(vec![(
+            0.0,
+            Row::new(vec![
+                (
+                    0.0,
+                    Button {
+                        size: Size { width: 2.0, height: 1.0 },
+                        ..make_button("a".into())
+                    },
+                ),
+                (
+                    2.0,
+                    Button {
+                        size: Size { width: 1.0, height: 1.0 },
+                        label: Label::IconName(CString::new("erase").unwrap()),
+                        ..make_button("BackSpace".into())
+                    },
+                ),
+            ]),
+        )]);
+        let shape = make_layout(hashmap! {
+            "base".into() => (c::Point { x: 0.5, y: 0.0 }, view),
+        }).shape;
+        let tree = shape.render_tree();
+        assert_eq!(tree.views.len(), 1);
+        let buttons = &tree.views[0].rows[0];
+        assert_eq!(
+            buttons.iter().map(|b| &b.label).collect::<Vec<_>>(),
+            vec![
+                &RenderLabel::Text("a".into()),
+                &RenderLabel::Icon("erase".into()),
+            ],
+        );
+        assert_eq!(
+            buttons[1].bounds,
+            c::Bounds { x: 2.5, y: 0.0, width: 1.0, height: 1.0 },
+        );
+    }
+
+    #[test]
+    fn latch_lock_unlock() {
+        let action = Action::LockView {
             lock: "lock".into(),
             unlock: "unlock".into(),
             latches: true,
             looks_locked_from: vec![],
+            double_tap_lock: false,
         };
 
         assert_eq!(
-            Layout::process_action_for_view(&action, "unlock", &LatchedState::Not),
+            Layout::process_action_for_view(&action, "unlock", &LatchedState::Not, false),
             (ViewTransition::ChangeTo("lock"), LatchedState::FromView("unlock".into())),
         );
 
         assert_eq!(
-            Layout::process_action_for_view(&action, "lock", &LatchedState::FromView("unlock".into())),
+            Layout::process_action_for_view(&action, "lock", &LatchedState::FromView("unlock".into()), false),
             (ViewTransition::NoChange, LatchedState::Not),
         );
 
         assert_eq!(
-            Layout::process_action_for_view(&action, "lock", &LatchedState::Not),
+            Layout::process_action_for_view(&action, "lock", &LatchedState::Not, false),
             (ViewTransition::ChangeTo("unlock"), LatchedState::Not),
         );
 
         assert_eq!(
-            Layout::process_action_for_view(&Action::Erase, "lock", &LatchedState::FromView("base".into())),
+            Layout::process_action_for_view(&Action::Erase, "lock", &LatchedState::FromView("base".into()), false),
             (ViewTransition::UnlatchAll, LatchedState::Not),
         );
     }
@@ -1307,6 +3613,7 @@ mod test {
             unlock: "base".into(),
             latches: true,
             looks_locked_from: vec![],
+            double_tap_lock: false,
         };
 
         let submit = Action::Erase;
@@ -1331,45 +3638,121 @@ mod test {
             ]),
         )]);
 
-        let mut layout = Layout {
-            state: LayoutState {
-                current_view: "base".into(),
-                view_latched: LatchedState::Not,
-                active_buttons: ActiveButtons(HashMap::new()),
-            },
-            shape: LayoutData {
-                keymaps: Vec::new(),
-                kind: ArrangementKind::Base,
-                margins: Margins {
-                    top: 0.0,
-                    left: 0.0,
-                    right: 0.0,
-                    bottom: 0.0,
-                },
-                views: hashmap! {
-                    // Both can use the same structure.
-                    // Switching doesn't depend on the view shape
-                    // as long as the switching button is present.
-                    "base".into() => (c::Point { x: 0.0, y: 0.0 }, view.clone()),
-                    "locked".into() => (c::Point { x: 0.0, y: 0.0 }, view),
-                },
-                purpose: ContentPurpose::Normal,
-            },
-        };
+        let mut layout = make_layout(hashmap! {
+            // Both can use the same structure.
+            // Switching doesn't depend on the view shape
+            // as long as the switching button is present.
+            "base".into() => (c::Point { x: 0.0, y: 0.0 }, view.clone()),
+            "locked".into() => (c::Point { x: 0.0, y: 0.0 }, view),
+        });
 
         // Basic cycle
-        layout.apply_view_transition(&switch);
+        layout.apply_view_transition(&switch, Timestamp(0));
         assert_eq!(&layout.state.current_view, "locked");
-        layout.apply_view_transition(&switch);
+        layout.apply_view_transition(&switch, Timestamp(0));
         assert_eq!(&layout.state.current_view, "locked");
-        layout.apply_view_transition(&submit);
+        layout.apply_view_transition(&submit, Timestamp(0));
         assert_eq!(&layout.state.current_view, "locked");
-        layout.apply_view_transition(&switch);
+        layout.apply_view_transition(&switch, Timestamp(0));
         assert_eq!(&layout.state.current_view, "base");
-        layout.apply_view_transition(&switch);
+        layout.apply_view_transition(&switch, Timestamp(0));
         // Unlatch
         assert_eq!(&layout.state.current_view, "locked");
-        layout.apply_view_transition(&submit);
+        layout.apply_view_transition(&submit, Timestamp(0));
+        assert_eq!(&layout.state.current_view, "base");
+    }
+
+    #[test]
+    fn test_keep_view() {
+        let data: parsing::Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "shift a"
+    upper:
+        - "shift A B"
+buttons:
+    shift:
+        action:
+            locking: { lock_view: upper, unlock_view: base }
+    A: { keep_view: true }
+outlines:
+    default: { width: 1, height: 1 }
+"#).unwrap();
+        let data = data.build(ArrangementKind::Base, ProblemPanic).0.unwrap();
+        let mut layout = Layout::new(
+            data,
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+        );
+        let position = |position_in_row| ButtonPosition {
+            view: "upper".into(),
+            row: 0,
+            position_in_row,
+        };
+        let shift = layout.shape.get_button(&position(0)).unwrap().action.clone();
+        let submit = layout.shape.get_button(&position(1)).unwrap().action.clone();
+
+        // Latched
+        layout.apply_view_transition(&shift, Timestamp(0));
+        assert_eq!(&layout.state.current_view, "upper");
+
+        layout.apply_button_transition(&position(1), &submit, Timestamp(10));
+        assert_eq!(&layout.state.current_view, "upper");
+        // A usual button still unlatches
+        layout.apply_button_transition(&position(2), &submit, Timestamp(20));
+        assert_eq!(&layout.state.current_view, "base");
+    }
+
+    #[test]
+    fn test_modal_view() {
+        let data: parsing::Layout = serde_yaml::from_str(r#"
+views:
+    base:
+        - "emoji a"
+    emoji:
+        - "abc smile"
+modal_views:
+    emoji: true
+buttons:
+    emoji:
+        action:
+            locking: { lock_view: emoji, unlock_view: base }
+    abc:
+        action:
+            set_view: base
+    smile: { text: "🙂" }
+outlines:
+    default: { width: 1, height: 1 }
+"#).unwrap();
+        let data = data.build(ArrangementKind::Base, ProblemPanic).0.unwrap();
+        let mut layout = Layout::new(
+            data,
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+        );
+        assert!(layout.shape.modal_views.contains("emoji"));
+        let position = |view: &str, position_in_row| ButtonPosition {
+            view: view.into(),
+            row: 0,
+            position_in_row,
+        };
+        let emoji = layout.shape.get_button(&position("base", 0)).unwrap()
+            .action.clone();
+        let abc = layout.shape.get_button(&position("emoji", 0)).unwrap()
+            .action.clone();
+        let smile = layout.shape.get_button(&position("emoji", 1)).unwrap()
+            .action.clone();
+
+        // Latched
+        layout.apply_view_transition(&emoji, Timestamp(0));
+        assert_eq!(&layout.state.current_view, "emoji");
+
+        layout.apply_button_transition(&position("emoji", 1), &smile, Timestamp(10));
+        assert_eq!(&layout.state.current_view, "emoji");
+        layout.apply_button_transition(&position("emoji", 1), &smile, Timestamp(20));
+        assert_eq!(&layout.state.current_view, "emoji");
+        // Leaving on purpose still works
+        layout.apply_button_transition(&position("emoji", 0), &abc, Timestamp(30));
         assert_eq!(&layout.state.current_view, "base");
     }
 
@@ -1380,6 +3763,7 @@ mod test {
             unlock: "base".into(),
             latches: true,
             looks_locked_from: vec![],
+            double_tap_lock: false,
         };
 
         let unswitch = Action::LockView {
@@ -1387,6 +3771,7 @@ mod test {
             unlock: "unlocked".into(),
             latches: false,
             looks_locked_from: vec![],
+            double_tap_lock: false,
         };
 
         let submit = Action::Erase;
@@ -1411,36 +3796,18 @@ mod test {
             ]),
         )]);
 
-        let mut layout = Layout {
-            state: LayoutState {
-                current_view: "base".into(),
-                view_latched: LatchedState::Not,
-                active_buttons: ActiveButtons(HashMap::new()),
-            },
-            shape: LayoutData {
-                keymaps: Vec::new(),
-                kind: ArrangementKind::Base,
-                margins: Margins {
-                    top: 0.0,
-                    left: 0.0,
-                    right: 0.0,
-                    bottom: 0.0,
-                },
-                views: hashmap! {
-                    // Both can use the same structure.
-                    // Switching doesn't depend on the view shape
-                    // as long as the switching button is present.
-                    "base".into() => (c::Point { x: 0.0, y: 0.0 }, view.clone()),
-                    "locked".into() => (c::Point { x: 0.0, y: 0.0 }, view.clone()),
-                    "unlocked".into() => (c::Point { x: 0.0, y: 0.0 }, view),
-                },
-                purpose: ContentPurpose::Normal,
-            },
-        };
+        let mut layout = make_layout(hashmap! {
+            // Both can use the same structure.
+            // Switching doesn't depend on the view shape
+            // as long as the switching button is present.
+            "base".into() => (c::Point { x: 0.0, y: 0.0 }, view.clone()),
+            "locked".into() => (c::Point { x: 0.0, y: 0.0 }, view.clone()),
+            "unlocked".into() => (c::Point { x: 0.0, y: 0.0 }, view),
+        });
 
-        layout.apply_view_transition(&switch);
+        layout.apply_view_transition(&switch, Timestamp(0));
         assert_eq!(&layout.state.current_view, "locked");
-        layout.apply_view_transition(&unswitch);
+        layout.apply_view_transition(&unswitch, Timestamp(0));
         assert_eq!(&layout.state.current_view, "unlocked");
     }
 
@@ -1451,6 +3818,7 @@ mod test {
             unlock: "base".into(),
             latches: true,
             looks_locked_from: vec![],
+            double_tap_lock: false,
         };
 
         let switch_again = Action::LockView {
@@ -1458,6 +3826,7 @@ mod test {
             unlock: "locked".into(),
             latches: true,
             looks_locked_from: vec![],
+            double_tap_lock: false,
         };
 
         let submit = Action::Erase;
@@ -1482,45 +3851,130 @@ mod test {
             ]),
         )]);
 
-        let mut layout = Layout {
-            state: LayoutState {
-                current_view: "base".into(),
-                view_latched: LatchedState::Not,
-                active_buttons: ActiveButtons(HashMap::new()),
-            },
-            shape: LayoutData {
-                keymaps: Vec::new(),
-                kind: ArrangementKind::Base,
-                margins: Margins {
-                    top: 0.0,
-                    left: 0.0,
-                    right: 0.0,
-                    bottom: 0.0,
-                },
-                views: hashmap! {
-                    // All can use the same structure.
-                    // Switching doesn't depend on the view shape
-                    // as long as the switching button is present.
-                    "base".into() => (c::Point { x: 0.0, y: 0.0 }, view.clone()),
-                    "locked".into() => (c::Point { x: 0.0, y: 0.0 }, view.clone()),
-                    "ĄĘ".into() => (c::Point { x: 0.0, y: 0.0 }, view),
-                },
-                purpose: ContentPurpose::Normal,
-            },
-        };
+        let mut layout = make_layout(hashmap! {
+            // All can use the same structure.
+            // Switching doesn't depend on the view shape
+            // as long as the switching button is present.
+            "base".into() => (c::Point { x: 0.0, y: 0.0 }, view.clone()),
+            "locked".into() => (c::Point { x: 0.0, y: 0.0 }, view.clone()),
+            "ĄĘ".into() => (c::Point { x: 0.0, y: 0.0 }, view),
+        });
 
         // Latch twice, then Ąto-unlatch across 2 levels
-        layout.apply_view_transition(&switch);
+        layout.apply_view_transition(&switch, Timestamp(0));
         println!("{:?}", layout.state.view_latched);
         assert_eq!(&layout.state.current_view, "locked");
-        layout.apply_view_transition(&switch_again);
+        layout.apply_view_transition(&switch_again, Timestamp(0));
         println!("{:?}", layout.state.view_latched);
         assert_eq!(&layout.state.current_view, "ĄĘ");
-        layout.apply_view_transition(&submit);
+        layout.apply_view_transition(&submit, Timestamp(0));
         println!("{:?}", layout.state.view_latched);
         assert_eq!(&layout.state.current_view, "base");
     }
 
+    #[test]
+    fn double_tap_lock_layout() {
+        let switch = Action::LockView {
+            lock: "locked".into(),
+            unlock: "base".into(),
+            latches: true,
+            looks_locked_from: vec![],
+            double_tap_lock: true,
+        };
+
+        let view = View::new(vec![(
+            0.0,
+            Row::new(vec![(
+                0.0,
+                Button {
+                    action: switch.clone(),
+                    ..make_button("switch".into())
+                },
+            )]),
+        )]);
+
+        let mut layout = make_layout(hashmap! {
+            "base".into() => (c::Point { x: 0.0, y: 0.0 }, view.clone()),
+            "locked".into() => (c::Point { x: 0.0, y: 0.0 }, view),
+        });
+
+        // Two quick presses lock
+        layout.apply_view_transition(&switch, Timestamp(1000));
+        assert_eq!(&layout.state.current_view, "locked");
+        assert_eq!(layout.state.view_latched, LatchedState::FromView("base".into()));
+        layout.apply_view_transition(&switch, Timestamp(1100));
+        assert_eq!(&layout.state.current_view, "locked");
+        assert_eq!(layout.state.view_latched, LatchedState::Not);
+        // Unlock
+        layout.apply_view_transition(&switch, Timestamp(1200));
+        assert_eq!(&layout.state.current_view, "base");
+
+        // Two slow presses unlock
+        layout.apply_view_transition(&switch, Timestamp(2000));
+        assert_eq!(&layout.state.current_view, "locked");
+        layout.apply_view_transition(&switch, Timestamp(3000));
+        assert_eq!(&layout.state.current_view, "base");
+        assert_eq!(layout.state.view_latched, LatchedState::Not);
+    }
+
+    #[test]
+    fn double_tap_lock_other_button() {
+        let switch = Action::LockView {
+            lock: "locked".into(),
+            unlock: "base".into(),
+            latches: true,
+            looks_locked_from: vec![],
+            double_tap_lock: true,
+        };
+        let other_switch = Action::LockView {
+            lock: "locked".into(),
+            unlock: "base".into(),
+            latches: true,
+            looks_locked_from: vec![],
+            double_tap_lock: false,
+        };
+
+        let view = View::new(vec![]);
+        let mut layout = make_layout(hashmap! {
+            "base".into() => (c::Point { x: 0.0, y: 0.0 }, view.clone()),
+            "locked".into() => (c::Point { x: 0.0, y: 0.0 }, view),
+        });
+
+        // A quick press of a different button is not a double tap
+        layout.apply_view_transition(&other_switch, Timestamp(1000));
+        assert_eq!(&layout.state.current_view, "locked");
+        layout.apply_view_transition(&switch, Timestamp(1100));
+        assert_eq!(&layout.state.current_view, "base");
+        assert_eq!(layout.state.view_latched, LatchedState::Not);
+    }
+
+    #[test]
+    fn previous_view() {
+        let view = View::new(vec![]);
+        let mut layout = make_layout(hashmap! {
+            "base".into() => (c::Point { x: 0.0, y: 0.0 }, view.clone()),
+            "numbers".into() => (c::Point { x: 0.0, y: 0.0 }, view.clone()),
+            "symbols".into() => (c::Point { x: 0.0, y: 0.0 }, view),
+        });
+        let back = Action::PreviousView;
+
+        layout.apply_view_transition(&Action::SetView("numbers".into()), Timestamp(0));
+        layout.apply_view_transition(&Action::SetView("symbols".into()), Timestamp(0));
+        assert_eq!(&layout.state.current_view, "symbols");
+        layout.apply_view_transition(&back, Timestamp(0));
+        assert_eq!(&layout.state.current_view, "numbers");
+        layout.apply_view_transition(&back, Timestamp(0));
+        assert_eq!(&layout.state.current_view, "base");
+        // No history left
+        layout.apply_view_transition(&back, Timestamp(0));
+        assert_eq!(&layout.state.current_view, "base");
+
+        // Coming back to a visited view by other means forgets the way.
+        layout.apply_view_transition(&Action::SetView("numbers".into()), Timestamp(0));
+        layout.apply_view_transition(&Action::SetView("base".into()), Timestamp(0));
+        assert_eq!(layout.state.view_history, Vec::<String>::new());
+    }
+
     #[test]
     fn check_centering() {
         //    A B
@@ -1591,21 +4045,11 @@ mod test {
                 )]),
             ),
         ]);
-        let layout = LayoutData {
-            keymaps: Vec::new(),
-            kind: ArrangementKind::Base,
-            // Lots of bottom margin
-            margins: Margins {
-                top: 0.0,
-                left: 0.0,
-                right: 0.0,
-                bottom: 1.0,
-            },
-            views: hashmap! {
-                String::new() => (c::Point { x: 0.0, y: 0.0 }, view),
-            },
-            purpose: ContentPurpose::Normal,
-        };
+        let mut layout = make_layout(hashmap! {
+            String::new() => (c::Point { x: 0.0, y: 0.0 }, view),
+        }).shape;
+        // Lots of bottom margin
+        layout.margins.bottom = 1.0;
         assert_eq!(
             layout.calculate_inner_size(),
             Size { width: 1.0, height: 1.0 }
@@ -1641,20 +4085,9 @@ mod test {
                 )]),
             ),
         ]);
-        let layout = LayoutData {
-            keymaps: Vec::new(),
-            kind: ArrangementKind::Base,
-            margins: Margins {
-                top: 0.0,
-                left: 0.0,
-                right: 0.0,
-                bottom: 0.0,
-            },
-            views: hashmap! {
-                String::new() => (c::Point { x: 0.0, y: 0.0 }, view),
-            },
-            purpose: ContentPurpose::Normal,
-        };
+        let layout = make_layout(hashmap! {
+            String::new() => (c::Point { x: 0.0, y: 0.0 }, view),
+        }).shape;
         let transformation = layout.calculate_transformation(
             Size { width: 100.0, height: 100.0 }
         );
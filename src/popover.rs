@@ -225,6 +225,12 @@ fn translate_layout_names(layouts: &Vec<LayoutId>) -> Vec<OwnedTranslation> {
         .collect()
 }
 
+/// Opens the panel of the system settings where keyboards are chosen
+pub fn open_settings() {
+    let s = CString::new("region").unwrap();
+    unsafe { c::popover_open_settings_panel(s.as_ptr()) };
+}
+
 pub fn show(
     window: EekGtkKeyboard,
     position: Bounds,
@@ -347,10 +353,7 @@ pub fn show(
 
     let settings_action = gio::SimpleAction::new("settings", None);
     settings_action.set_enabled(popover.settings_active);
-    settings_action.connect_activate(move |_, _| {
-        let s = CString::new("region").unwrap();
-        unsafe { c::popover_open_settings_panel(s.as_ptr()) };
-    });
+    settings_action.connect_activate(move |_, _| open_settings());
     action_group.add_action(&settings_action);
 
     menu.insert_action_group("popup", Some(&action_group));
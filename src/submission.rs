@@ -26,6 +26,7 @@ use crate::imservice;
 use crate::imservice::IMService;
 use crate::keyboard::{ KeyCode, KeyStateId, Modifiers, PressType };
 use crate::layout;
+use crate::logging;
 use crate::util::vec_remove;
 use crate::vkeyboard;
 use crate::vkeyboard::VirtualKeyboard;
@@ -66,7 +67,7 @@ pub mod c {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub struct Timestamp(pub u32);
 
 #[derive(Clone)]
@@ -83,6 +84,11 @@ pub struct Submission {
     pressed: Vec<(KeyStateId, SubmittedAction)>,
     keymap_fds: Vec<vkeyboard::c::KeyMap>,
     keymap_idx: Option<usize>,
+    /// A dead key was sent, and the application waits for the next key
+    /// to combine it with
+    dead_key_pending: bool,
+    /// Connected on the first D-Bus call
+    dbus_connection: Option<gio::DBusConnection>,
 }
 
 pub enum SubmitData<'a> {
@@ -100,6 +106,8 @@ impl Submission {
             pressed: Vec::new(),
             keymap_fds: Vec::new(),
             keymap_idx: None,
+            dead_key_pending: false,
+            dbus_connection: None,
         }
     }
 
@@ -132,8 +140,11 @@ impl Submission {
         time: Timestamp,
     ) {
         let mods_are_on = !self.modifiers_active.is_empty();
+        // Committed text would go around the dead key,
+        // so it would never get combined
+        let needs_keys = mods_are_on || self.dead_key_pending;
 
-        let was_committed_as_text = match (&mut self.imservice, mods_are_on) {
+        let was_committed_as_text = match (&mut self.imservice, needs_keys) {
             (Some(imservice), false) => {
                 enum Outcome {
                     Submitted(Result<(), imservice::SubmitError>),
@@ -204,6 +215,7 @@ impl Submission {
         };
         
         self.pressed.push((key_id, submit_action));
+        self.dead_key_pending = false;
     }
     
     pub fn handle_release(
@@ -301,6 +313,103 @@ impl Submission {
         self.virtual_keyboard.set_modifiers_state(raw_modifiers);
     }
 
+    /// Presses and releases the keys with only Control held,
+    /// the way a keyboard shortcut is typed.
+    /// Modifiers locked by the user are restored afterwards.
+    pub fn handle_shortcut(&mut self, keycodes: &[KeyCode], time: Timestamp) {
+        for keycode in keycodes {
+            // Changing keymaps clears modifiers, so set Control afterwards.
+            self.select_keymap(keycode.keymap_idx, time);
+            self.virtual_keyboard.set_modifiers_state(Modifiers::CONTROL);
+            self.virtual_keyboard.switch(
+                keycode.code,
+                PressType::Pressed,
+                time,
+            );
+            self.virtual_keyboard.switch(
+                keycode.code,
+                PressType::Released,
+                time,
+            );
+        }
+        self.update_modifiers();
+    }
+
+    /// Remembers that the keys just sent make up a dead key,
+    /// so that the next submission uses keys too.
+    pub fn handle_dead_key(&mut self) {
+        self.dead_key_pending = true;
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_dead_key_pending(&self) -> bool {
+        self.dead_key_pending
+    }
+
+    /// Drops the state which would otherwise change the next submission.
+    /// Those are the locked modifiers, and a dead key waiting for a letter.
+    /// The application may still combine the dead key on its own.
+    pub fn handle_clear_pending(&mut self, _time: Timestamp) {
+        self.clear_all_modifiers();
+        self.dead_key_pending = false;
+    }
+
+    /// Calls a method on the session bus without arguments.
+    /// Doesn't wait for the reply. Only failures are reported.
+    pub fn handle_dbus_call(
+        &mut self,
+        service: &str,
+        path: &str,
+        interface: &str,
+        method: &str,
+    ) {
+        let connection = match &self.dbus_connection {
+            Some(connection) => connection,
+            None => match gio::bus_get_sync(
+                gio::BusType::Session,
+                gio::Cancellable::NONE,
+            ) {
+                Ok(connection) => self.dbus_connection.insert(connection),
+                Err(e) => {
+                    log_print!(
+                        logging::Level::Warning,
+                        "Can't connect to the session bus: {}", e,
+                    );
+                    return;
+                },
+            },
+        };
+        let description = format!("{}.{} on {}", interface, method, service);
+        connection.call(
+            Some(service), path, interface, method,
+            None, None,
+            gio::DBusCallFlags::NONE,
+            -1,
+            gio::Cancellable::NONE,
+            move |result| if let Err(e) = result {
+                log_print!(
+                    logging::Level::Warning,
+                    "Calling {} failed: {}", description, e,
+                );
+            },
+        );
+    }
+
+    /// The text field has no text in it.
+    /// Without a text field, or without knowing its text, it counts as empty.
+    pub fn is_field_empty(&self) -> bool {
+        self.imservice.as_ref()
+            .map(|imservice| imservice.is_field_empty())
+            .unwrap_or(true)
+    }
+
+    /// The text field takes more than one line
+    pub fn is_multiline(&self) -> bool {
+        self.imservice.as_ref()
+            .map(|imservice| imservice.is_multiline())
+            .unwrap_or(false)
+    }
+
     pub fn is_modifier_active(&self, modifier: Modifier) -> bool {
         self.modifiers_active.iter()
             .position(|(_id, m)| *m == modifier)
@@ -1,7 +1,10 @@
 /*! Testing functionality */
 
-use crate::data::parsing::Layout;
+use crate::data::parsing::{ Layout, Length, Outline, WarningHandler };
+use crate::layout::ArrangementKind;
 use crate::logging;
+use std::collections::HashMap;
+use std::time::{ Duration, Instant };
 use xkbcommon::xkb;
 
 
@@ -20,6 +23,8 @@ impl logging::Handler for CountAndPrint {
     }
 }
 
+impl WarningHandler for CountAndPrint {}
+
 impl CountAndPrint {
     fn new() -> CountAndPrint {
         CountAndPrint(0)
@@ -71,8 +76,19 @@ fn check_sym_presence(
 }
 
 fn check_layout(layout: Layout, allow_missing_return: bool) {
+    // Multi-character names without a description are legal,
+    // but likely typos, so they are only pointed out.
+    for name in layout.missing_buttons() {
+        log_print!(
+            logging::Level::Surprise,
+            "Button {} is not described and will submit its name",
+            name,
+        );
+    }
+
     let handler = CountAndPrint::new();
-    let (layout, mut handler) = layout.build(handler);
+    // The arrangement only affects labels, which are not checked
+    let (layout, mut handler) = layout.build(ArrangementKind::Base, handler);
 
     if handler.0 > 0 {
         println!("{} problems while parsing layout", handler.0)
@@ -135,3 +151,41 @@ fn check_layout(layout: Layout, allow_missing_return: bool) {
         panic!("Layout contains mistakes");
     }
 }
+
+/// Ignores all messages
+struct Discard;
+
+impl logging::Handler for Discard {
+    fn handle(&mut self, _level: logging::Level, _message: &str) {}
+}
+
+impl WarningHandler for Discard {}
+
+/// Builds a generated layout with `instances` buttons
+/// using `names` different names, 20 buttons in a row.
+/// Returns the time spent building.
+pub fn time_build(instances: usize, names: usize) -> Duration {
+    let button_names: Vec<String> = (0..instances)
+        .map(|i| format!("k{}", i % names))
+        .collect();
+    let rows: Vec<String> = button_names.chunks(20)
+        .map(|row| row.join(" "))
+        .collect();
+    let layout = Layout {
+        views: HashMap::from([("base".to_string(), rows)]),
+        default_outline: Some(Outline {
+            width: Length::Absolute(1.0),
+            height: Length::Absolute(1.0),
+            x: 0.0,
+            y: 0.0,
+            wide: None,
+        }),
+        ..Layout::default()
+    };
+
+    let start = Instant::now();
+    let (out, _) = layout.build(ArrangementKind::Base, Discard);
+    let elapsed = start.elapsed();
+    out.expect("Generated layout doesn't build");
+    elapsed
+}
@@ -13,6 +13,8 @@ pub mod c {
     
     use std::cell::RefCell;
     use std::ffi::{ CStr, CString };
+    use std::mem::ManuallyDrop;
+    use std::ops::Deref;
     use std::os::raw::c_char;
     use std::rc::Rc;
     use std::str::Utf8Error;
@@ -65,8 +67,92 @@ pub mod c {
         fn test_null_str() {
             assert_eq!(as_str(&ptr::null()), Ok(None))
         }
+
+        #[test]
+        fn test_wrapped_refcount() {
+            let wrapped = Wrapped::new(5u32);
+            {
+                let rc = wrapped.clone_ref();
+                assert_eq!(Rc::strong_count(&rc), 2);
+                *rc.borrow_mut() = 6;
+            }
+            let copy = wrapped.clone();
+            assert_eq!(copy.clone_owned(), 6);
+            let rc = unsafe { copy.unwrap() };
+            assert_eq!(Rc::strong_count(&rc), 2);
+            drop(rc);
+            let rc = unsafe { wrapped.unwrap() };
+            assert_eq!(Rc::strong_count(&rc), 1);
+        }
+
+        #[test]
+        fn test_arc_wrapped_refcount() {
+            let wrapped = ArcWrapped::new(5u32);
+            {
+                let arc = wrapped.clone_ref();
+                assert_eq!(Arc::strong_count(&arc), 2);
+                *arc.lock().unwrap() = 6;
+            }
+            assert_eq!(wrapped.clone_owned(), 6);
+            let arc = unsafe { wrapped.unwrap() };
+            assert_eq!(Arc::strong_count(&arc), 1);
+        }
     }
     
+    /// Lends out the `Rc` behind a raw pointer owned by C,
+    /// and gives the pointer back when dropped.
+    /// The reference count is the same before and after,
+    /// so forgetting to call `Rc::into_raw` is no longer possible.
+    struct RcGuard<T>(ManuallyDrop<Rc<T>>);
+
+    impl<T> RcGuard<T> {
+        /// The pointer must come from `Rc::into_raw`
+        /// and remain valid for the life of the guard.
+        unsafe fn from_raw(ptr: *const T) -> Self {
+            RcGuard(ManuallyDrop::new(Rc::from_raw(ptr)))
+        }
+    }
+
+    impl<T> Deref for RcGuard<T> {
+        type Target = Rc<T>;
+        fn deref(&self) -> &Rc<T> {
+            &self.0
+        }
+    }
+
+    impl<T> Drop for RcGuard<T> {
+        fn drop(&mut self) {
+            // Return ownership to the raw pointer.
+            let rc = unsafe { ManuallyDrop::take(&mut self.0) };
+            let _ = Rc::into_raw(rc);
+        }
+    }
+
+    /// Same as `RcGuard`, but for `Arc`.
+    struct ArcGuard<T>(ManuallyDrop<Arc<T>>);
+
+    impl<T> ArcGuard<T> {
+        /// The pointer must come from `Arc::into_raw`
+        /// and remain valid for the life of the guard.
+        unsafe fn from_raw(ptr: *const T) -> Self {
+            ArcGuard(ManuallyDrop::new(Arc::from_raw(ptr)))
+        }
+    }
+
+    impl<T> Deref for ArcGuard<T> {
+        type Target = Arc<T>;
+        fn deref(&self) -> &Arc<T> {
+            &self.0
+        }
+    }
+
+    impl<T> Drop for ArcGuard<T> {
+        fn drop(&mut self) {
+            let arc = unsafe { ManuallyDrop::take(&mut self.0) };
+            let _ = Arc::into_raw(arc);
+        }
+    }
+
     /// Marker trait for values that can be transferred to/received from C.
     /// They must be either *const or *mut or repr(transparent).
     pub trait COpaquePtr {}
@@ -102,11 +188,9 @@ pub mod c {
         /// Creates a new Rc reference to the same data.
         /// Use for accessing the underlying data as a reference.
         pub fn clone_ref(&self) -> Rc<RefCell<T>> {
-            // A bit dangerous: the Rc may be in use elsewhere
-            let used_rc = unsafe { Rc::from_raw(self.0) };
-            let rc = used_rc.clone();
-            Rc::into_raw(used_rc); // prevent dropping the original reference
-            rc
+            // The guard keeps the original reference alive
+            let used_rc = unsafe { RcGuard::from_raw(self.0) };
+            Rc::clone(&used_rc)
         }
     }
     
@@ -151,11 +235,9 @@ pub mod c {
         /// Creates a new Rc reference to the same data.
         /// Use for accessing the underlying data as a reference.
         pub fn clone_ref(&self) -> Arc<Mutex<T>> {
-            // A bit dangerous: the Rc may be in use elsewhere
-            let used_rc = unsafe { Arc::from_raw(self.0) };
-            let rc = used_rc.clone();
-            let _ = Arc::into_raw(used_rc); // prevent dropping the original reference
-            rc
+            // The guard keeps the original reference alive
+            let used_arc = unsafe { ArcGuard::from_raw(self.0) };
+            Arc::clone(&used_arc)
         }
     }
     
@@ -311,6 +393,25 @@ pub trait WarningHandler {
     fn handle(&mut self, warning: &str);
 }
 
+/// Quotes the text as a JSON string
+pub fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if c.is_control() => {
+                quoted.push_str(&format!("\\u{:04x}", c as u32))
+            },
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
 /// Removes the first matcing item
 pub fn vec_remove<T, F: FnMut(&T) -> bool>(v: &mut Vec<T>, pred: F) -> Option<T> {
     let idx = v.iter().position(pred);
@@ -349,6 +450,11 @@ mod tests {
         assert_eq!(s.remove(&Pointer(first)), true);
     }
 
+    #[test]
+    fn check_json_string() {
+        assert_eq!(json_string("a\"\\\n\u{7}ą"), r#""a\"\\\n\u0007ą""#);
+    }
+
     #[test]
     fn check_count() {
         assert_eq!(
@@ -44,6 +44,7 @@ pub mod c {
         }
     }
 
+    #[cfg(not(test))]
     extern "C" {
         // From libc, to let KeyMap get deallocated.
         fn close(fd: u32);
@@ -67,6 +68,55 @@ pub mod c {
         
         pub fn squeek_key_map_from_str(keymap_str: *const c_char) -> KeyMap;
     }
+
+    #[cfg(test)]
+    pub(crate) use self::fake::*;
+
+    /// Stands in for the C side in tests,
+    /// remembering the sent keys instead of sending them
+    #[cfg(test)]
+    mod fake {
+        use super::*;
+        use std::cell::RefCell;
+
+        thread_local! {
+            /// Keycodes as sent to the compositor, with the press state
+            static SENT_KEYS: RefCell<Vec<(u32, u32)>>
+                = RefCell::new(Vec::new());
+        }
+
+        /// Returns the keys sent since the last call
+        pub(crate) fn take_sent_keys() -> Vec<(u32, u32)> {
+            SENT_KEYS.with(|keys| keys.take())
+        }
+
+        pub(super) unsafe fn close(_fd: u32) {}
+
+        pub(crate) unsafe fn eek_virtual_keyboard_v1_key(
+            _virtual_keyboard: ZwpVirtualKeyboardV1,
+            _timestamp: u32,
+            keycode: u32,
+            press: u32,
+        ) {
+            SENT_KEYS.with(|keys| keys.borrow_mut().push((keycode, press)));
+        }
+
+        pub(crate) unsafe fn eek_virtual_keyboard_update_keymap(
+            _virtual_keyboard: ZwpVirtualKeyboardV1,
+            _keymap: *const KeyMap,
+        ) {}
+
+        pub(crate) unsafe fn eek_virtual_keyboard_set_modifiers(
+            _virtual_keyboard: ZwpVirtualKeyboardV1,
+            _modifiers: u32,
+        ) {}
+
+        pub(crate) unsafe fn squeek_key_map_from_str(
+            _keymap_str: *const c_char,
+        ) -> KeyMap {
+            KeyMap { fd: 0, fd_len: 0 }
+        }
+    }
 }
 
 /// Layout-independent backend. TODO: Have one instance per program or seat
@@ -83,7 +133,7 @@ impl VirtualKeyboard {
         let keycode = keycode - 8;
         unsafe {
             c::eek_virtual_keyboard_v1_key(
-                self.0, timestamp.0, keycode, action.clone() as u32
+                self.0, timestamp.0, keycode, action.to_u32()
             );
         }
     }